@@ -0,0 +1,109 @@
+use crate::common::Point;
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Aabb {
+    pub(crate) x: Interval,
+    pub(crate) y: Interval,
+    pub(crate) z: Interval,
+}
+
+impl Aabb {
+    pub(crate) const EMPTY: Self = Self {
+        x: Interval::EMPTY,
+        y: Interval::EMPTY,
+        z: Interval::EMPTY,
+    };
+
+    pub(crate) const UNIVERSE: Self = Self {
+        x: Interval::UNIVERSE,
+        y: Interval::UNIVERSE,
+        z: Interval::UNIVERSE,
+    };
+
+    pub(crate) fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        Self { x, y, z }.pad_to_minimums()
+    }
+
+    // Treats the two points as extrema, in any order.
+    pub(crate) fn from_points(a: Point, b: Point) -> Self {
+        Self::new(
+            Interval::new(a.x.min(b.x), a.x.max(b.x)),
+            Interval::new(a.y.min(b.y), a.y.max(b.y)),
+            Interval::new(a.z.min(b.z), a.z.max(b.z)),
+        )
+    }
+
+    // Bounds a disk of the given radius facing the unit vector `normal`.
+    pub(crate) fn from_disk(center: Point, normal: Vec3, radius: f64) -> Self {
+        let extent = Vec3::new(
+            radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+            radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+            radius * (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+        );
+        Self::from_points(center - extent, center + extent)
+    }
+
+    pub(crate) fn enclosing(a: Self, b: Self) -> Self {
+        Self {
+            x: Interval::enclosing(a.x, b.x),
+            y: Interval::enclosing(a.y, b.y),
+            z: Interval::enclosing(a.z, b.z),
+        }
+    }
+
+    pub(crate) fn axis(&self, n: usize) -> Interval {
+        match n {
+            1 => self.y,
+            2 => self.z,
+            _ => self.x,
+        }
+    }
+
+    pub(crate) fn hit(&self, ray: &Ray, ray_t: Interval) -> bool {
+        self.clip(ray, ray_t).is_some()
+    }
+
+    // The part of `ray_t` where the ray is inside the box.
+    pub(crate) fn clip(&self, ray: &Ray, ray_t: Interval) -> Option<Interval> {
+        let mut ray_t = ray_t;
+        for axis in 0..3 {
+            let interval = self.axis(axis);
+            let (origin, direction) = match axis {
+                0 => (ray.origin.x, ray.direction.x),
+                1 => (ray.origin.y, ray.direction.y),
+                _ => (ray.origin.z, ray.direction.z),
+            };
+            let inv_d = 1.0 / direction;
+
+            let t0 = (interval.min - origin) * inv_d;
+            let t1 = (interval.max - origin) * inv_d;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            ray_t.min = ray_t.min.max(t0);
+            ray_t.max = ray_t.max.min(t1);
+            if ray_t.max <= ray_t.min {
+                return None;
+            }
+        }
+        Some(ray_t)
+    }
+
+    // Flat boxes get a little thickness so that ray slab tests still work.
+    fn pad_to_minimums(self) -> Self {
+        let delta = 0.0001;
+        let pad = |interval: Interval| {
+            if interval.size() < delta {
+                interval.expand(delta)
+            } else {
+                interval
+            }
+        };
+        Self {
+            x: pad(self.x),
+            y: pad(self.y),
+            z: pad(self.z),
+        }
+    }
+}
@@ -0,0 +1,817 @@
+use std::f64::consts::PI;
+use std::ops::BitOr;
+
+use crate::hair::HairBsdf;
+use crate::medium::sample_henyey_greenstein;
+use crate::microfacet::{
+    fr_complex, fr_dielectric, fr_schlick, fr_thin_film, reflect, refract, refracted_half_vector,
+    TrowbridgeReitz,
+};
+use crate::onb::Onb;
+use crate::principled::PrincipledBsdf;
+use crate::vec3::Vec3;
+
+/// The kinds of scattering a lobe does, so that light sampling can skip
+/// specular lobes, whose delta distributions no light sample can hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LobeFlags(u8);
+
+impl LobeFlags {
+    pub(crate) const REFLECTION: Self = Self(1);
+    pub(crate) const TRANSMISSION: Self = Self(2);
+    pub(crate) const DIFFUSE: Self = Self(4);
+    pub(crate) const GLOSSY: Self = Self(8);
+    pub(crate) const SPECULAR: Self = Self(16);
+
+    pub(crate) fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub(crate) fn is_specular(self) -> bool {
+        self.contains(Self::SPECULAR)
+    }
+}
+
+impl BitOr for LobeFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self::Output {
+        Self(self.0 | other.0)
+    }
+}
+
+/// A sampled incident direction with the BSDF value and density there.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BsdfSample {
+    pub(crate) wi: Vec3,
+    pub(crate) f: Vec3,
+    pub(crate) pdf: f64,
+    pub(crate) flags: LobeFlags,
+}
+
+impl BsdfSample {
+    /// The factor by which the sample scales the light arriving along `wi`.
+    pub(crate) fn weight(&self) -> Vec3 {
+        self.f / self.pdf
+    }
+}
+
+/// How a material scatters light at one point, in its local shading frame.
+///
+/// Directions are local, with z along the shading normal, and both point
+/// away from the surface: `wo` towards the viewer, `wi` towards the light.
+/// Values of `eval` and `sample` include the cosine of `wi` with the normal,
+/// so that `f / pdf` is the throughput of a sample, and the same holds for
+/// phase functions, which have no cosine. Specular lobes are only returned
+/// by `sample`, with `f` and `pdf` both scaled by the chance of choosing
+/// them; `eval` and `pdf` leave them out.
+pub(crate) struct Bsdf {
+    pub(crate) frame: Onb,
+    lobe: Lobe,
+}
+
+pub(crate) enum Lobe {
+    Lambertian {
+        albedo: Vec3,
+    },
+    // microfacet reflection, or a mirror when the distribution is smooth
+    Conductor {
+        distribution: TrowbridgeReitz,
+        fresnel: Fresnel,
+    },
+    // reflection and refraction into a medium with relative index of
+    // refraction `eta`, the far side over the near side, optionally through
+    // a thin film
+    Dielectric {
+        eta: f64,
+        distribution: TrowbridgeReitz,
+        film: Option<Film>,
+    },
+    Hair(HairBsdf),
+    Principled(Box<PrincipledBsdf>),
+    // the Henyey-Greenstein phase function, isotropic for `g` of zero
+    Phase {
+        albedo: Vec3,
+        g: f64,
+    },
+    // a dielectric coat of index of refraction `eta` over another lobe,
+    // which sees the light the coat lets through, tinted on each pass by
+    // `tint` per unit of path length relative to the coat's thickness
+    Coated {
+        eta: f64,
+        distribution: TrowbridgeReitz,
+        tint: Vec3,
+        base: Box<Lobe>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Fresnel {
+    // Schlick's approximation with the given reflectance at normal incidence
+    Schlick(Vec3),
+    // a conductor with complex index of refraction eta + ik
+    Complex { eta: Vec3, k: Vec3 },
+    // a conductor under a thin film
+    ThinFilm { film: Film, eta: Vec3, k: Vec3 },
+}
+
+impl Fresnel {
+    fn eval(self, cos_theta: f64) -> Vec3 {
+        match self {
+            Fresnel::Schlick(f0) => fr_schlick(cos_theta, f0),
+            Fresnel::Complex { eta, k } => fr_complex(cos_theta, eta, k),
+            Fresnel::ThinFilm { film, eta, k } => film.reflectance(cos_theta, eta, k),
+        }
+    }
+}
+
+/// A thin film of index of refraction `ior` and `thickness` nanometers
+/// whose interference colors the reflectance of a surface, with the color
+/// channels at wavelengths `lambda`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Film {
+    pub(crate) ior: f64,
+    pub(crate) thickness: f64,
+    pub(crate) lambda: [f64; 3],
+}
+
+impl Film {
+    // Reflectance through the film off a base of complex index eta + ik, per
+    // channel, with indices relative to the side the light comes from.
+    fn reflectance(&self, cos_theta: f64, eta: Vec3, k: Vec3) -> Vec3 {
+        let channel = |i: usize, eta: f64, k: f64| {
+            fr_thin_film(cos_theta, self.ior, self.thickness, eta, k, self.lambda[i])
+        };
+        Vec3::new(
+            channel(0, eta.x, k.x),
+            channel(1, eta.y, k.y),
+            channel(2, eta.z, k.z),
+        )
+    }
+}
+
+impl Bsdf {
+    pub(crate) fn new(frame: Onb, lobe: Lobe) -> Self {
+        Self { frame, lobe }
+    }
+
+    pub(crate) fn to_local(&self, w: Vec3) -> Vec3 {
+        self.frame.to_local(w)
+    }
+
+    pub(crate) fn to_world(&self, w: Vec3) -> Vec3 {
+        self.frame.to_world(w)
+    }
+
+    /// Whether every lobe is specular, so that `eval` is always zero.
+    pub(crate) fn is_specular(&self) -> bool {
+        self.lobe.is_specular()
+    }
+
+    /// The BSDF value times the cosine of `wi`, for light arriving from `wi`
+    /// and leaving towards `wo`.
+    pub(crate) fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.lobe.eval(wo, wi)
+    }
+
+    /// The density with which `sample` returns `wi`, apart from specular
+    /// lobes.
+    pub(crate) fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        self.lobe.pdf(wo, wi)
+    }
+
+    /// Samples an incident direction for light leaving towards `wo`, from
+    /// uniform samples `uc`, which picks a lobe, and `u`, which picks a
+    /// direction in it. `None` when the sampled direction carries no light,
+    /// such as a microfacet reflection into the surface.
+    pub(crate) fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        self.lobe.sample(wo, uc, u)
+    }
+}
+
+impl Lobe {
+    /// Puts a thin film on the surface, in vacuum on the outside, changing
+    /// the reflectance of conductors and dielectrics and leaving other lobes
+    /// as they are. `front_face` tells whether light comes from outside.
+    pub(crate) fn with_film(self, film: Film, front_face: bool) -> Self {
+        match self {
+            Lobe::Conductor {
+                distribution,
+                fresnel,
+            } => {
+                let (eta, k) = match fresnel {
+                    Fresnel::Schlick(f0) => {
+                        // the dielectric with the same reflectance head on
+                        let eta = |f0: f64| {
+                            let r = f0.clamp(0.0, 0.99).sqrt();
+                            (1.0 + r) / (1.0 - r)
+                        };
+                        (
+                            Vec3::new(eta(f0.x), eta(f0.y), eta(f0.z)),
+                            Vec3::new(0.0, 0.0, 0.0),
+                        )
+                    }
+                    Fresnel::Complex { eta, k } | Fresnel::ThinFilm { eta, k, .. } => (eta, k),
+                };
+                Lobe::Conductor {
+                    distribution,
+                    fresnel: Fresnel::ThinFilm { film, eta, k },
+                }
+            }
+            Lobe::Dielectric {
+                eta, distribution, ..
+            } => {
+                // From inside, the film's index is relative to the dielectric.
+                let near = if front_face { 1.0 } else { 1.0 / eta };
+                Lobe::Dielectric {
+                    eta,
+                    distribution,
+                    film: Some(Film {
+                        ior: film.ior / near,
+                        ..film
+                    }),
+                }
+            }
+            lobe => lobe,
+        }
+    }
+
+    /// Replaces the microfacet distribution of conductors, dielectrics and
+    /// coats, leaving other lobes as they are.
+    pub(crate) fn with_distribution(self, distribution: TrowbridgeReitz) -> Self {
+        match self {
+            Lobe::Conductor { fresnel, .. } => Lobe::Conductor {
+                distribution,
+                fresnel,
+            },
+            Lobe::Dielectric { eta, film, .. } => Lobe::Dielectric {
+                eta,
+                distribution,
+                film,
+            },
+            Lobe::Coated {
+                eta, tint, base, ..
+            } => Lobe::Coated {
+                eta,
+                distribution,
+                tint,
+                base,
+            },
+            lobe => lobe,
+        }
+    }
+
+    fn is_specular(&self) -> bool {
+        match self {
+            Lobe::Conductor { distribution, .. } | Lobe::Dielectric { distribution, .. } => {
+                distribution.is_smooth()
+            }
+            Lobe::Coated {
+                distribution, base, ..
+            } => distribution.is_smooth() && base.is_specular(),
+            _ => false,
+        }
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        match self {
+            Lobe::Lambertian { albedo } => {
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return zero;
+                }
+                *albedo * (wi.z / PI)
+            }
+            Lobe::Conductor {
+                distribution,
+                fresnel,
+            } => {
+                if distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+                    return zero;
+                }
+                let wm = (wo + wi).unit_vector();
+                fresnel.eval(wo.dot(wm))
+                    * (distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z))
+            }
+            Lobe::Dielectric {
+                eta,
+                distribution,
+                film,
+            } => {
+                if distribution.is_smooth() || wo.z <= 0.0 || wi.z == 0.0 {
+                    return zero;
+                }
+                if wi.z > 0.0 {
+                    let wm = (wo + wi).unit_vector();
+                    dielectric_reflectance(wo.dot(wm), *eta, film)
+                        * (distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z))
+                } else {
+                    // pbrt-v4's BTDF, without scaling radiance by 1/eta^2 so
+                    // that it doesn't need undoing on the way out
+                    match refracted_half_vector(wo, wi, *eta) {
+                        Some((wm, denominator)) => {
+                            (Vec3::new(1.0, 1.0, 1.0)
+                                - dielectric_reflectance(wo.dot(wm), *eta, film))
+                                * (distribution.d(wm)
+                                    * distribution.g(wo, wi)
+                                    * (wi.dot(wm) * wo.dot(wm) / (wo.z * denominator)).abs())
+                        }
+                        None => zero,
+                    }
+                }
+            }
+            Lobe::Hair(hair) => hair.eval(wo, wi) * wi.z.abs(),
+            Lobe::Principled(principled) => principled.eval(wo, wi) * wi.z.abs(),
+            Lobe::Phase { albedo, g } => *albedo * henyey_greenstein(wo, wi, *g),
+            Lobe::Coated {
+                eta,
+                distribution,
+                tint,
+                base,
+            } => {
+                if wo.z <= 0.0 {
+                    return zero;
+                }
+                let coat = if distribution.is_smooth() || wi.z <= 0.0 {
+                    0.0
+                } else {
+                    let wm = (wo + wi).unit_vector();
+                    fr_dielectric(wo.dot(wm), *eta) * distribution.d(wm) * distribution.g(wo, wi)
+                        / (4.0 * wo.z)
+                };
+                Vec3::new(coat, coat, coat)
+                    + base.eval(wo, wi) * coat_transmittance(wo, wi, *eta, *tint)
+            }
+        }
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        match self {
+            Lobe::Lambertian { .. } => {
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return 0.0;
+                }
+                wi.z / PI
+            }
+            Lobe::Conductor { distribution, .. } => {
+                if distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+                    return 0.0;
+                }
+                let wm = (wo + wi).unit_vector();
+                distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm))
+            }
+            Lobe::Dielectric {
+                eta,
+                distribution,
+                film,
+            } => {
+                if distribution.is_smooth() || wo.z <= 0.0 || wi.z == 0.0 {
+                    return 0.0;
+                }
+                if wi.z > 0.0 {
+                    let wm = (wo + wi).unit_vector();
+                    average(dielectric_reflectance(wo.dot(wm), *eta, film))
+                        * distribution.d_visible(wo, wm)
+                        / (4.0 * wo.dot(wm))
+                } else {
+                    match refracted_half_vector(wo, wi, *eta) {
+                        Some((wm, denominator)) => {
+                            (1.0 - average(dielectric_reflectance(wo.dot(wm), *eta, film)))
+                                * distribution.d_visible(wo, wm)
+                                * wi.dot(wm).abs()
+                                / denominator
+                        }
+                        None => 0.0,
+                    }
+                }
+            }
+            Lobe::Hair(hair) => hair.pdf(wo, wi),
+            Lobe::Principled(principled) => principled.pdf(wo, wi),
+            Lobe::Phase { g, .. } => henyey_greenstein(wo, wi, *g),
+            Lobe::Coated {
+                eta,
+                distribution,
+                base,
+                ..
+            } => {
+                if wo.z <= 0.0 {
+                    return 0.0;
+                }
+                let coat_chance = fr_dielectric(wo.z, *eta);
+                let coat = if distribution.is_smooth() || wi.z <= 0.0 {
+                    0.0
+                } else {
+                    let wm = (wo + wi).unit_vector();
+                    distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm))
+                };
+                coat_chance * coat + (1.0 - coat_chance) * base.pdf(wo, wi)
+            }
+        }
+    }
+
+    fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let (wi, flags) = match self {
+            Lobe::Lambertian { .. } => (
+                Vec3::cosine_direction(u),
+                LobeFlags::REFLECTION | LobeFlags::DIFFUSE,
+            ),
+            Lobe::Conductor {
+                distribution,
+                fresnel,
+            } => {
+                if distribution.is_smooth() {
+                    return Some(BsdfSample {
+                        wi: Vec3::new(-wo.x, -wo.y, wo.z),
+                        f: fresnel.eval(wo.z),
+                        pdf: 1.0,
+                        flags: LobeFlags::REFLECTION | LobeFlags::SPECULAR,
+                    });
+                }
+                let wi = reflect(wo, distribution.sample_wm(wo, u));
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                (wi, LobeFlags::REFLECTION | LobeFlags::GLOSSY)
+            }
+            Lobe::Dielectric {
+                eta,
+                distribution,
+                film,
+            } => {
+                if distribution.is_smooth() {
+                    return Some(sample_smooth_dielectric(wo, *eta, film, uc));
+                }
+                let wm = distribution.sample_wm(wo, u);
+                if uc < average(dielectric_reflectance(wo.dot(wm), *eta, film)) {
+                    let wi = reflect(wo, wm);
+                    if wi.z <= 0.0 {
+                        return None;
+                    }
+                    (wi, LobeFlags::REFLECTION | LobeFlags::GLOSSY)
+                } else {
+                    let wi = refract(wo, wm, *eta).filter(|wi| wi.z < 0.0)?;
+                    (wi, LobeFlags::TRANSMISSION | LobeFlags::GLOSSY)
+                }
+            }
+            Lobe::Hair(hair) => (
+                hair.sample(wo, uc, u),
+                LobeFlags::REFLECTION | LobeFlags::TRANSMISSION | LobeFlags::GLOSSY,
+            ),
+            Lobe::Principled(principled) => principled.sample(wo, uc, u)?,
+            // Phase functions neither reflect nor transmit, but spread light
+            // over all directions like a diffuse lobe.
+            Lobe::Phase { g, .. } => (sample_henyey_greenstein(-wo, *g, u), LobeFlags::DIFFUSE),
+            Lobe::Coated {
+                eta,
+                distribution,
+                tint,
+                base,
+            } => {
+                if wo.z <= 0.0 {
+                    return None;
+                }
+                // Reflect off the coat as often as its Fresnel reflectance
+                // says, and otherwise sample the base through it, with uc
+                // stretched back over [0, 1) to pick among its lobes.
+                let coat_chance = fr_dielectric(wo.z, *eta);
+                if uc < coat_chance {
+                    if distribution.is_smooth() {
+                        return Some(BsdfSample {
+                            wi: Vec3::new(-wo.x, -wo.y, wo.z),
+                            f: Vec3::new(coat_chance, coat_chance, coat_chance),
+                            pdf: coat_chance,
+                            flags: LobeFlags::REFLECTION | LobeFlags::SPECULAR,
+                        });
+                    }
+                    let wi = reflect(wo, distribution.sample_wm(wo, u));
+                    if wi.z <= 0.0 {
+                        return None;
+                    }
+                    (wi, LobeFlags::REFLECTION | LobeFlags::GLOSSY)
+                } else {
+                    let uc = ((uc - coat_chance) / (1.0 - coat_chance)).min(1.0 - f64::EPSILON);
+                    let sample = base.sample(wo, uc, u)?;
+                    if sample.flags.is_specular() {
+                        return Some(BsdfSample {
+                            f: sample.f * coat_transmittance(wo, sample.wi, *eta, *tint),
+                            pdf: (1.0 - coat_chance) * sample.pdf,
+                            ..sample
+                        });
+                    }
+                    (sample.wi, sample.flags)
+                }
+            }
+        };
+
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            flags,
+        })
+    }
+}
+
+fn average(v: Vec3) -> f64 {
+    (v.x + v.y + v.z) / 3.0
+}
+
+fn dielectric_reflectance(cos_theta: f64, eta: f64, film: &Option<Film>) -> Vec3 {
+    match film {
+        Some(film) => film.reflectance(
+            cos_theta,
+            Vec3::new(eta, eta, eta),
+            Vec3::new(0.0, 0.0, 0.0),
+        ),
+        None => {
+            let value = fr_dielectric(cos_theta, eta);
+            Vec3::new(value, value, value)
+        }
+    }
+}
+
+// The fraction of light that makes it through a coat into the base along
+// wo, and along wi too unless it comes through the base from below. The
+// coat reflects some at its surface and absorbs some along the refracted
+// path, whose length grows as it tilts.
+fn coat_transmittance(wo: Vec3, wi: Vec3, eta: f64, tint: Vec3) -> Vec3 {
+    let pass = |w: Vec3| {
+        let cos_theta = w.z.abs();
+        let cos_theta_t = (1.0 - (1.0 - cos_theta * cos_theta) / (eta * eta))
+            .max(1e-4)
+            .sqrt();
+        let path = 1.0 / cos_theta_t;
+        let absorbed = Vec3::new(
+            tint.x.max(0.0).powf(path),
+            tint.y.max(0.0).powf(path),
+            tint.z.max(0.0).powf(path),
+        );
+        absorbed * (1.0 - fr_dielectric(cos_theta, eta))
+    };
+    if wi.z > 0.0 {
+        pass(wo) * pass(wi)
+    } else {
+        pass(wo)
+    }
+}
+
+// The book's glass: reflection with Schlick's approximation of the Fresnel
+// reflectance, and refraction otherwise. A film makes the reflectance
+// differ between channels, which then weight the choice.
+fn sample_smooth_dielectric(wo: Vec3, eta: f64, film: &Option<Film>, uc: f64) -> BsdfSample {
+    let cos_theta = wo.z.min(1.0);
+    let refracted = refract(wo, Vec3::new(0.0, 0.0, 1.0), eta);
+    let reflectance = match (refracted, film) {
+        (None, _) => Vec3::new(1.0, 1.0, 1.0),
+        (Some(_), Some(_)) => dielectric_reflectance(cos_theta, eta, film),
+        (Some(_), None) => {
+            let value = schlick_reflectance(cos_theta, eta);
+            Vec3::new(value, value, value)
+        }
+    };
+    let chance = average(reflectance);
+    if uc < chance {
+        BsdfSample {
+            wi: Vec3::new(-wo.x, -wo.y, wo.z),
+            f: reflectance,
+            pdf: chance,
+            flags: LobeFlags::REFLECTION | LobeFlags::SPECULAR,
+        }
+    } else {
+        BsdfSample {
+            // refract only fails when the reflectance is one
+            wi: refracted.unwrap_or(-wo),
+            f: Vec3::new(1.0, 1.0, 1.0) - reflectance,
+            pdf: 1.0 - chance,
+            flags: LobeFlags::TRANSMISSION | LobeFlags::SPECULAR,
+        }
+    }
+}
+
+fn schlick_reflectance(cosine: f64, eta: f64) -> f64 {
+    let r0 = (1.0 - eta) / (1.0 + eta);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+// The Henyey-Greenstein phase function for light travelling along -wo that
+// scatters into wi, or along -wi into wo.
+fn henyey_greenstein(wo: Vec3, wi: Vec3, g: f64) -> f64 {
+    let cos_theta = -wo.dot(wi);
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(1e-12).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::common::Point;
+    use crate::material::{Material, MaterialKind};
+    use crate::principled::Principled;
+    use crate::ray::Ray;
+    use crate::scene::HitRecord;
+    use crate::spectrum::RGB_WAVELENGTHS;
+    use crate::texture::Texture;
+
+    // Uniform samples on an n by n grid, with the lobe choice running
+    // across the grid diagonally so that every lobe meets every direction.
+    fn grid(n: usize) -> impl Iterator<Item = (f64, (f64, f64))> {
+        (0..n * n).map(move |i| {
+            let (x, y) = (i % n, i / n);
+            let at = |k: usize| (k as f64 + 0.5) / n as f64;
+            (at((x + 7 * y) % n), (at(x), at(y)))
+        })
+    }
+
+    // The integral of the lobe's density over the sphere, by the midpoint
+    // rule in cos(theta) and phi.
+    fn integrate_pdf(lobe: &Lobe, wo: Vec3) -> f64 {
+        let n = 400;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..2 * n {
+                let phi = PI * (j as f64 + 0.5) / n as f64;
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += lobe.pdf(wo, wi);
+            }
+        }
+        sum * 4.0 * PI / (2 * n * n) as f64
+    }
+
+    // A principled BSDF with every lobe in play, looked up on the front of
+    // a surface facing +z.
+    fn principled() -> Lobe {
+        let principled = Arc::new(Principled {
+            base_color: Texture::Constant(Vec3::new(0.8, 0.5, 0.3)),
+            metallic: Texture::constant(0.3),
+            roughness: Texture::constant(0.4),
+            sheen: Texture::constant(0.5),
+            clearcoat: Texture::constant(0.7),
+            clearcoat_gloss: Texture::constant(0.5),
+            transmission: Texture::constant(0.6),
+            ..Principled::default()
+        });
+        let ray = Ray::new(Point::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let material = Arc::new(Material {
+            kind: MaterialKind::Principled(principled.clone()),
+            albedo: Vec3::new(1.0, 1.0, 1.0),
+        });
+        let hit = HitRecord::new(
+            &ray,
+            Point::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            (0.5, 0.5),
+            material,
+        );
+        Lobe::Principled(Box::new(principled.bsdf(&ray, &hit)))
+    }
+
+    fn lobes() -> Vec<Lobe> {
+        let rough = TrowbridgeReitz::new(0.3, 0.15);
+        let film = Film {
+            ior: 1.33,
+            thickness: 400.0,
+            lambda: RGB_WAVELENGTHS,
+        };
+        vec![
+            Lobe::Lambertian {
+                albedo: Vec3::new(0.5, 0.5, 0.5),
+            },
+            Lobe::Conductor {
+                distribution: rough,
+                fresnel: Fresnel::Schlick(Vec3::new(0.9, 0.6, 0.3)),
+            },
+            Lobe::Dielectric {
+                eta: 1.5,
+                distribution: rough,
+                film: None,
+            },
+            Lobe::Coated {
+                eta: 1.5,
+                distribution: TrowbridgeReitz::from_roughness(0.4),
+                tint: Vec3::new(1.0, 1.0, 1.0),
+                base: Box::new(Lobe::Lambertian {
+                    albedo: Vec3::new(0.5, 0.5, 0.5),
+                }),
+            },
+            // thin films over a rough conductor and a rough dielectric
+            Lobe::Conductor {
+                distribution: rough,
+                fresnel: Fresnel::ThinFilm {
+                    film,
+                    eta: Vec3::new(0.2, 0.9, 1.1),
+                    k: Vec3::new(3.9, 2.4, 2.1),
+                },
+            },
+            Lobe::Dielectric {
+                eta: 1.5,
+                distribution: rough,
+                film: Some(film),
+            },
+            principled(),
+            Lobe::Hair(HairBsdf::new(
+                0.3,
+                1.55,
+                Vec3::new(0.4, 0.7, 1.4),
+                0.3,
+                0.3,
+                2.0,
+            )),
+        ]
+    }
+
+    #[test]
+    fn samples_agree_with_eval_and_pdf() {
+        let wo = Vec3::new(0.3, -0.2, 0.9).unit_vector();
+        for lobe in lobes() {
+            for (uc, u) in grid(64) {
+                let Some(sample) = lobe.sample(wo, uc, u) else {
+                    continue;
+                };
+                assert!((sample.pdf - lobe.pdf(wo, sample.wi)).abs() <= 1e-9 * sample.pdf);
+                assert!((sample.f - lobe.eval(wo, sample.wi)).length() <= 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_the_share_of_samples_kept() {
+        let wo = Vec3::new(0.5, 0.1, 0.7).unit_vector();
+        for lobe in lobes() {
+            let n = 200;
+            let kept = grid(n).filter(|&(uc, u)| lobe.sample(wo, uc, u).is_some());
+            let share = kept.count() as f64 / (n * n) as f64;
+            let integral = integrate_pdf(&lobe, wo);
+            assert!((integral - share).abs() < 0.02, "{integral} vs {share}");
+        }
+    }
+
+    #[test]
+    fn sample_weights_average_to_the_integral_of_eval() {
+        // which they only do if samples are distributed as pdf says
+        let wo = Vec3::new(0.4, -0.3, 0.6).unit_vector();
+        for lobe in lobes() {
+            let n = 400;
+            let mut integral = Vec3::new(0.0, 0.0, 0.0);
+            for i in 0..n {
+                let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                for j in 0..2 * n {
+                    let phi = PI * (j as f64 + 0.5) / n as f64;
+                    let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                    integral += lobe.eval(wo, wi);
+                }
+            }
+            let integral = integral * (4.0 * PI / (2 * n * n) as f64);
+
+            let m = 300;
+            let estimate = grid(m)
+                .filter_map(|(uc, u)| lobe.sample(wo, uc, u))
+                .map(|sample| sample.weight())
+                .sum::<Vec3>()
+                / (m * m) as f64;
+            assert!(
+                (estimate - integral).length() < 3e-3,
+                "{estimate:?} vs {integral:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn lambertian_samples_weigh_the_albedo() {
+        let bsdf = Bsdf::new(
+            Onb::new(Vec3::new(0.0, 1.0, 0.0)),
+            Lobe::Lambertian {
+                albedo: Vec3::new(0.2, 0.4, 0.8),
+            },
+        );
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        for (uc, u) in grid(16) {
+            let sample = bsdf.sample(wo, uc, u).unwrap();
+            assert!((sample.weight() - Vec3::new(0.2, 0.4, 0.8)).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn smooth_dielectric_picks_reflection_by_uc() {
+        let lobe = Lobe::Dielectric {
+            eta: 1.5,
+            distribution: TrowbridgeReitz::new(0.0, 0.0),
+            film: None,
+        };
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let reflected = lobe.sample(wo, 0.01, (0.5, 0.5)).unwrap();
+        let refracted = lobe.sample(wo, 0.99, (0.5, 0.5)).unwrap();
+        assert!(reflected.wi.z > 0.0 && reflected.flags.contains(LobeFlags::REFLECTION));
+        assert!(refracted.wi.z < 0.0 && refracted.flags.contains(LobeFlags::TRANSMISSION));
+    }
+}
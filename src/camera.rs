@@ -5,13 +5,26 @@ use std::sync::Arc;
 use crate::color::Color;
 use crate::common::random_f64;
 use crate::common::Point;
+use crate::environment::Environment;
 use crate::interval::Interval;
+use crate::lens::RealisticLens;
+use crate::lighttree::LightSampling;
+use crate::material::SurfaceScatter;
+use crate::medium::Atmosphere;
 use crate::ray::Ray;
-use crate::scene::World;
+use crate::scene::{HitRecord, Hittable, World};
+use crate::sky::{Sky, Sun};
+use crate::spectrum::SampledWavelengths;
 use crate::vec3::Vec3;
 use image::ImageBuffer;
 use image::Rgb;
 use rayon::prelude::*;
+
+pub enum Lens {
+    Thin,
+    Realistic(RealisticLens),
+}
+
 pub struct Camera {
     aspect_ratio: f64,
     image_width: u32,
@@ -25,10 +38,6 @@ pub struct Camera {
     pixel_samples_scale: f64,
 
     center: Point,
-    vertical_fov: f64,
-    look_from: Vec3,
-    look_at: Vec3,
-    up: Vec3,
 
     u: Vec3,
     v: Vec3,
@@ -37,9 +46,18 @@ pub struct Camera {
     defocus_angle: f64,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    focus_distance: f64,
+
+    lens: Lens,
+    atmosphere: Option<Atmosphere>,
+    environment: Option<Environment>,
+    sun: Option<Sun>,
+    light_sampling: LightSampling,
+    spectral: bool,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         aspect_ratio: f64,
         image_width: u32,
@@ -92,10 +110,6 @@ impl Camera {
             pixel_samples_scale: 1.0 / samples_per_pixel as f64,
 
             center,
-            vertical_fov,
-            look_from,
-            look_at,
-            up,
 
             u,
             v,
@@ -104,9 +118,63 @@ impl Camera {
             defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
+            focus_distance,
+
+            lens: Lens::Thin,
+            atmosphere: None,
+            environment: None,
+            sun: None,
+            light_sampling: LightSampling::Tree,
+            spectral: false,
         }
     }
 
+    /// Replaces the thin lens with a realistic lens system, focused at this
+    /// camera's focus distance. The field of view is then determined by the
+    /// lens and its film, not by `vertical_fov`.
+    pub fn with_realistic_lens(mut self, mut lens: RealisticLens) -> Self {
+        lens.prepare(self.focus_distance, self.aspect_ratio);
+        self.lens = Lens::Realistic(lens);
+        self
+    }
+
+    /// Fills the space around the camera with a homogeneous medium that
+    /// scatters light on every ray segment.
+    pub fn with_atmosphere(mut self, atmosphere: Atmosphere) -> Self {
+        self.atmosphere = Some(atmosphere);
+        self
+    }
+
+    /// Lights the scene with an environment map in place of the sky, which
+    /// surfaces also sample directly.
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Lights the scene with daylight: the sky takes the place of the
+    /// environment map, and the sun shines as a light of its own.
+    pub fn with_sky(mut self, sky: Sky) -> Self {
+        self.environment = Some(sky.environment(1024));
+        self.sun = Some(sky.sun());
+        self
+    }
+
+    /// Chooses how next event estimation picks one of the world's lights at
+    /// each shading point, from a light tree by default.
+    pub fn with_light_sampling(mut self, light_sampling: LightSampling) -> Self {
+        self.light_sampling = light_sampling;
+        self
+    }
+
+    /// Renders spectrally: each camera ray carries a few wavelengths, colors
+    /// are upsampled to spectra at them, and the film turns the radiance
+    /// they bring back into color.
+    pub fn with_spectral(mut self) -> Self {
+        self.spectral = true;
+        self
+    }
+
     pub fn render(&self, world: &World) {
         let completed_pixels = Arc::new(AtomicUsize::new(0));
 
@@ -137,9 +205,17 @@ impl Camera {
                     .map(|i| {
                         let mut color = Vec3::new(0.0, 0.0, 0.0);
                         color += (0..self.samples_per_pixel)
-                            .map(|_| {
-                                let ray = self.get_ray(i as i32, j as i32);
-                                ray_color(&ray, world)
+                            .map(|_| match self.get_ray(i as i32, j as i32) {
+                                Some((ray, weight)) if self.spectral => {
+                                    let wavelengths = SampledWavelengths::sample();
+                                    let ray = Ray {
+                                        wavelengths: Some(wavelengths),
+                                        ..ray
+                                    };
+                                    wavelengths.to_rgb(self.ray_color(&ray, world)) * weight
+                                }
+                                Some((ray, weight)) => self.ray_color(&ray, world) * weight,
+                                None => Vec3::new(0.0, 0.0, 0.0),
                             })
                             .sum();
                         // update progress
@@ -159,18 +235,25 @@ impl Camera {
         }
         img.save("output.png").unwrap();
 
-        for scan_line in scan_lines {
-            for color in scan_line {
-                // println!("{}", color);
-            }
-        }
-
         progress_thread.join().unwrap();
         eprintln!("\rDone.                 ");
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray {
+    fn get_ray(&self, i: i32, j: i32) -> Option<(Ray, f64)> {
         let offset = sample_square();
+        if let Lens::Realistic(lens) = &self.lens {
+            let film = (
+                (i as f64 + 0.5 + offset.x) / self.image_width as f64,
+                (j as f64 + 0.5 + offset.y) / self.image_height as f64,
+            );
+            let (ray, weight) = lens.generate_ray(film, (random_f64(), random_f64()))?;
+            // camera space looks down +z, the camera basis looks down -w
+            let to_world = |v: Vec3| v.x * self.u + v.y * self.v - v.z * self.w;
+            let origin = self.center + to_world(ray.origin);
+            let direction = to_world(ray.direction);
+            return Some((Ray::new(origin, direction), weight));
+        }
+
         let pixel_sample = self.pixel00_loc
             + (self.pixel_delta_u * (i as f64 + offset.x))
             + (self.pixel_delta_v * (j as f64 + offset.y));
@@ -181,37 +264,258 @@ impl Camera {
             self.defocus_disk_sample()
         };
         let direction = pixel_sample - origin;
-        Ray { origin, direction }
+        Some((Ray::new(origin, direction), 1.0))
     }
     fn defocus_disk_sample(&self) -> Vec3 {
         // Returns a random point in the camera defocus disk.
         let p = Vec3::random_in_unit_disk();
         self.center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
     }
-}
 
-fn ray_color(ray: &Ray, world: &World) -> Vec3 {
-    ray_color_(ray, world, 0)
-}
+    fn ray_color(&self, ray: &Ray, world: &World) -> Vec3 {
+        self.ray_color_(ray, world, 0, None)
+    }
 
-fn ray_color_(ray: &Ray, world: &World, depth: i32) -> Vec3 {
-    const MAX_DEPTH: i32 = 50;
-    if depth >= MAX_DEPTH {
-        return Vec3::new(0.0, 0.0, 0.0);
+    // `bounce` is the surface the ray last scattered off, if lights were
+    // also sampled there.
+    fn ray_color_(&self, ray: &Ray, world: &World, depth: i32, bounce: Option<Bounce>) -> Vec3 {
+        const MAX_DEPTH: i32 = 50;
+        if depth >= MAX_DEPTH {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        // Walk through the inside of subsurface materials until the ray
+        // reaches their boundary, in a loop of its own since the walk can
+        // take many more steps than bounces between surfaces.
+        const MAX_WALK: i32 = 256;
+        let mut ray = *ray;
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut steps = 0;
+        let hit = loop {
+            let hit = world.hit(&ray, Interval::new(0.001, f64::INFINITY));
+            let Some(scatter) = hit
+                .as_ref()
+                .and_then(|hit| hit.material.interior_scatter(&ray, hit))
+            else {
+                break hit;
+            };
+            steps += 1;
+            if steps >= MAX_WALK {
+                return Vec3::new(0.0, 0.0, 0.0);
+            }
+            throughput = throughput * scatter.attenuation;
+            ray = scatter.scattered;
+        };
+        let bounce = if steps == 0 { bounce } else { None };
+        throughput * self.surface_color(&ray, hit, world, depth, bounce)
     }
-    if let Some(hit) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) {
-        match hit.material.scatter(ray, &hit) {
-            Some(scatter) => {
-                return scatter.attenuation * ray_color_(&scatter.scattered, world, depth + 1);
+
+    fn surface_color(
+        &self,
+        ray: &Ray,
+        hit: Option<HitRecord>,
+        world: &World,
+        depth: i32,
+        bounce: Option<Bounce>,
+    ) -> Vec3 {
+        // triangle lights are not part of the world's objects, and the ray
+        // stops at whichever comes first
+        let t_max = hit.as_ref().map_or(f64::INFINITY, |hit| hit.t);
+        let light_hit = world.hit_light(ray, Interval::new(0.001, t_max));
+        let t_max = light_hit.map_or(t_max, |(_, _, t)| t);
+
+        if let Some(atmosphere) = &self.atmosphere {
+            if let Some(scatter) = atmosphere.scatter(ray, t_max, self.center) {
+                return scatter.attenuation
+                    * self.ray_color_(&scatter.scattered, world, depth + 1, None);
             }
-            None => return Vec3::new(0.0, 0.0, 0.0),
         }
+
+        // lights at a point or direction that camera rays see
+        if depth == 0 {
+            if let Some(radiance) = world
+                .lights()
+                .iter()
+                .find_map(|light| light.visible_radiance(ray, t_max))
+            {
+                return ray.color(radiance);
+            }
+        }
+
+        if let Some((light, index, t)) = light_hit {
+            let weight = bounce.map_or(1.0, |bounce| {
+                let pmf =
+                    world
+                        .light_tree()
+                        .pmf(bounce.p, bounce.normal, index, self.light_sampling);
+                power_heuristic(bounce.pdf, pmf * light.pdf(ray, t))
+            });
+            return ray.color(light.emitted(ray)) * weight;
+        }
+
+        if let Some(hit) = hit {
+            let emitted = hit.material.emitted(ray, &hit);
+            match hit.material.scatter(ray, &hit) {
+                Some(scatter) => {
+                    let (direct, bounce) = match &scatter.surface {
+                        Some(surface) => (
+                            self.sample_lights(surface, &hit, world),
+                            surface.pdf.map(|pdf| Bounce {
+                                p: hit.p,
+                                normal: hit.normal,
+                                pdf,
+                            }),
+                        ),
+                        None => (Vec3::new(0.0, 0.0, 0.0), None),
+                    };
+                    return emitted
+                        + direct
+                        + scatter.attenuation
+                            * self.ray_color_(&scatter.scattered, world, depth + 1, bounce);
+                }
+                None => return emitted,
+            }
+        }
+
+        if self.environment.is_some() || self.sun.is_some() {
+            let weight = |light_pdf: f64| {
+                bounce.map_or(1.0, |bounce| power_heuristic(bounce.pdf, light_pdf))
+            };
+            let mut color = Vec3::new(0.0, 0.0, 0.0);
+            if let Some(environment) = &self.environment {
+                color += ray.color(environment.radiance(ray.direction))
+                    * weight(environment.pdf(ray.direction));
+            }
+            if let Some(sun) = &self.sun {
+                color += ray.color(sun.radiance(ray.direction)) * weight(sun.pdf(ray.direction));
+            }
+            return color;
+        }
+
+        let unit_direction = ray.direction.unit_vector();
+        let y_direction = unit_direction.y;
+        let a = 0.5 * (y_direction + 1.0);
+        ray.color(Vec3::new(1.0, 1.0, 1.0) * (1.0 - a) + Vec3::new(0.5, 0.7, 1.0) * a)
+    }
+
+    // Next event estimation: light from the environment map, the sun and one
+    // of the world's lights scattered at the surface towards the previous
+    // vertex. Directions sampled from lights that rays can also hit are
+    // weighted against the BSDF sampling them as well.
+    fn sample_lights(&self, surface: &SurfaceScatter, hit: &HitRecord, world: &World) -> Vec3 {
+        let mut color = Vec3::new(0.0, 0.0, 0.0);
+        if surface.bsdf.is_specular() {
+            return color;
+        }
+        if let Some(environment) = &self.environment {
+            if let Some((direction, pdf)) = environment.sample() {
+                let radiance = environment.radiance(direction);
+                color += self.direct_light(
+                    surface,
+                    hit,
+                    world,
+                    direction,
+                    f64::INFINITY,
+                    radiance,
+                    Some(pdf),
+                );
+            }
+        }
+        if let Some(sun) = &self.sun {
+            if let Some((direction, pdf)) = sun.sample() {
+                let radiance = sun.radiance(direction);
+                color += self.direct_light(
+                    surface,
+                    hit,
+                    world,
+                    direction,
+                    f64::INFINITY,
+                    radiance,
+                    Some(pdf),
+                );
+            }
+        }
+        let light = world
+            .light_tree()
+            .sample(hit.p, hit.normal, self.light_sampling)
+            .and_then(|(index, pmf)| Some((world.lights()[index].sample(hit.p)?, pmf)));
+        if let Some((sample, pmf)) = light {
+            let (radiance, pdf) = match sample.pdf {
+                Some(pdf) => (sample.radiance, Some(pdf * pmf)),
+                None => (sample.radiance / pmf, None),
+            };
+            color += self.direct_light(
+                surface,
+                hit,
+                world,
+                sample.direction,
+                sample.distance,
+                radiance,
+                pdf,
+            );
+        }
+        color
+    }
+
+    // Light of `radiance` arriving from `direction`, unless something is in
+    // the way before `distance`, which a light sampled with density
+    // `light_pdf`, or `None` for a delta light.
+    #[allow(clippy::too_many_arguments)]
+    fn direct_light(
+        &self,
+        surface: &SurfaceScatter,
+        hit: &HitRecord,
+        world: &World,
+        direction: Vec3,
+        distance: f64,
+        radiance: Vec3,
+        light_pdf: Option<f64>,
+    ) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let bsdf = &surface.bsdf;
+        let wo = bsdf.to_local(-surface.ray_in.direction.unit_vector());
+        let wi = bsdf.to_local(direction);
+        let f = bsdf.eval(wo, wi);
+        if f.length_squared() <= 0.0 || radiance.length_squared() <= 0.0 {
+            return black;
+        }
+        let shadow_ray = surface
+            .ray_in
+            .continued(hit.spawn_point(direction), direction);
+        let visibility = world.visibility(&shadow_ray, Interval::new(0.001, distance - 0.001));
+        if visibility <= 0.0 {
+            return black;
+        }
+        let transmittance = visibility
+            * self.atmosphere.map_or(1.0, |atmosphere| {
+                atmosphere.transmittance(&shadow_ray, distance, self.center)
+            });
+
+        let weight = light_pdf.map_or(1.0, |light_pdf| {
+            power_heuristic(light_pdf, bsdf.pdf(wo, wi)) / light_pdf
+        });
+        f * surface.weight * shadow_ray.color(radiance) * (transmittance * weight)
     }
+}
 
-    let unit_direction = ray.direction.unit_vector();
-    let y_direction = unit_direction.y;
-    let a = 0.5 * (y_direction + 1.0);
-    Vec3::new(1.0, 1.0, 1.0) * (1.0 - a) + Vec3::new(0.5, 0.7, 1.0) * a
+// Where a ray last scattered off a surface that also sampled lights, to
+// weight light the ray finds against theirs.
+#[derive(Clone, Copy)]
+struct Bounce {
+    p: Point,
+    normal: Vec3,
+    // the density the surface sampled the ray's direction with
+    pdf: f64,
+}
+
+// Veach's power heuristic with exponent 2, for a sample drawn with density
+// `pdf` that another strategy draws with density `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
 }
 
 fn sample_square() -> Vec3 {
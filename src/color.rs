@@ -7,12 +7,6 @@ pub(crate) struct Color {
     pub(crate) b: u8,
 }
 
-impl Color {
-    pub(crate) fn new(r: u8, g: u8, b: u8) -> Self {
-        Self { r, g, b }
-    }
-}
-
 impl From<Vec3> for Color {
     fn from(v: Vec3) -> Self {
         let to_gamma = |f: f64| {
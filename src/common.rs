@@ -1,3 +1,5 @@
+use std::io;
+
 use crate::vec3::Vec3;
 
 pub(crate) type Point = Vec3;
@@ -9,3 +11,17 @@ pub(crate) fn random_f64() -> f64 {
 pub(crate) fn random_f64_in_range(min: f64, max: f64) -> f64 {
     rand::random::<f64>() * (max - min) + min
 }
+
+/// The error for a file whose contents can't be read as what it should be.
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// A path of its own in the temporary directory for a test to write
+/// `contents` to.
+#[cfg(test)]
+pub(crate) fn temp_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("raytracer-{}-{name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
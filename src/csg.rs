@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+
+// Bounds the number of surfaces collected along a ray per operand.
+const MAX_CROSSINGS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// A boolean combination of two closed objects. Either operand may itself be
+/// a `Csg`, so trees of operations can be built up.
+pub(crate) struct Csg {
+    operation: CsgOperation,
+    left: Arc<dyn Hittable + Send + Sync>,
+    right: Arc<dyn Hittable + Send + Sync>,
+    bbox: Aabb,
+}
+
+impl Csg {
+    pub(crate) fn new(
+        operation: CsgOperation,
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        let (a, b) = (left.bounding_box(), right.bounding_box());
+        let bbox = match operation {
+            CsgOperation::Union => Aabb::enclosing(a, b),
+            CsgOperation::Intersection => Aabb {
+                x: Interval::new(a.x.min.max(b.x.min), a.x.max.min(b.x.max)),
+                y: Interval::new(a.y.min.max(b.y.min), a.y.max.min(b.y.max)),
+                z: Interval::new(a.z.min.max(b.z.min), a.z.max.min(b.z.max)),
+            },
+            CsgOperation::Difference => a,
+        };
+        Self {
+            operation,
+            left,
+            right,
+            bbox,
+        }
+    }
+
+    pub(crate) fn union(
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        Self::new(CsgOperation::Union, left, right)
+    }
+
+    pub(crate) fn intersection(
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        Self::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub(crate) fn difference(
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        Self::new(CsgOperation::Difference, left, right)
+    }
+}
+
+// Every surface crossing of a closed object along the whole line of the ray,
+// in order, and whether the line starts out inside the object.
+fn crossings(object: &dyn Hittable, ray: &Ray) -> (bool, Vec<HitRecord>) {
+    let mut hits: Vec<HitRecord> = Vec::new();
+    let mut t = f64::NEG_INFINITY;
+    while hits.len() < MAX_CROSSINGS {
+        match object.hit(ray, Interval::new(t, f64::INFINITY)) {
+            Some(hit) => {
+                t = hit.t;
+                hits.push(hit);
+            }
+            None => break,
+        }
+    }
+    // Leaving through the first surface means the line started inside.
+    let starts_inside = hits.first().is_some_and(|hit| !hit.front_face);
+    (starts_inside, hits)
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if !self.bbox.hit(ray, ray_t) {
+            return None;
+        }
+
+        let (mut in_left, left) = crossings(self.left.as_ref(), ray);
+        let (mut in_right, right) = crossings(self.right.as_ref(), ray);
+        let mut inside = self.operation.inside(in_left, in_right);
+
+        // Merge both operands' crossings in order of t, tracking which solids
+        // the ray is in. A boundary of the result is wherever that changes.
+        let mut left = left.into_iter().peekable();
+        let mut right = right.into_iter().peekable();
+        loop {
+            let from_left = match (left.peek(), right.peek()) {
+                (Some(l), Some(r)) => l.t <= r.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let mut hit = if from_left {
+                let hit = left.next()?;
+                in_left = hit.front_face;
+                hit
+            } else {
+                let hit = right.next()?;
+                in_right = hit.front_face;
+                hit
+            };
+
+            let now_inside = self.operation.inside(in_left, in_right);
+            if now_inside == inside {
+                continue;
+            }
+            inside = now_inside;
+            if hit.t >= ray_t.max {
+                return None;
+            }
+            if ray_t.surrounds(hit.t) {
+                // The normal already faces the ray. Only which side is the
+                // outside changes, e.g. for the surfaces of a subtracted
+                // object, whose outside is the inside of the result.
+                hit.front_face = now_inside;
+                return Some(hit);
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Point;
+    use crate::material::{Material, MaterialKind};
+    use crate::scene::Sphere;
+    use crate::vec3::Vec3;
+
+    // Unit spheres overlapping between x = -0.5 and 0.5.
+    fn operands() -> (
+        Arc<dyn Hittable + Send + Sync>,
+        Arc<dyn Hittable + Send + Sync>,
+    ) {
+        let material = Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        });
+        (
+            Arc::new(Sphere::new(
+                Point::new(-0.5, 0.0, 0.0),
+                1.0,
+                material.clone(),
+            )),
+            Arc::new(Sphere::new(Point::new(0.5, 0.0, 0.0), 1.0, material)),
+        )
+    }
+
+    // Where the ray from `x` along +x crosses the boundary, and whether it
+    // enters there, up to `t_max`.
+    fn boundary(csg: &Csg, x: f64, t_max: f64) -> Vec<(f64, bool)> {
+        let ray = Ray::new(Point::new(x, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let mut found = Vec::new();
+        let mut t = 0.0;
+        while let Some(hit) = csg.hit(&ray, Interval::new(t, t_max)) {
+            assert!(hit.normal.dot(ray.direction) < 0.0);
+            found.push((x + hit.t, hit.front_face));
+            t = hit.t + 1e-9;
+        }
+        found
+    }
+
+    fn assert_boundary(found: Vec<(f64, bool)>, expected: &[(f64, bool)]) {
+        assert_eq!(found.len(), expected.len(), "{found:?}");
+        for ((x, entering), (expected_x, expected_entering)) in found.into_iter().zip(expected) {
+            assert!((x - expected_x).abs() < 1e-9, "{x} vs {expected_x}");
+            assert_eq!(entering, *expected_entering);
+        }
+    }
+
+    #[test]
+    fn operations_keep_the_right_intervals() {
+        let (a, b) = operands();
+        let union = Csg::union(a.clone(), b.clone());
+        assert_boundary(boundary(&union, -5.0, 10.0), &[(-1.5, true), (1.5, false)]);
+        let intersection = Csg::intersection(a.clone(), b.clone());
+        assert_boundary(
+            boundary(&intersection, -5.0, 10.0),
+            &[(-0.5, true), (0.5, false)],
+        );
+        let difference = Csg::difference(a.clone(), b.clone());
+        assert_boundary(
+            boundary(&difference, -5.0, 10.0),
+            &[(-1.5, true), (-0.5, false)],
+        );
+        let difference = Csg::difference(b, a);
+        assert_boundary(
+            boundary(&difference, -5.0, 10.0),
+            &[(0.5, true), (1.5, false)],
+        );
+    }
+
+    #[test]
+    fn rays_starting_inside_or_stopping_short() {
+        let (a, b) = operands();
+        let union = Csg::union(a.clone(), b.clone());
+        assert_boundary(boundary(&union, 0.0, 10.0), &[(1.5, false)]);
+        let difference = Csg::difference(a.clone(), b.clone());
+        assert_boundary(boundary(&difference, -1.0, 10.0), &[(-0.5, false)]);
+        assert_boundary(boundary(&difference, -5.0, 3.0), &[]);
+
+        // off to the side, through the left sphere alone
+        let intersection = Csg::intersection(a, b);
+        let ray = Ray::new(Point::new(-1.2, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(intersection.hit(&ray, Interval::new(0.0, 10.0)).is_none());
+    }
+
+    #[test]
+    fn operations_nest() {
+        let (a, b) = operands();
+        let material = Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        });
+        let core = Arc::new(Sphere::new(Point::new(0.0, 0.0, 0.0), 0.25, material));
+        // the lens where the spheres overlap, hollowed out in the middle
+        let shell = Csg::difference(Arc::new(Csg::intersection(a, b)), core);
+        assert_boundary(
+            boundary(&shell, -5.0, 10.0),
+            &[(-0.5, true), (-0.25, false), (0.25, true), (0.5, false)],
+        );
+    }
+}
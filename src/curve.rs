@@ -0,0 +1,351 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::{invalid_data, Point};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable, World};
+use crate::vec3::Vec3;
+
+// Bounds the number of times a segment is split in half while intersecting.
+const MAX_SPLITS: u32 = 10;
+
+/// How the four control points of a cubic segment define the curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CurveBasis {
+    // passes through the first and last points
+    Bezier,
+    // uniform cubic B-spline, which joins neighboring segments smoothly
+    BSpline,
+}
+
+/// The cross section of a curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CurveShape {
+    // a ribbon that always faces the ray, for hair and fur seen from afar
+    Flat,
+    // shaded as a round tube
+    Cylinder,
+}
+
+/// A cubic curve segment with a width that varies linearly along it. It is
+/// intersected as a ribbon facing the ray, and hits report the curve
+/// parameter in u and the position across the width in v, and have the
+/// curve's direction as dpdu.
+pub(crate) struct Curve {
+    // Bezier control points
+    control_points: [Point; 4],
+    widths: (f64, f64),
+    shape: CurveShape,
+    splits: u32,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Curve {
+    pub(crate) fn new(
+        control_points: [Point; 4],
+        basis: CurveBasis,
+        widths: (f64, f64),
+        shape: CurveShape,
+        material: Arc<Material>,
+    ) -> Self {
+        let control_points = match basis {
+            CurveBasis::Bezier => control_points,
+            CurveBasis::BSpline => {
+                let [p0, p1, p2, p3] = control_points;
+                [
+                    (p0 + p1 * 4.0 + p2) / 6.0,
+                    (p1 * 2.0 + p2) / 3.0,
+                    (p1 + p2 * 2.0) / 3.0,
+                    (p1 + p2 * 4.0 + p3) / 6.0,
+                ]
+            }
+        };
+
+        // Split until the segments are close enough to straight lines
+        // relative to the width, from Pharr et al.'s bound on the distance
+        // between a Bezier curve and its chord.
+        let [p0, p1, p2, p3] = control_points;
+        let second_difference = |a: Point, b: Point, c: Point| a - b * 2.0 + c;
+        let l0 = [second_difference(p0, p1, p2), second_difference(p1, p2, p3)]
+            .iter()
+            .map(|d| d.x.abs().max(d.y.abs()).max(d.z.abs()))
+            .fold(0.0, f64::max);
+        let epsilon = widths.0.max(widths.1) * 0.05;
+        let splits = if epsilon > 0.0 && l0 > 0.0 {
+            let r0 = (std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * epsilon)).log2() / 2.0;
+            r0.round().clamp(0.0, MAX_SPLITS as f64) as u32
+        } else {
+            0
+        };
+
+        let half_width = widths.0.max(widths.1) / 2.0;
+        let padding = Vec3::new(half_width, half_width, half_width);
+        let bbox = control_points.iter().fold(Aabb::EMPTY, |bbox, &p| {
+            Aabb::enclosing(bbox, Aabb::from_points(p - padding, p + padding))
+        });
+
+        Self {
+            control_points,
+            widths,
+            shape,
+            splits,
+            material,
+            bbox,
+        }
+    }
+
+    fn width(&self, u: f64) -> f64 {
+        self.widths.0 + (self.widths.1 - self.widths.0) * u
+    }
+
+    // Intersects the part of the curve over [u0, u1] with control points
+    // `cp`, given in a frame where the ray starts at the origin and runs
+    // along +z with unit speed. Returns the distance along the ray, u, v,
+    // and the point and derivative of the curve at u, in that frame.
+    fn hit_segment(
+        &self,
+        cp: &[Point; 4],
+        (u0, u1): (f64, f64),
+        depth_range: &mut Interval,
+        splits: u32,
+    ) -> Option<(f64, f64, f64, Point, Vec3)> {
+        if splits > 0 {
+            let halves = split_bezier(cp);
+            let u_mid = (u0 + u1) / 2.0;
+            let mut closest = None;
+            for (half, range) in halves.iter().zip([(u0, u_mid), (u_mid, u1)]) {
+                let half_width = self.width(range.0).max(self.width(range.1)) / 2.0;
+                let bounds = |axis: fn(&Point) -> f64| {
+                    half.iter().map(axis).fold(Interval::EMPTY, |i, x| {
+                        Interval::enclosing(i, Interval::new(x, x))
+                    })
+                };
+                let (x, y, z) = (bounds(|p| p.x), bounds(|p| p.y), bounds(|p| p.z));
+                if x.min - half_width > 0.0
+                    || x.max + half_width < 0.0
+                    || y.min - half_width > 0.0
+                    || y.max + half_width < 0.0
+                    || z.min - half_width > depth_range.max
+                    || z.max + half_width < depth_range.min
+                {
+                    continue;
+                }
+                if let Some(hit) = self.hit_segment(half, range, depth_range, splits - 1) {
+                    depth_range.max = hit.0;
+                    closest = Some(hit);
+                }
+            }
+            return closest;
+        }
+
+        // The ray must pass between the planes through the end points
+        // perpendicular to the curve there.
+        let [p0, p1, p2, p3] = *cp;
+        if (p1.x - p0.x) * -p0.x + (p1.y - p0.y) * -p0.y < 0.0
+            || (p2.x - p3.x) * -p3.x + (p2.y - p3.y) * -p3.y < 0.0
+        {
+            return None;
+        }
+
+        // Take the segment as a line and find where the ray passes closest
+        // to it in the xy plane.
+        let (dx, dy) = (p3.x - p0.x, p3.y - p0.y);
+        let denominator = dx * dx + dy * dy;
+        if denominator == 0.0 {
+            return None;
+        }
+        let w = ((-p0.x * dx - p0.y * dy) / denominator).clamp(0.0, 1.0);
+        let u = (u0 + (u1 - u0) * w).clamp(u0, u1);
+        let hit_width = self.width(u);
+        let (pc, dpcdw) = eval_bezier(cp, w);
+
+        let distance_squared = pc.x * pc.x + pc.y * pc.y;
+        if distance_squared > hit_width * hit_width / 4.0 || !depth_range.surrounds(pc.z) {
+            return None;
+        }
+        // Which side of the curve the ray passed on decides the sign of v.
+        let distance = distance_squared.sqrt();
+        let side = dpcdw.x * -pc.y + pc.x * dpcdw.y;
+        let v = if side > 0.0 {
+            0.5 + distance / hit_width
+        } else {
+            0.5 - distance / hit_width
+        };
+        Some((pc.z, u, v, pc, dpcdw))
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if !self.bbox.hit(ray, ray_t) {
+            return None;
+        }
+
+        let ray_length = ray.direction.length();
+        let frame = Onb::new(ray.direction);
+        let cp = self.control_points.map(|p| frame.to_local(p - ray.origin));
+        let mut depth_range = Interval::new(ray_t.min * ray_length, ray_t.max * ray_length);
+        let (depth, u, v, pc, dpcdw) =
+            self.hit_segment(&cp, (0.0, 1.0), &mut depth_range, self.splits)?;
+
+        let t = depth / ray_length;
+        let dpdu = frame.to_world(dpcdw);
+        let tangent = dpdu.unit_vector();
+        let facing = -frame.w;
+        let normal = match self.shape {
+            CurveShape::Flat => facing,
+            // Bend the facing normal around the curve with the offset
+            // across the width, as if the ray hit a tube.
+            CurveShape::Cylinder => {
+                let across = tangent.cross(facing).unit_vector();
+                let offset = (2.0 * v - 1.0).clamp(-1.0, 1.0);
+                across * offset + facing * (1.0 - offset * offset).sqrt()
+            }
+        };
+        // Shading needs the normal perpendicular to the fiber.
+        let normal = normal - tangent * normal.dot(tangent);
+        let normal = if normal.is_near_zero() {
+            facing
+        } else {
+            normal.unit_vector()
+        };
+
+        // The hit lies on the curve's center line, at the depth of the
+        // closest point.
+        let p = ray.origin + frame.to_world(Vec3::new(0.0, 0.0, pc.z));
+        let across = normal.cross(tangent);
+        Some(
+            HitRecord::new(ray, p, normal, t, (u, v), self.material.clone())
+                .with_derivatives(dpdu, across),
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+// The point and derivative of a cubic Bezier curve at `t`.
+fn eval_bezier(cp: &[Point; 4], t: f64) -> (Point, Vec3) {
+    let lerp = |a: Point, b: Point| a * (1.0 - t) + b * t;
+    let (a, b, c) = (lerp(cp[0], cp[1]), lerp(cp[1], cp[2]), lerp(cp[2], cp[3]));
+    let (d, e) = (lerp(a, b), lerp(b, c));
+    let derivative = if (e - d).is_near_zero() {
+        cp[3] - cp[0]
+    } else {
+        (e - d) * 3.0
+    };
+    (lerp(d, e), derivative)
+}
+
+// Splits a cubic Bezier curve at its middle with de Casteljau's algorithm.
+fn split_bezier(cp: &[Point; 4]) -> [[Point; 4]; 2] {
+    let mid = |a: Point, b: Point| (a + b) / 2.0;
+    let (a, b, c) = (mid(cp[0], cp[1]), mid(cp[1], cp[2]), mid(cp[2], cp[3]));
+    let (d, e) = (mid(a, b), mid(b, c));
+    let center = mid(d, e);
+    [[cp[0], a, d, center], [center, e, c, cp[3]]]
+}
+
+/// Builds the segments of a strand through `points`: 3n + 1 points make n
+/// Bezier segments sharing their end points, and n + 3 points make n
+/// B-spline segments. The width varies linearly along the whole strand.
+pub(crate) fn strand(
+    points: &[Point],
+    basis: CurveBasis,
+    widths: (f64, f64),
+    shape: CurveShape,
+    material: Arc<Material>,
+) -> World {
+    let (step, segments) = match basis {
+        CurveBasis::Bezier => (3, points.len().saturating_sub(1) / 3),
+        CurveBasis::BSpline => (1, points.len().saturating_sub(3)),
+    };
+    let width = |i: usize| widths.0 + (widths.1 - widths.0) * i as f64 / segments as f64;
+
+    let mut world = World::new();
+    for i in 0..segments {
+        let start = i * step;
+        let control_points = [
+            points[start],
+            points[start + 1],
+            points[start + 2],
+            points[start + 3],
+        ];
+        world.add(Curve::new(
+            control_points,
+            basis,
+            (width(i), width(i + 1)),
+            shape,
+            material.clone(),
+        ));
+    }
+    world
+}
+
+/// Loads strands from a text file with one strand per line:
+///
+/// `bezier|bspline flat|cylinder <width at root> <width at tip> x y z ...`
+///
+/// followed by the control points as `strand` takes them. `#` starts a
+/// comment.
+pub(crate) fn load_curves(path: impl AsRef<Path>, material: Arc<Material>) -> io::Result<World> {
+    let text = fs::read_to_string(path)?;
+    let mut world = World::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(basis) = fields.next() else {
+            continue;
+        };
+        let error = |message: &str| invalid_data(&format!("line {}: {message}", number + 1));
+
+        let basis = match basis {
+            "bezier" => CurveBasis::Bezier,
+            "bspline" => CurveBasis::BSpline,
+            _ => return Err(error(&format!("unknown curve basis: {basis}"))),
+        };
+        let shape = match fields.next() {
+            Some("flat") => CurveShape::Flat,
+            Some("cylinder") => CurveShape::Cylinder,
+            _ => return Err(error("expected a curve shape, flat or cylinder")),
+        };
+        let numbers = fields
+            .map(|field| {
+                field
+                    .parse::<f64>()
+                    .map_err(|_| error(&format!("invalid number: {field}")))
+            })
+            .collect::<io::Result<Vec<f64>>>()?;
+        if numbers.len() < 2 || !(numbers.len() - 2).is_multiple_of(3) {
+            return Err(error("expected two widths and x y z triples"));
+        }
+
+        let points: Vec<Point> = numbers[2..]
+            .chunks_exact(3)
+            .map(|p| Point::new(p[0], p[1], p[2]))
+            .collect();
+        let valid = match basis {
+            CurveBasis::Bezier => points.len() >= 4 && (points.len() - 1).is_multiple_of(3),
+            CurveBasis::BSpline => points.len() >= 4,
+        };
+        if !valid {
+            return Err(error("wrong number of control points for the basis"));
+        }
+        world.add(strand(
+            &points,
+            basis,
+            (numbers[0], numbers[1]),
+            shape,
+            material.clone(),
+        ));
+    }
+    Ok(world)
+}
@@ -0,0 +1,176 @@
+/// A piecewise-constant density over [0, 1), proportional to `func` on
+/// equal-width pieces, sampled by inverting its cumulative distribution.
+pub(crate) struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// A uniform density if `func` is zero everywhere. Panics if `func` is
+    /// empty.
+    pub(crate) fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        assert!(n > 0, "a distribution needs at least one piece");
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub(crate) fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Maps a uniform `u` to a point in [0, 1), returning it with its density
+    /// and the index of the piece it falls in.
+    pub(crate) fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.func.len();
+        // the last piece whose cumulative distribution starts at or below u
+        let offset = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            (u - self.cdf[offset]) / width
+        } else {
+            0.0
+        };
+        let x = ((offset as f64 + du) / n as f64).min(1.0 - f64::EPSILON);
+        (x, self.piece_pdf(offset), offset)
+    }
+
+    pub(crate) fn pdf(&self, x: f64) -> f64 {
+        self.piece_pdf(self.offset(x))
+    }
+
+    /// The probability that `sample` lands in piece `offset`.
+    pub(crate) fn discrete_pmf(&self, offset: usize) -> f64 {
+        self.piece_pdf(offset) / self.func.len() as f64
+    }
+
+    fn offset(&self, x: f64) -> usize {
+        ((x * self.func.len() as f64) as usize).min(self.func.len() - 1)
+    }
+
+    fn piece_pdf(&self, offset: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[offset].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// A piecewise-constant density over the unit square, given as rows of
+/// values over `u` from `v = 0` up. Samples pick a row from the marginal
+/// density over `v`, then a point in it from that row's conditional density.
+pub(crate) struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub(crate) fn new(rows: Vec<Vec<f64>>) -> Self {
+        let conditional: Vec<_> = rows.into_iter().map(Distribution1D::new).collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Maps uniform `(u0, u1)` to a point `(u, v)`, returning it with its
+    /// density.
+    pub(crate) fn sample(&self, u0: f64, u1: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample(u0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub(crate) fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = &self.conditional[self.marginal.offset(v)];
+        self.marginal.pdf(v) * row.pdf(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uniform(n: usize) -> impl Iterator<Item = f64> {
+        (0..n).map(move |i| (i as f64 + 0.5) / n as f64)
+    }
+
+    #[test]
+    fn samples_land_in_proportion_to_func() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0, 4.0]);
+        assert!((distribution.integral() - 2.0).abs() < 1e-12);
+
+        let mut counts = [0; 4];
+        for u in uniform(8000) {
+            let (x, pdf, offset) = distribution.sample(u);
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(offset, distribution.offset(x));
+            assert_eq!(pdf, distribution.pdf(x));
+            counts[offset] += 1;
+        }
+        for (offset, count) in counts.into_iter().enumerate() {
+            let share = count as f64 / 8000.0;
+            assert!((share - distribution.discrete_pmf(offset)).abs() < 1e-3);
+        }
+        assert_eq!(counts[1], 0);
+    }
+
+    #[test]
+    fn zero_func_is_uniform() {
+        let distribution = Distribution1D::new(vec![0.0; 5]);
+        for u in uniform(50) {
+            let (x, pdf, _) = distribution.sample(u);
+            assert!((x - u).abs() < 1e-12);
+            assert_eq!(pdf, 1.0);
+        }
+        assert!((distribution.discrete_pmf(2) - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_func_is_rejected() {
+        Distribution1D::new(Vec::new());
+    }
+
+    #[test]
+    fn joint_density_matches_sample_and_integrates_to_one() {
+        let distribution = Distribution2D::new(vec![
+            vec![1.0, 2.0, 0.0],
+            vec![0.0, 0.0, 0.0],
+            vec![5.0, 1.0, 3.0],
+        ]);
+        for u0 in uniform(30) {
+            for u1 in uniform(30) {
+                let ((u, v), pdf) = distribution.sample(u0, u1);
+                assert!(pdf > 0.0);
+                assert!((pdf - distribution.pdf(u, v)).abs() < 1e-12);
+            }
+        }
+
+        let n = 60;
+        let integral: f64 = uniform(n)
+            .flat_map(|v| uniform(n).map(move |u| (u, v)))
+            .map(|(u, v)| distribution.pdf(u, v))
+            .sum::<f64>()
+            / (n * n) as f64;
+        assert!((integral - 1.0).abs() < 1e-9);
+    }
+}
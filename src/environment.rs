@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::common::random_f64;
+use crate::distribution::Distribution2D;
+use crate::texture::ImageData;
+use crate::vec3::Vec3;
+
+/// Light from infinitely far away in every direction, given by an
+/// equirectangular (latitude-longitude) image such as an HDR panorama. The
+/// image wraps around the y axis with its top row straight up, laid out
+/// like the UV coordinates of a sphere.
+pub struct Environment {
+    image: ImageData,
+    intensity: f64,
+    // about the y axis, in radians
+    rotation: f64,
+    // over the image's UV coordinates, in proportion to the light coming from
+    // each pixel's solid angle
+    distribution: Distribution2D,
+}
+
+impl Environment {
+    /// Loads a `.hdr` or `.exr` panorama, or any other image, taken as
+    /// linear. `intensity` scales its radiance, and `rotation` turns it
+    /// about the y axis, in degrees.
+    pub fn load(path: impl AsRef<Path>, intensity: f64, rotation: f64) -> image::ImageResult<Self> {
+        Ok(Self::from_image(
+            ImageData::load(path, false)?,
+            intensity,
+            rotation,
+        ))
+    }
+
+    pub(crate) fn from_image(image: ImageData, intensity: f64, rotation: f64) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let rows = (0..height)
+            .map(|j| {
+                // rows from v = 0, the bottom of the image, up; pixels near
+                // the poles cover less solid angle
+                let y = height - 1 - j;
+                let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+                (0..width)
+                    .map(|x| luminance(image.pixel(x, y)) * sin_theta)
+                    .collect()
+            })
+            .collect();
+        Self {
+            image,
+            intensity,
+            rotation: rotation.to_radians(),
+            distribution: Distribution2D::new(rows),
+        }
+    }
+
+    /// The radiance arriving from `direction`, in linear RGB.
+    pub(crate) fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        // keep the lookup from wrapping around the poles
+        let half_pixel = 0.5 / self.image.height() as f64;
+        self.image.sample(u, v.clamp(half_pixel, 1.0 - half_pixel)) * self.intensity
+    }
+
+    /// Samples a direction toward the environment in proportion to the
+    /// light from it, returning it with its density per unit solid angle.
+    pub(crate) fn sample(&self) -> Option<(Vec3, f64)> {
+        let ((u, v), pdf) = self.distribution.sample(random_f64(), random_f64());
+        let sin_theta = (PI * v).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some((
+            self.uv_to_direction(u, v),
+            pdf / (2.0 * PI * PI * sin_theta),
+        ))
+    }
+
+    /// The density per unit solid angle with which `sample` picks
+    /// `direction`.
+    pub(crate) fn pdf(&self, direction: Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    // The same mapping as `sphere_uv`, after undoing the rotation.
+    fn direction_to_uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = rotate_y(direction.unit_vector(), -self.rotation);
+        let theta = (-d.y).clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let (sin_theta, cos_theta) = (PI * v).sin_cos();
+        let (sin_phi, cos_phi) = (2.0 * PI * u).sin_cos();
+        let d = Vec3::new(-sin_theta * cos_phi, -cos_theta, sin_theta * sin_phi);
+        rotate_y(d, self.rotation)
+    }
+}
+
+fn rotate_y(v: Vec3, angle: f64) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(cos * v.x + sin * v.z, v.y, -sin * v.x + cos * v.z)
+}
+
+fn luminance(color: Vec3) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
@@ -0,0 +1,362 @@
+use std::f64::consts::{LN_2, PI};
+
+use crate::microfacet::fr_dielectric;
+use crate::vec3::Vec3;
+
+// Lobes evaluated separately: R, TT and TRT. Longer paths are lumped into
+// one more, isotropic in azimuth.
+const P_MAX: usize = 3;
+
+const SQRT_PI_OVER_8: f64 = 0.626_657_068_657_750_1;
+
+/// Absorption coefficient of a fiber with the given concentrations of
+/// eumelanin (brown to black) and pheomelanin (red), after d'Eon et al.
+pub(crate) fn sigma_a_from_melanin(eumelanin: f64, pheomelanin: f64) -> Vec3 {
+    let eumelanin_sigma_a = Vec3::new(0.419, 0.697, 1.37);
+    let pheomelanin_sigma_a = Vec3::new(0.187, 0.4, 1.05);
+    eumelanin_sigma_a * eumelanin + pheomelanin_sigma_a * pheomelanin
+}
+
+/// Absorption coefficient that gives a fiber roughly the color `color` in
+/// multiple scattering, for azimuthal roughness `beta_n`. From Chiang et al.,
+/// "A Practical and Controllable Hair and Fur Model for Production Path
+/// Tracing".
+pub(crate) fn sigma_a_from_reflectance(color: Vec3, beta_n: f64) -> Vec3 {
+    let denominator = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+        + 5.574 * beta_n.powi(4)
+        + 0.245 * beta_n.powi(5);
+    let channel = |c: f64| (c.max(1e-4).ln() / denominator).powi(2);
+    Vec3::new(channel(color.x), channel(color.y), channel(color.z))
+}
+
+/// The hair scattering model of Chiang et al., as formulated in pbrt-v3,
+/// with lobes for reflection (R), transmission (TT) and internal reflection
+/// (TRT) separated by the longitudinal and azimuthal roughnesses `beta_m`
+/// and `beta_n` in [0, 1], and the cuticle scale tilt `alpha` in degrees.
+///
+/// `h` in [-1, 1] is where across the fiber width the ray hit it. Directions
+/// are in a local frame with x along the fiber.
+pub(crate) struct HairBsdf {
+    h: f64,
+    gamma_o: f64,
+    eta: f64,
+    sigma_a: Vec3,
+    v: [f64; P_MAX + 1],
+    s: f64,
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+impl HairBsdf {
+    pub(crate) fn new(
+        h: f64,
+        eta: f64,
+        sigma_a: Vec3,
+        beta_m: f64,
+        beta_n: f64,
+        alpha: f64,
+    ) -> Self {
+        let h = h.clamp(-1.0, 1.0);
+
+        // longitudinal variance per lobe, wider for the longer paths
+        let v0 = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let v = [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0];
+        let s =
+            SQRT_PI_OVER_8 * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+
+        // sines and cosines of 2^k alpha, for tilting the lobes
+        let mut sin_2k_alpha = [alpha.to_radians().sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+
+        Self {
+            h,
+            gamma_o: h.asin(),
+            eta,
+            sigma_a,
+            v,
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// The BSDF value for light arriving from `wi` and leaving towards `wo`.
+    pub(crate) fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let (sin_theta_o, cos_theta_o, phi_o) = angles(wo);
+        let (sin_theta_i, cos_theta_i, phi_i) = angles(wi);
+        let (gamma_t, ap) = self.attenuations(sin_theta_o, cos_theta_o);
+
+        let phi = phi_i - phi_o;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        for (p, ap) in ap.iter().enumerate().take(P_MAX) {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            sum +=
+                *ap * mp(
+                    cos_theta_i,
+                    cos_theta_op,
+                    sin_theta_i,
+                    sin_theta_op,
+                    self.v[p],
+                ) * np(phi, p, self.s, self.gamma_o, gamma_t);
+        }
+        sum += ap[P_MAX]
+            * (mp(
+                cos_theta_i,
+                cos_theta_o,
+                sin_theta_i,
+                sin_theta_o,
+                self.v[P_MAX],
+            ) / (2.0 * PI));
+
+        if wi.z.abs() > 0.0 {
+            sum / wi.z.abs()
+        } else {
+            sum
+        }
+    }
+
+    /// Samples a direction of incident light for outgoing direction `wo` by
+    /// picking a lobe with `uc` and sampling its longitudinal and azimuthal
+    /// terms with `u` and what is left of `uc`, as in pbrt-v4.
+    pub(crate) fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Vec3 {
+        let (sin_theta_o, cos_theta_o, phi_o) = angles(wo);
+        let ap_pdf = self.lobe_pdfs(sin_theta_o, cos_theta_o);
+
+        // Pick the lobe whose share of [0, 1) uc falls in, and stretch that
+        // share back over [0, 1).
+        let mut uc = uc;
+        let mut p = P_MAX;
+        for (lobe, pdf) in ap_pdf.iter().enumerate().take(P_MAX) {
+            if uc < *pdf {
+                p = lobe;
+                uc /= pdf;
+                break;
+            }
+            uc -= pdf;
+        }
+        if p == P_MAX {
+            uc = (uc / ap_pdf[P_MAX]).min(1.0 - f64::EPSILON);
+        }
+
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let u_m = u.0.max(1e-5);
+        let cos_theta = 1.0 + self.v[p] * (u_m + (1.0 - u_m) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * u.1).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o);
+        let dphi = if p < P_MAX {
+            phi(p, self.gamma_o, gamma_t) + sample_trimmed_logistic(uc, self.s, -PI, PI)
+        } else {
+            2.0 * PI * uc
+        };
+        let phi_i = phi_o + dphi;
+        Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        )
+    }
+
+    /// The density with which `sample` returns `wi` for `wo`.
+    pub(crate) fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let (sin_theta_o, cos_theta_o, phi_o) = angles(wo);
+        let (sin_theta_i, cos_theta_i, phi_i) = angles(wi);
+        let ap_pdf = self.lobe_pdfs(sin_theta_o, cos_theta_o);
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o);
+
+        let dphi = phi_i - phi_o;
+        let mut pdf = 0.0;
+        for (p, ap_pdf) in ap_pdf.iter().enumerate().take(P_MAX) {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            pdf += mp(
+                cos_theta_i,
+                cos_theta_op,
+                sin_theta_i,
+                sin_theta_op,
+                self.v[p],
+            ) * ap_pdf
+                * np(dphi, p, self.s, self.gamma_o, gamma_t);
+        }
+        pdf + mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * ap_pdf[P_MAX]
+            / (2.0 * PI)
+    }
+
+    // The azimuthal angle of refraction into the fiber.
+    fn gamma_t(&self, sin_theta_o: f64, cos_theta_o: f64) -> f64 {
+        let etap = safe_sqrt(self.eta * self.eta - sin_theta_o * sin_theta_o) / cos_theta_o;
+        safe_asin(self.h / etap)
+    }
+
+    // The refraction angle and the attenuation of each lobe, from Fresnel
+    // reflection at the cuticle and absorption inside the fiber.
+    fn attenuations(&self, sin_theta_o: f64, cos_theta_o: f64) -> (f64, [Vec3; P_MAX + 1]) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o);
+        let cos_gamma_t = gamma_t.cos();
+        let path_length = 2.0 * cos_gamma_t / cos_theta_t;
+        let transmittance = Vec3::new(
+            (-self.sigma_a.x * path_length).exp(),
+            (-self.sigma_a.y * path_length).exp(),
+            (-self.sigma_a.z * path_length).exp(),
+        );
+
+        let cos_gamma_o = safe_sqrt(1.0 - self.h * self.h);
+        let f = fr_dielectric(cos_theta_o * cos_gamma_o, self.eta);
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        let mut ap = [Vec3::new(0.0, 0.0, 0.0); P_MAX + 1];
+        ap[0] = one * f;
+        ap[1] = transmittance * (1.0 - f).powi(2);
+        for p in 2..P_MAX {
+            ap[p] = ap[p - 1] * transmittance * f;
+        }
+        // the geometric series of all longer paths
+        let tf = transmittance * f;
+        ap[P_MAX] = ap[P_MAX - 1]
+            * Vec3::new(
+                tf.x / (1.0 - tf.x),
+                tf.y / (1.0 - tf.y),
+                tf.z / (1.0 - tf.z),
+            );
+        (gamma_t, ap)
+    }
+
+    // How often to sample each lobe, in proportion to its luminance.
+    fn lobe_pdfs(&self, sin_theta_o: f64, cos_theta_o: f64) -> [f64; P_MAX + 1] {
+        let (_, ap) = self.attenuations(sin_theta_o, cos_theta_o);
+        let luminance = ap.map(|a| 0.2126 * a.x + 0.7152 * a.y + 0.0722 * a.z);
+        let sum: f64 = luminance.iter().sum();
+        if sum > 0.0 {
+            luminance.map(|l| l / sum)
+        } else {
+            [1.0 / (P_MAX + 1) as f64; P_MAX + 1]
+        }
+    }
+
+    // The outgoing angle shifted by the scales on the cuticle: R is tilted
+    // by -2 alpha, TT by alpha and TRT by 4 alpha.
+    fn tilt(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        let (sin, cos) = (&self.sin_2k_alpha, &self.cos_2k_alpha);
+        let (sin_theta_op, cos_theta_op) = match p {
+            0 => (
+                sin_theta_o * cos[1] - cos_theta_o * sin[1],
+                cos_theta_o * cos[1] + sin_theta_o * sin[1],
+            ),
+            1 => (
+                sin_theta_o * cos[0] + cos_theta_o * sin[0],
+                cos_theta_o * cos[0] - sin_theta_o * sin[0],
+            ),
+            2 => (
+                sin_theta_o * cos[2] + cos_theta_o * sin[2],
+                cos_theta_o * cos[2] - sin_theta_o * sin[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin_theta_op, cos_theta_op.abs())
+    }
+}
+
+// Sine, cosine and azimuth of a direction's angle to the normal plane of the
+// fiber.
+fn angles(w: Vec3) -> (f64, f64, f64) {
+    let sin_theta = w.x.clamp(-1.0, 1.0);
+    (
+        sin_theta,
+        safe_sqrt(1.0 - sin_theta * sin_theta),
+        w.z.atan2(w.y),
+    )
+}
+
+// Longitudinal scattering with variance `v`.
+fn mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        (log_i0(a) - b - 1.0 / v + LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+// Azimuthal scattering of lobe `p` through relative azimuth `phi`.
+fn np(relative_phi: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = relative_phi - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+// The azimuth at which lobe `p` leaves the fiber.
+fn phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    let p = p as f64;
+    2.0 * p * gamma_t - 2.0 * gamma_o + p * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+// The modified Bessel function of the first kind, by its power series.
+fn i0(x: f64) -> f64 {
+    let mut value = 0.0;
+    let mut x2i = 1.0;
+    let mut factorial = 1.0;
+    let mut four_i = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f64;
+        }
+        value += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.0;
+    }
+    value
+}
+
+fn log_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f64) -> f64 {
+    x.clamp(-1.0, 1.0).asin()
+}
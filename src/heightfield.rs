@@ -0,0 +1,439 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::{invalid_data, Point};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::vec3::Vec3;
+
+// One level of the min/max mipmap over the grid cells. Level 0 has an entry
+// per cell, and each level above merges 2x2 entries of the one below.
+struct Level {
+    width: usize,
+    depth: usize,
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+/// A terrain surface sampled on a regular grid of heights, drawn as two
+/// triangles per grid cell with normals interpolated between the samples.
+///
+/// The grid spans `size.x` along x and `size.z` along z from `corner`, and a
+/// height h is placed at `corner.y + h * size.y`. u runs along x and v along
+/// z, both over [0, 1] across the whole extent. The front face is the side
+/// facing +y.
+pub(crate) struct Heightfield {
+    nx: usize,
+    nz: usize,
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    levels: Vec<Level>,
+    corner: Point,
+    size: Vec3,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Heightfield {
+    /// `heights` has `nx * nz` samples in rows of constant z, with x varying
+    /// fastest. There must be at least two samples along each axis.
+    pub(crate) fn new(
+        nx: usize,
+        nz: usize,
+        heights: Vec<f32>,
+        corner: Point,
+        size: Vec3,
+        material: Arc<Material>,
+    ) -> Self {
+        assert!(
+            nx >= 2 && nz >= 2,
+            "a heightfield needs at least 2x2 samples"
+        );
+        assert_eq!(heights.len(), nx * nz);
+
+        let mut field = Self {
+            nx,
+            nz,
+            heights,
+            normals: Vec::new(),
+            levels: Vec::new(),
+            corner,
+            size,
+            material,
+            bbox: Aabb::EMPTY,
+        };
+        field.normals = field.vertex_normals();
+        field.levels = field.mipmap();
+        let top = &field.levels[field.levels.len() - 1];
+        let (y0, y1) = field.height_range(top.min[0], top.max[0]);
+        field.bbox = Aabb::from_points(
+            Point::new(corner.x, y0, corner.z),
+            Point::new(corner.x + size.x, y1, corner.z + size.z),
+        );
+        field
+    }
+
+    /// Loads heights from a grayscale image, one sample per pixel with the
+    /// top row at z = `corner.z`. Integer images are normalized to [0, 1]
+    /// and floating point ones (such as DEM tiles in TIFF) are used as is.
+    pub(crate) fn from_image(
+        path: impl AsRef<Path>,
+        corner: Point,
+        size: Vec3,
+        material: Arc<Material>,
+    ) -> image::ImageResult<Self> {
+        let image = image::open(path)?.to_luma32f();
+        let (nx, nz) = (image.width() as usize, image.height() as usize);
+        if nx < 2 || nz < 2 {
+            return Err(image::ImageError::Parameter(
+                image::error::ParameterError::from_kind(
+                    image::error::ParameterErrorKind::DimensionMismatch,
+                ),
+            ));
+        }
+        Ok(Self::new(nx, nz, image.into_raw(), corner, size, material))
+    }
+
+    /// Loads heights from a text grid: a header line `nx nz`, then the
+    /// samples separated by whitespace in the order `new` takes them, with
+    /// `#` starting a comment.
+    pub(crate) fn load_grid(
+        path: impl AsRef<Path>,
+        corner: Point,
+        size: Vec3,
+        material: Arc<Material>,
+    ) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut values = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(str::split_whitespace);
+
+        let mut dimension = || {
+            values
+                .next()
+                .and_then(|field| field.parse::<usize>().ok())
+                .ok_or_else(|| invalid_data("heightfield grid must start with nx nz"))
+        };
+        let (nx, nz) = (dimension()?, dimension()?);
+        let heights = values
+            .map(|value| {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| invalid_data(&format!("invalid height: {value}")))
+            })
+            .collect::<io::Result<Vec<f32>>>()?;
+        if nx < 2 || nz < 2 || heights.len() != nx * nz {
+            return Err(invalid_data(&format!(
+                "expected a grid of at least 2x2 heights, found {} for {nx}x{nz}",
+                heights.len()
+            )));
+        }
+        Ok(Self::new(nx, nz, heights, corner, size, material))
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[i + self.nx * j] as f64
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point {
+        Point::new(
+            self.corner.x + self.size.x * i as f64 / (self.nx - 1) as f64,
+            self.corner.y + self.size.y * self.height(i, j),
+            self.corner.z + self.size.z * j as f64 / (self.nz - 1) as f64,
+        )
+    }
+
+    // World space y extent of heights between `min` and `max`, which is
+    // flipped when the vertical scale is negative.
+    fn height_range(&self, min: f32, max: f32) -> (f64, f64) {
+        let a = self.corner.y + self.size.y * min as f64;
+        let b = self.corner.y + self.size.y * max as f64;
+        (a.min(b), a.max(b))
+    }
+
+    // Upward facing normals at the samples, from central differences (one
+    // sided on the border) of the scaled heights.
+    fn vertex_normals(&self) -> Vec<Vec3> {
+        let dx = self.size.x / (self.nx - 1) as f64;
+        let dz = self.size.z / (self.nz - 1) as f64;
+        let mut normals = Vec::with_capacity(self.nx * self.nz);
+        for j in 0..self.nz {
+            for i in 0..self.nx {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.nz - 1));
+                let slope_x = self.size.y * (self.height(i1, j) - self.height(i0, j))
+                    / ((i1 - i0) as f64 * dx);
+                let slope_z = self.size.y * (self.height(i, j1) - self.height(i, j0))
+                    / ((j1 - j0) as f64 * dz);
+                normals.push(Vec3::new(-slope_x, 1.0, -slope_z).unit_vector());
+            }
+        }
+        normals
+    }
+
+    fn mipmap(&self) -> Vec<Level> {
+        let (width, depth) = (self.nx - 1, self.nz - 1);
+        let mut min = Vec::with_capacity(width * depth);
+        let mut max = Vec::with_capacity(width * depth);
+        for j in 0..depth {
+            for i in 0..width {
+                let corners = [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)]
+                    .map(|(i, j)| self.heights[i + self.nx * j]);
+                min.push(corners.into_iter().fold(f32::INFINITY, f32::min));
+                max.push(corners.into_iter().fold(f32::NEG_INFINITY, f32::max));
+            }
+        }
+
+        let mut levels = vec![Level {
+            width,
+            depth,
+            min,
+            max,
+        }];
+        while let Some(below) = levels.last().filter(|l| l.width > 1 || l.depth > 1) {
+            let (width, depth) = (below.width.div_ceil(2), below.depth.div_ceil(2));
+            let mut min = vec![f32::INFINITY; width * depth];
+            let mut max = vec![f32::NEG_INFINITY; width * depth];
+            for j in 0..below.depth {
+                for i in 0..below.width {
+                    let (from, to) = (i + below.width * j, i / 2 + width * (j / 2));
+                    min[to] = min[to].min(below.min[from]);
+                    max[to] = max[to].max(below.max[from]);
+                }
+            }
+            levels.push(Level {
+                width,
+                depth,
+                min,
+                max,
+            });
+        }
+        levels
+    }
+
+    // The box around node (i, j) of a mipmap level, covering 2^level cells
+    // along each axis, clamped to the grid.
+    fn node_box(&self, level: usize, i: usize, j: usize) -> Aabb {
+        let span = 1 << level;
+        let cells_x = (self.nx - 1) as f64;
+        let cells_z = (self.nz - 1) as f64;
+        let x0 = (i * span) as f64 / cells_x;
+        let x1 = (((i + 1) * span) as f64 / cells_x).min(1.0);
+        let z0 = (j * span) as f64 / cells_z;
+        let z1 = (((j + 1) * span) as f64 / cells_z).min(1.0);
+
+        let l = &self.levels[level];
+        let index = i + l.width * j;
+        let (y0, y1) = self.height_range(l.min[index], l.max[index]);
+        let bbox = Aabb::from_points(
+            Point::new(
+                self.corner.x + self.size.x * x0,
+                y0,
+                self.corner.z + self.size.z * z0,
+            ),
+            Point::new(
+                self.corner.x + self.size.x * x1,
+                y1,
+                self.corner.z + self.size.z * z1,
+            ),
+        );
+        // Triangle edges lie on the box faces, so leave some slack for
+        // rounding in the slab test.
+        let slack = 1e-9 * (self.size.x.abs() + self.size.y.abs() + self.size.z.abs());
+        Aabb::new(
+            bbox.x.expand(slack),
+            bbox.y.expand(slack),
+            bbox.z.expand(slack),
+        )
+    }
+
+    // Walks down the mipmap from node (i, j), skipping nodes whose bounds
+    // the ray misses, and visits the children nearest the ray origin first
+    // so that `ray_t` shrinks quickly.
+    fn hit_node(
+        &self,
+        ray: &Ray,
+        ray_t: &mut Interval,
+        level: usize,
+        (i, j): (usize, usize),
+    ) -> Option<HitRecord> {
+        let l = &self.levels[level];
+        if i >= l.width || j >= l.depth {
+            return None;
+        }
+        self.node_box(level, i, j).clip(ray, *ray_t)?;
+        if level == 0 {
+            return self.hit_cell(ray, ray_t, i, j);
+        }
+
+        // Children are ordered along each axis by the sign of the direction
+        // in grid space.
+        let flip_x = (ray.direction.x * self.size.x < 0.0) as usize;
+        let flip_z = (ray.direction.z * self.size.z < 0.0) as usize;
+        let mut closest = None;
+        for (a, b) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let child = (2 * i + (a ^ flip_x), 2 * j + (b ^ flip_z));
+            if let Some(hit) = self.hit_node(ray, ray_t, level - 1, child) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn hit_cell(&self, ray: &Ray, ray_t: &mut Interval, i: usize, j: usize) -> Option<HitRecord> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut closest = None;
+        for triangle in [[0, 1, 2], [0, 2, 3]] {
+            let [a, b, c] = triangle.map(|k| corners[k]);
+            if let Some(hit) = self.hit_triangle(ray, ray_t, [a, b, c]) {
+                ray_t.max = hit.t;
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    // Möller-Trumbore, with the vertex normals and UVs blended by the
+    // barycentric coordinates of the hit.
+    fn hit_triangle(
+        &self,
+        ray: &Ray,
+        ray_t: &Interval,
+        samples: [(usize, usize); 3],
+    ) -> Option<HitRecord> {
+        let [a, b, c] = samples.map(|(i, j)| self.vertex(i, j));
+        let (e1, e2) = (b - a, c - a);
+        let p = ray.direction.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = ray.origin - a;
+        let beta = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let gamma = ray.direction.dot(q) * inv_det;
+        if gamma < 0.0 || beta + gamma > 1.0 {
+            return None;
+        }
+        let t = e2.dot(q) * inv_det;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let alpha = 1.0 - beta - gamma;
+        let [na, nb, nc] = samples.map(|(i, j)| self.normals[i + self.nx * j]);
+        let shading_normal = (na * alpha + nb * beta + nc * gamma).unit_vector();
+        // the face's own normal, turned up like the vertex normals
+        let face_normal = e1.cross(e2).unit_vector();
+        let face_normal = if face_normal.dot(shading_normal) < 0.0 {
+            -face_normal
+        } else {
+            face_normal
+        };
+        let [(ia, ja), (ib, jb), (ic, jc)] = samples;
+        let grid_u = alpha * ia as f64 + beta * ib as f64 + gamma * ic as f64;
+        let grid_v = alpha * ja as f64 + beta * jb as f64 + gamma * jc as f64;
+        let uv = (grid_u / (self.nx - 1) as f64, grid_v / (self.nz - 1) as f64);
+        // Tangents along x and z over the whole extent, sloped by the
+        // heights as the shading normal is, so that they lie in its plane.
+        let slope_x = -shading_normal.x / shading_normal.y;
+        let slope_z = -shading_normal.z / shading_normal.y;
+        let dpdu = Vec3::new(1.0, slope_x, 0.0) * self.size.x;
+        let dpdv = Vec3::new(0.0, slope_z, 1.0) * self.size.z;
+        Some(
+            HitRecord::new(ray, ray.at(t), face_normal, t, uv, self.material.clone())
+                .with_shading_normal(shading_normal)
+                .with_derivatives(dpdu, dpdv),
+        )
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut ray_t = ray_t;
+        let top = self.levels.len() - 1;
+        self.hit_node(ray, &mut ray_t, top, (0, 0))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_f64_in_range;
+    use crate::material::MaterialKind;
+
+    #[test]
+    fn traversal_finds_the_closest_cell_hit() {
+        // odd sizes, so that mipmap levels round their sizes up
+        let (nx, nz) = (5, 7);
+        let heights = (0..nx * nz)
+            .map(|_| random_f64_in_range(0.0, 1.0) as f32)
+            .collect();
+        let material = Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        });
+        let field = Heightfield::new(
+            nx,
+            nz,
+            heights,
+            Point::new(-2.0, 0.0, -3.0),
+            Vec3::new(4.0, 1.5, 6.0),
+            material,
+        );
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Point::new(
+                random_f64_in_range(-4.0, 4.0),
+                random_f64_in_range(-1.0, 3.0),
+                random_f64_in_range(-5.0, 5.0),
+            );
+            let target = Point::new(
+                random_f64_in_range(-2.0, 2.0),
+                random_f64_in_range(0.0, 1.5),
+                random_f64_in_range(-3.0, 3.0),
+            );
+            let ray = Ray::new(origin, target - origin);
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+
+            let mut brute_t = ray_t;
+            let mut brute = None;
+            for j in 0..nz - 1 {
+                for i in 0..nx - 1 {
+                    if let Some(hit) = field.hit_cell(&ray, &mut brute_t, i, j) {
+                        brute = Some(hit);
+                    }
+                }
+            }
+
+            match (field.hit(&ray, ray_t), brute) {
+                (Some(hit), Some(brute)) => {
+                    assert!((hit.t - brute.t).abs() < 1e-12, "{} vs {}", hit.t, brute.t);
+                    hits += 1;
+                }
+                (None, None) => {}
+                (hit, brute) => panic!(
+                    "traversal {:?} but brute force {:?}",
+                    hit.map(|hit| hit.t),
+                    brute.map(|hit| hit.t)
+                ),
+            }
+        }
+        assert!(hits > 1000, "{hits}");
+    }
+}
@@ -1,15 +1,16 @@
+#[derive(Debug, Clone, Copy)]
 pub(crate) struct Interval {
     pub(crate) min: f64,
     pub(crate) max: f64,
 }
 
 impl Interval {
-    const EMPTY: Self = Self {
+    pub(crate) const EMPTY: Self = Self {
         min: f64::INFINITY,
         max: f64::NEG_INFINITY,
     };
 
-    const UNIVERSE: Self = Self {
+    pub(crate) const UNIVERSE: Self = Self {
         min: f64::NEG_INFINITY,
         max: f64::INFINITY,
     };
@@ -18,6 +19,14 @@ impl Interval {
         Self { min, max }
     }
 
+    // The smallest interval containing both.
+    pub(crate) fn enclosing(a: Self, b: Self) -> Self {
+        Self {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
     pub(crate) fn size(&self) -> f64 {
         self.max - self.min
     }
@@ -30,7 +39,11 @@ impl Interval {
         self.min < t && t < self.max
     }
 
-    pub(crate) fn clamp(&self, n: f64) -> f64 {
-        n.clamp(self.min, self.max)
+    pub(crate) fn expand(&self, delta: f64) -> Self {
+        let padding = delta / 2.0;
+        Self {
+            min: self.min - padding,
+            max: self.max + padding,
+        }
     }
 }
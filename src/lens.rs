@@ -0,0 +1,538 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::common::invalid_data;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+// Lens prescriptions are written in millimeters, the scene is in meters.
+const MM_TO_M: f64 = 0.001;
+
+const PUPIL_INTERVALS: usize = 64;
+const PUPIL_SAMPLES: usize = 16384;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LensElement {
+    // zero for the aperture stop
+    curvature_radius: f64,
+    thickness: f64,
+    // zero for air
+    eta: f64,
+    aperture_radius: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bounds2 {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+}
+
+impl Bounds2 {
+    const EMPTY: Self = Self {
+        min_x: f64::INFINITY,
+        min_y: f64::INFINITY,
+        max_x: f64::NEG_INFINITY,
+        max_y: f64::NEG_INFINITY,
+    };
+
+    fn is_empty(&self) -> bool {
+        self.min_x > self.max_x || self.min_y > self.max_y
+    }
+
+    fn area(&self) -> f64 {
+        if self.is_empty() {
+            0.0
+        } else {
+            (self.max_x - self.min_x) * (self.max_y - self.min_y)
+        }
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        self.min_x <= x && x <= self.max_x && self.min_y <= y && y <= self.max_y
+    }
+
+    fn extend(&mut self, x: f64, y: f64) {
+        self.min_x = self.min_x.min(x);
+        self.min_y = self.min_y.min(y);
+        self.max_x = self.max_x.max(x);
+        self.max_y = self.max_y.max(y);
+    }
+
+    fn expand(&mut self, delta: f64) {
+        self.min_x -= delta;
+        self.min_y -= delta;
+        self.max_x += delta;
+        self.max_y += delta;
+    }
+
+    fn lerp(&self, s: f64, t: f64) -> (f64, f64) {
+        (
+            self.min_x + (self.max_x - self.min_x) * s,
+            self.min_y + (self.max_y - self.min_y) * t,
+        )
+    }
+}
+
+/// A camera lens made of spherical elements, traced ray by ray from the film
+/// out into the scene (the "realistic camera" from pbrt).
+///
+/// Lens space has the film at z = 0 and the elements at negative z, with the
+/// front element farthest from the film. Camera space is the same with z
+/// flipped, so that the camera looks down +z.
+pub struct RealisticLens {
+    elements: Vec<LensElement>,
+    film_diagonal: f64,
+    film_width: f64,
+    film_height: f64,
+    exit_pupil_bounds: Vec<Bounds2>,
+}
+
+impl RealisticLens {
+    /// Loads a lens prescription: one element per line as
+    /// `curvature_radius thickness eta aperture_diameter` in millimeters,
+    /// ordered from the scene side to the film. A radius of zero marks the
+    /// aperture stop and an eta of zero means air. The thickness of the last
+    /// element is the film distance and is replaced when the lens is focused.
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        aperture_diameter: f64,
+        film_diagonal: f64,
+    ) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut values = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            for token in line.split_whitespace() {
+                let value = token.parse::<f64>().map_err(|_| {
+                    invalid_data(&format!("invalid number in lens prescription: {token}"))
+                })?;
+                values.push(value);
+            }
+        }
+        if values.is_empty() || values.len() % 4 != 0 {
+            return Err(invalid_data(
+                "lens prescription must have four values per element",
+            ));
+        }
+
+        let elements = values
+            .chunks(4)
+            .map(|element| {
+                let [curvature_radius, thickness, eta, diameter] =
+                    [element[0], element[1], element[2], element[3]];
+                let diameter = if curvature_radius == 0.0 {
+                    diameter.min(aperture_diameter)
+                } else {
+                    diameter
+                };
+                LensElement {
+                    curvature_radius: curvature_radius * MM_TO_M,
+                    thickness: thickness * MM_TO_M,
+                    eta,
+                    aperture_radius: diameter * MM_TO_M / 2.0,
+                }
+            })
+            .collect();
+
+        Ok(Self::new(elements, film_diagonal))
+    }
+
+    pub(crate) fn new(elements: Vec<LensElement>, film_diagonal: f64) -> Self {
+        let film_diagonal = film_diagonal * MM_TO_M;
+        Self {
+            elements,
+            film_diagonal,
+            film_width: film_diagonal / 2.0_f64.sqrt(),
+            film_height: film_diagonal / 2.0_f64.sqrt(),
+            exit_pupil_bounds: Vec::new(),
+        }
+    }
+
+    /// Fits the film to the image aspect ratio, moves it so that objects at
+    /// `focus_distance` are sharp and precomputes the exit pupil.
+    pub(crate) fn prepare(&mut self, focus_distance: f64, aspect_ratio: f64) {
+        self.film_height = self.film_diagonal / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+        self.film_width = self.film_height * aspect_ratio;
+
+        let film_distance = self.focus_thick_lens(focus_distance);
+        if let Some(last) = self.elements.last_mut() {
+            last.thickness = film_distance;
+        }
+
+        self.exit_pupil_bounds = (0..PUPIL_INTERVALS)
+            .map(|i| {
+                let r0 = i as f64 / PUPIL_INTERVALS as f64 * self.film_diagonal / 2.0;
+                let r1 = (i + 1) as f64 / PUPIL_INTERVALS as f64 * self.film_diagonal / 2.0;
+                self.bound_exit_pupil(r0, r1)
+            })
+            .collect();
+    }
+
+    /// Generates a camera-space ray through the lens for a point on the film
+    /// given in [0, 1]^2 image coordinates (y pointing down) and a lens
+    /// sample in [0, 1]^2. Returns the ray and its weight, or `None` if the
+    /// ray is blocked inside the lens.
+    pub(crate) fn generate_ray(&self, film: (f64, f64), lens: (f64, f64)) -> Option<(Ray, f64)> {
+        // The lens flips the image, so the top left of the image is exposed
+        // by the bottom right of the film.
+        let film_x = (0.5 - film.0) * self.film_width;
+        let film_y = (film.1 - 0.5) * self.film_height;
+        let p_film = Vec3::new(film_x, film_y, 0.0);
+
+        let (p_rear, bounds_area) = self.sample_exit_pupil(film_x, film_y, lens)?;
+        let ray_film = Ray::new(p_film, p_rear - p_film);
+        let ray = self.trace_from_film(&ray_film)?;
+
+        let cos_theta = ray_film.direction.unit_vector().z;
+        let cos4_theta = cos_theta.powi(4);
+        // Weights are relative to the pupil seen from the film center, which
+        // a lens that lets no light through there leaves without a scale.
+        let center_area = self.exit_pupil_bounds[0].area();
+        if center_area == 0.0 {
+            return None;
+        }
+        let weight = cos4_theta * bounds_area / center_area;
+        Some((ray, weight))
+    }
+
+    fn rear_z(&self) -> f64 {
+        self.elements.last().map_or(0.0, |e| e.thickness)
+    }
+
+    fn front_z(&self) -> f64 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear_aperture_radius(&self) -> f64 {
+        self.elements.last().map_or(0.0, |e| e.aperture_radius)
+    }
+
+    fn trace_from_film(&self, ray: &Ray) -> Option<Ray> {
+        let mut ray = to_lens_space(ray);
+        let mut element_z = 0.0;
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            element_z -= element.thickness;
+
+            let is_stop = element.curvature_radius == 0.0;
+            let (t, normal) = if is_stop {
+                (
+                    (element_z - ray.origin.z) / ray.direction.z,
+                    Vec3::new(0.0, 0.0, 0.0),
+                )
+            } else {
+                let z_center = element_z + element.curvature_radius;
+                intersect_spherical_element(element.curvature_radius, z_center, &ray)?
+            };
+            if !t.is_finite() || t < 0.0 {
+                return None;
+            }
+
+            let p_hit = ray.at(t);
+            if p_hit.x * p_hit.x + p_hit.y * p_hit.y > element.aperture_radius.powi(2) {
+                return None;
+            }
+            ray.origin = p_hit;
+
+            if !is_stop {
+                let eta_i = air_if_zero(element.eta);
+                let eta_t = if i > 0 {
+                    air_if_zero(self.elements[i - 1].eta)
+                } else {
+                    1.0
+                };
+                ray.direction = refract(-ray.direction.unit_vector(), normal, eta_i / eta_t)?;
+            }
+        }
+        Some(to_lens_space(&ray))
+    }
+
+    fn trace_from_scene(&self, ray: &Ray) -> Option<Ray> {
+        let mut ray = to_lens_space(ray);
+        let mut element_z = -self.front_z();
+        for i in 0..self.elements.len() {
+            let element = &self.elements[i];
+
+            let is_stop = element.curvature_radius == 0.0;
+            let (t, normal) = if is_stop {
+                (
+                    (element_z - ray.origin.z) / ray.direction.z,
+                    Vec3::new(0.0, 0.0, 0.0),
+                )
+            } else {
+                let z_center = element_z + element.curvature_radius;
+                intersect_spherical_element(element.curvature_radius, z_center, &ray)?
+            };
+            if !t.is_finite() || t < 0.0 {
+                return None;
+            }
+
+            let p_hit = ray.at(t);
+            if p_hit.x * p_hit.x + p_hit.y * p_hit.y > element.aperture_radius.powi(2) {
+                return None;
+            }
+            ray.origin = p_hit;
+
+            if !is_stop {
+                let eta_i = if i == 0 {
+                    1.0
+                } else {
+                    air_if_zero(self.elements[i - 1].eta)
+                };
+                let eta_t = air_if_zero(element.eta);
+                ray.direction = refract(-ray.direction.unit_vector(), normal, eta_i / eta_t)?;
+            }
+            element_z += element.thickness;
+        }
+        Some(to_lens_space(&ray))
+    }
+
+    // Returns the z of the principal plane and the focal point on each side
+    // of the lens, found by tracing rays parallel to the axis.
+    fn thick_lens_approximation(&self) -> Option<([f64; 2], [f64; 2])> {
+        let x = 0.001 * self.film_diagonal;
+
+        let ray_scene = Ray::new(
+            Vec3::new(x, 0.0, self.front_z() + 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        let ray_film = self.trace_from_scene(&ray_scene)?;
+        let (pz0, fz0) = cardinal_points(&ray_scene, &ray_film);
+
+        let ray_film = Ray::new(
+            Vec3::new(x, 0.0, self.rear_z() - 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let ray_scene = self.trace_from_film(&ray_film)?;
+        let (pz1, fz1) = cardinal_points(&ray_film, &ray_scene);
+
+        Some(([pz0, pz1], [fz0, fz1]))
+    }
+
+    fn focus_thick_lens(&self, focus_distance: f64) -> f64 {
+        let Some((pz, fz)) = self.thick_lens_approximation() else {
+            return self.rear_z();
+        };
+        let f = fz[0] - pz[0];
+        let z = -focus_distance;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * f - pz[0]);
+        if c <= 0.0 {
+            // focus distance is closer than the lens can focus
+            return self.rear_z();
+        }
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        self.rear_z() + delta
+    }
+
+    fn bound_exit_pupil(&self, film_x0: f64, film_x1: f64) -> Bounds2 {
+        let mut pupil_bounds = Bounds2::EMPTY;
+
+        let rear_radius = 1.5 * self.rear_aperture_radius();
+        let rear_bounds = Bounds2 {
+            min_x: -rear_radius,
+            min_y: -rear_radius,
+            max_x: rear_radius,
+            max_y: rear_radius,
+        };
+
+        for i in 0..PUPIL_SAMPLES {
+            let film_x = film_x0 + (film_x1 - film_x0) * (i as f64 + 0.5) / PUPIL_SAMPLES as f64;
+            let p_film = Vec3::new(film_x, 0.0, 0.0);
+            let (rear_x, rear_y) = rear_bounds.lerp(radical_inverse(2, i), radical_inverse(3, i));
+            let p_rear = Vec3::new(rear_x, rear_y, self.rear_z());
+
+            if pupil_bounds.contains(rear_x, rear_y)
+                || self
+                    .trace_from_film(&Ray::new(p_film, p_rear - p_film))
+                    .is_some()
+            {
+                pupil_bounds.extend(rear_x, rear_y);
+            }
+        }
+
+        if !pupil_bounds.is_empty() {
+            let diagonal = 2.0 * rear_radius * 2.0_f64.sqrt();
+            pupil_bounds.expand(2.0 * diagonal / (PUPIL_SAMPLES as f64).sqrt());
+        }
+        pupil_bounds
+    }
+
+    // Samples a point on the rear element that is likely to get through the
+    // lens, using the bounds computed for the film point's distance from the
+    // optical axis rotated around to its actual angle.
+    fn sample_exit_pupil(&self, film_x: f64, film_y: f64, lens: (f64, f64)) -> Option<(Vec3, f64)> {
+        let r_film = (film_x * film_x + film_y * film_y).sqrt();
+        let index = (r_film / (self.film_diagonal / 2.0) * PUPIL_INTERVALS as f64) as usize;
+        let bounds = self.exit_pupil_bounds.get(index.min(PUPIL_INTERVALS - 1))?;
+        if bounds.is_empty() {
+            return None;
+        }
+
+        let (lens_x, lens_y) = bounds.lerp(lens.0, lens.1);
+        let (sin_theta, cos_theta) = if r_film != 0.0 {
+            (film_y / r_film, film_x / r_film)
+        } else {
+            (0.0, 1.0)
+        };
+        let p_rear = Vec3::new(
+            cos_theta * lens_x - sin_theta * lens_y,
+            sin_theta * lens_x + cos_theta * lens_y,
+            self.rear_z(),
+        );
+        Some((p_rear, bounds.area()))
+    }
+}
+
+fn air_if_zero(eta: f64) -> f64 {
+    if eta == 0.0 {
+        1.0
+    } else {
+        eta
+    }
+}
+
+fn to_lens_space(ray: &Ray) -> Ray {
+    Ray::new(
+        Vec3::new(ray.origin.x, ray.origin.y, -ray.origin.z),
+        Vec3::new(ray.direction.x, ray.direction.y, -ray.direction.z),
+    )
+}
+
+fn intersect_spherical_element(radius: f64, z_center: f64, ray: &Ray) -> Option<(f64, Vec3)> {
+    let o = ray.origin - Vec3::new(0.0, 0.0, z_center);
+    let d = ray.direction;
+    let a = d.length_squared();
+    let b = 2.0 * d.dot(o);
+    let c = o.length_squared() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    // numerically stable form of the quadratic formula
+    let q = if b < 0.0 {
+        -0.5 * (b - discriminant.sqrt())
+    } else {
+        -0.5 * (b + discriminant.sqrt())
+    };
+    let (t0, t1) = (q / a, c / q);
+    let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+
+    // Which of the two intersections is on the lens depends on the ray
+    // direction and on whether the element is convex or concave.
+    let use_closer_t = (d.z > 0.0) ^ (radius < 0.0);
+    let t = if use_closer_t { t0 } else { t1 };
+    if t < 0.0 {
+        return None;
+    }
+
+    let normal = (o + d * t).unit_vector();
+    let normal = if normal.dot(-d) < 0.0 {
+        -normal
+    } else {
+        normal
+    };
+    Some((t, normal))
+}
+
+// `wi` points away from the surface and `normal` is on its side.
+fn refract(wi: Vec3, normal: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = normal.dot(wi);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(eta * -wi + (eta * cos_theta_i - cos_theta_t) * normal)
+}
+
+fn cardinal_points(ray_in: &Ray, ray_out: &Ray) -> (f64, f64) {
+    let tf = -ray_out.origin.x / ray_out.direction.x;
+    let fz = -ray_out.at(tf).z;
+    let tp = (ray_in.origin.x - ray_out.origin.x) / ray_out.direction.x;
+    let pz = -ray_out.at(tp).z;
+    (pz, fz)
+}
+
+fn radical_inverse(base: usize, mut i: usize) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    while i > 0 {
+        result += (i % base) as f64 * inv;
+        i /= base;
+        inv *= inv_base;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A biconvex singlet, 50 mm radii and 5 mm thick, stopped down to a
+    // 10 mm aperture, in front of a 35 mm film diagonal.
+    fn singlet() -> RealisticLens {
+        let element = |curvature_radius: f64, thickness: f64, eta: f64| LensElement {
+            curvature_radius: curvature_radius * MM_TO_M,
+            thickness: thickness * MM_TO_M,
+            eta,
+            aperture_radius: 5.0 * MM_TO_M,
+        };
+        RealisticLens::new(
+            vec![element(50.0, 5.0, 1.5), element(-50.0, 50.0, 0.0)],
+            35.0,
+        )
+    }
+
+    #[test]
+    fn focusing_at_infinity_puts_the_film_at_the_back_focal_distance() {
+        // thick lens equations for the focal length and the rear principal
+        // plane, which lies inside the lens before its rear vertex
+        let (n, r, d) = (1.5, 50.0, 5.0);
+        let f = 1.0 / ((n - 1.0) * (2.0 / r - (n - 1.0) * d / (n * r * r)));
+        let back_focal_distance = f - f * (n - 1.0) * d / (n * r);
+
+        let mut lens = singlet();
+        lens.prepare(1e6, 1.0);
+        let film_distance = lens.rear_z() / MM_TO_M;
+        assert!(
+            (film_distance - back_focal_distance).abs() < 0.01,
+            "{film_distance} mm, expected {back_focal_distance} mm"
+        );
+    }
+
+    #[test]
+    fn rays_from_a_film_point_meet_on_the_focal_plane() {
+        let focus_distance = 1.0;
+        let mut lens = singlet();
+        lens.prepare(focus_distance, 1.0);
+
+        for film in [(0.5, 0.5), (0.3, 0.6)] {
+            let mut points = Vec::new();
+            for lens_sample in [(0.5, 0.5), (0.4, 0.5), (0.6, 0.5), (0.5, 0.4), (0.5, 0.6)] {
+                let (ray, weight) = lens.generate_ray(film, lens_sample).unwrap();
+                assert!(weight > 0.0);
+                assert!(ray.direction.z > 0.0);
+                let t = (focus_distance - ray.origin.z) / ray.direction.z;
+                points.push(ray.at(t));
+            }
+            if film == (0.5, 0.5) {
+                assert!(points[0].x.abs() < 1e-6 && points[0].y.abs() < 1e-6);
+            }
+            // a singlet's curved field blurs points off the axis a little
+            let tolerance = if film == (0.5, 0.5) { 1e-4 } else { 1e-3 };
+            for p in &points[1..] {
+                assert!(
+                    (*p - points[0]).length() < tolerance,
+                    "{p:?} vs {:?}",
+                    points[0]
+                );
+            }
+        }
+    }
+}
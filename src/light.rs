@@ -0,0 +1,686 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::{invalid_data, random_f64, Point};
+use crate::interval::Interval;
+use crate::lighttree::LightBounds;
+use crate::material::{Material, MaterialKind};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// The shape of the light from a light source. Apart from triangles, lights
+/// are a single point or direction, which no ray can hit by chance, and
+/// only reach surfaces through the shadow rays cast towards them.
+#[derive(Clone)]
+pub(crate) enum LightKind {
+    // `intensity` in every direction
+    Point {
+        position: Point,
+    },
+    // `intensity` inside a cone around `direction` of half-angle
+    // `cone_angle`, fading out smoothly over the outer `falloff` degrees
+    Spot {
+        position: Point,
+        direction: Vec3,
+        cone_angle: f64,
+        falloff: f64,
+    },
+    // parallel light travelling along `direction`, with `intensity` the
+    // irradiance on a surface facing it
+    Directional {
+        direction: Vec3,
+    },
+    // `intensity` times the candela of an IES profile, with its nadir, the
+    // vertical angle 0, along `direction`
+    Photometric {
+        position: Point,
+        direction: Vec3,
+        profile: Arc<IesProfile>,
+    },
+    // `intensity` the radiance given off the front, where the corners
+    // appear counterclockwise, as on emissive geometry
+    Triangle {
+        vertices: [Point; 3],
+    },
+}
+
+#[derive(Clone)]
+pub(crate) struct Light {
+    pub(crate) kind: LightKind,
+    pub(crate) intensity: Vec3,
+    /// To camera rays, lights at a point show up as a sphere of this radius,
+    /// and directional lights as a disk of this angular radius, in degrees.
+    /// `None` keeps the light invisible. Triangles are always visible.
+    pub(crate) visible_size: Option<f64>,
+}
+
+/// Light reaching a point from a light source.
+pub(crate) struct LightSample {
+    // unit vector towards the light
+    pub(crate) direction: Vec3,
+    // to the light, infinite for directional lights
+    pub(crate) distance: f64,
+    // the radiance from the light, or for a delta light, the irradiance it
+    // gives a surface facing it
+    pub(crate) radiance: Vec3,
+    // the density, per unit solid angle, of sampling the direction, or none
+    // for a delta light
+    pub(crate) pdf: Option<f64>,
+}
+
+impl Light {
+    pub(crate) fn point(position: Point, intensity: Vec3) -> Self {
+        Self::new(LightKind::Point { position }, intensity)
+    }
+
+    pub(crate) fn spot(
+        position: Point,
+        direction: Vec3,
+        cone_angle: f64,
+        falloff: f64,
+        intensity: Vec3,
+    ) -> Self {
+        let kind = LightKind::Spot {
+            position,
+            direction: direction.unit_vector(),
+            cone_angle,
+            falloff,
+        };
+        Self::new(kind, intensity)
+    }
+
+    pub(crate) fn directional(direction: Vec3, irradiance: Vec3) -> Self {
+        let kind = LightKind::Directional {
+            direction: direction.unit_vector(),
+        };
+        Self::new(kind, irradiance)
+    }
+
+    pub(crate) fn photometric(
+        position: Point,
+        direction: Vec3,
+        profile: Arc<IesProfile>,
+        intensity: Vec3,
+    ) -> Self {
+        let kind = LightKind::Photometric {
+            position,
+            direction: direction.unit_vector(),
+            profile,
+        };
+        Self::new(kind, intensity)
+    }
+
+    pub(crate) fn triangle(vertices: [Point; 3], radiance: Vec3) -> Self {
+        Self::new(LightKind::Triangle { vertices }, radiance)
+    }
+
+    /// The lights standing in for the `triangles` of a surface made of
+    /// `material`, or `None` if the material isn't a diffuse light.
+    pub(crate) fn emissive_triangles(
+        material: &Material,
+        triangles: impl IntoIterator<Item = [Point; 3]>,
+    ) -> Option<Vec<Self>> {
+        match material.kind {
+            MaterialKind::DiffuseLight => Some(
+                triangles
+                    .into_iter()
+                    .map(|vertices| Self::triangle(vertices, material.albedo))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn new(kind: LightKind, intensity: Vec3) -> Self {
+        Self {
+            kind,
+            intensity,
+            visible_size: None,
+        }
+    }
+
+    pub(crate) fn with_visible_size(mut self, size: f64) -> Self {
+        self.visible_size = Some(size);
+        self
+    }
+
+    /// The light arriving at `p`, before anything gets in its way.
+    pub(crate) fn sample(&self, p: Point) -> Option<LightSample> {
+        if let LightKind::Triangle {
+            vertices: [a, b, c],
+        } = self.kind
+        {
+            // uniformly over the area
+            let s = random_f64().sqrt();
+            let (b0, b1) = (1.0 - s, random_f64() * s);
+            let point = a * b0 + b * b1 + c * (1.0 - b0 - b1);
+            let to_light = point - p;
+            let distance = to_light.length();
+            let direction = to_light / distance;
+            let pdf = self.pdf(&Ray::new(p, direction), distance);
+            return (pdf > 0.0).then_some(LightSample {
+                direction,
+                distance,
+                radiance: self.intensity,
+                pdf: Some(pdf),
+            });
+        }
+
+        let (direction, distance) = match self.kind {
+            LightKind::Directional { direction } => (-direction, f64::INFINITY),
+            LightKind::Point { position }
+            | LightKind::Spot { position, .. }
+            | LightKind::Photometric { position, .. } => {
+                let to_light = position - p;
+                let distance = to_light.length();
+                (to_light / distance, distance)
+            }
+            LightKind::Triangle { .. } => unreachable!(),
+        };
+        let radiance = match self.kind {
+            LightKind::Directional { .. } => self.intensity,
+            _ => self.intensity_towards(-direction) / (distance * distance),
+        };
+        if radiance.length_squared() <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance,
+            pdf: None,
+        })
+    }
+
+    /// Where `ray` hits a triangle light, within `ray_t`. Other lights cannot
+    /// be hit.
+    pub(crate) fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<f64> {
+        let LightKind::Triangle {
+            vertices: [a, b, c],
+        } = self.kind
+        else {
+            return None;
+        };
+        // Möller-Trumbore
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let h = ray.direction.cross(edge2);
+        let det = edge1.dot(h);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = ray.origin - a;
+        let b1 = s.dot(h) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let b2 = ray.direction.dot(q) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inv_det;
+        ray_t.surrounds(t).then_some(t)
+    }
+
+    /// The radiance a ray that hit the light sees.
+    pub(crate) fn emitted(&self, ray: &Ray) -> Vec3 {
+        match self.kind {
+            LightKind::Triangle { vertices } if ray.direction.dot(normal(vertices)) < 0.0 => {
+                self.intensity
+            }
+            _ => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// The density per unit solid angle with which `sample`, at the origin
+    /// of `ray`, picks the direction that hits the light at `t`.
+    pub(crate) fn pdf(&self, ray: &Ray, t: f64) -> f64 {
+        let LightKind::Triangle { vertices } = self.kind else {
+            return 0.0;
+        };
+        let [a, b, c] = vertices;
+        let area = 0.5 * (b - a).cross(c - a).length();
+        let distance = t * ray.direction.length();
+        let cos_theta = -ray.direction.unit_vector().dot(normal(vertices));
+        if cos_theta <= 0.0 || area <= 0.0 {
+            return 0.0;
+        }
+        distance * distance / (area * cos_theta)
+    }
+
+    /// Where the light is, which way it shines and how much, for choosing
+    /// among many lights. `None` for directional lights, which are
+    /// everywhere.
+    pub(crate) fn bounds(&self) -> Option<LightBounds> {
+        let max = |v: Vec3| v.x.max(v.y).max(v.z);
+        let point = |position: Point| Aabb::from_points(position, position);
+        match &self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position } => Some(LightBounds::new(
+                point(*position),
+                4.0 * PI * max(self.intensity),
+                Vec3::new(0.0, 0.0, 1.0),
+                -1.0,
+                0.0,
+            )),
+            LightKind::Spot {
+                position,
+                direction,
+                cone_angle,
+                falloff,
+            } => Some(LightBounds::new(
+                point(*position),
+                4.0 * PI * max(self.intensity),
+                *direction,
+                (cone_angle - falloff).max(0.0).to_radians().cos(),
+                falloff.min(*cone_angle).to_radians().cos(),
+            )),
+            LightKind::Photometric {
+                position, profile, ..
+            } => Some(LightBounds::new(
+                point(*position),
+                4.0 * PI * max(self.intensity) * profile.max_candela(),
+                Vec3::new(0.0, 0.0, 1.0),
+                -1.0,
+                0.0,
+            )),
+            LightKind::Triangle { vertices } => {
+                let [a, b, c] = *vertices;
+                let area = 0.5 * (b - a).cross(c - a).length();
+                let bbox = Aabb::enclosing(Aabb::from_points(a, b), Aabb::from_points(c, c));
+                Some(LightBounds::new(
+                    bbox,
+                    PI * area * max(self.intensity),
+                    normal(*vertices),
+                    1.0,
+                    0.0,
+                ))
+            }
+        }
+    }
+
+    /// The radiance a camera ray sees of a visible light, if it reaches the
+    /// light before parameter `t_max`.
+    pub(crate) fn visible_radiance(&self, ray: &Ray, t_max: f64) -> Option<Vec3> {
+        let size = self.visible_size?;
+        let (position, direction) = match self.kind {
+            LightKind::Directional { direction } => {
+                // a disk in the sky, giving off the light's irradiance
+                let cos_radius = size.to_radians().cos();
+                let cos_angle = -ray.direction.unit_vector().dot(direction);
+                return (t_max.is_infinite() && cos_angle >= cos_radius)
+                    .then(|| self.intensity / (2.0 * PI * (1.0 - cos_radius)));
+            }
+            LightKind::Point { position }
+            | LightKind::Spot { position, .. }
+            | LightKind::Photometric { position, .. } => (position, ray.direction),
+            LightKind::Triangle { .. } => return None,
+        };
+
+        // a sphere, showing the intensity towards the ray origin over its
+        // projected area
+        let oc = position - ray.origin;
+        let a = direction.length_squared();
+        let h = direction.dot(oc);
+        let discriminant = h * h - a * (oc.length_squared() - size * size);
+        if discriminant < 0.0 {
+            return None;
+        }
+        let t = (h - discriminant.sqrt()) / a;
+        if t <= 0.001 || t >= t_max {
+            return None;
+        }
+        let towards_camera = (ray.origin - position).unit_vector();
+        Some(self.intensity_towards(towards_camera) / (PI * size * size))
+    }
+
+    // The radiant intensity from a light at a point towards `w`.
+    fn intensity_towards(&self, w: Vec3) -> Vec3 {
+        match &self.kind {
+            LightKind::Point { .. } => self.intensity,
+            LightKind::Spot {
+                direction,
+                cone_angle,
+                falloff,
+                ..
+            } => {
+                let cos_theta = w.unit_vector().dot(*direction);
+                let cos_end = cone_angle.to_radians().cos();
+                let cos_start = (cone_angle - falloff).max(0.0).to_radians().cos();
+                self.intensity * smoothstep(cos_end, cos_start, cos_theta)
+            }
+            LightKind::Photometric {
+                direction, profile, ..
+            } => {
+                // horizontal angle 0 towards +x, or +z for lights pointing
+                // along the x axis
+                let tangent = if direction.x.abs() < 0.999 {
+                    Vec3::new(1.0, 0.0, 0.0)
+                } else {
+                    Vec3::new(0.0, 0.0, 1.0)
+                };
+                let local = Onb::from_normal_tangent(*direction, tangent).to_local(w.unit_vector());
+                let theta = local.z.clamp(-1.0, 1.0).acos().to_degrees();
+                let phi = local.y.atan2(local.x).to_degrees().rem_euclid(360.0);
+                self.intensity * profile.candela(theta, phi)
+            }
+            LightKind::Directional { .. } | LightKind::Triangle { .. } => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+fn normal([a, b, c]: [Point; 3]) -> Vec3 {
+    (b - a).cross(c - a).unit_vector()
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge0 >= edge1 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// The distribution of light from a luminaire, as measured in an IES LM-63
+/// photometric data file, with type C photometry.
+pub(crate) struct IesProfile {
+    // in degrees, ascending
+    vertical_angles: Vec<f64>,
+    horizontal_angles: Vec<f64>,
+    // rows for each horizontal angle, over the vertical angles
+    candela: Vec<Vec<f64>>,
+}
+
+impl IesProfile {
+    pub(crate) fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .map(str::trim)
+            .find(|line| line.starts_with("TILT="))
+            .ok_or_else(|| invalid_data("no TILT line"))?;
+
+        let numbers: Vec<f64> = lines
+            .flat_map(|line| line.split([' ', '\t', ',']))
+            .filter(|field| !field.is_empty())
+            .map(|field| field.parse().map_err(|_| invalid_data("bad number")))
+            .collect::<io::Result<_>>()?;
+        let mut numbers = numbers.into_iter();
+        let mut next = || {
+            numbers
+                .next()
+                .ok_or_else(|| invalid_data("file ends early"))
+        };
+
+        if tilt == "TILT=INCLUDE" {
+            // lamp to luminaire geometry, then pairs of angles and factors,
+            // which only matter for lamps that are not kept upright
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        if photometric_type != 1.0 {
+            return Err(invalid_data("only type C photometry is supported"));
+        }
+        // units and dimensions of the luminous opening
+        for _ in 0..4 {
+            next()?;
+        }
+        let ballast_factor = next()?;
+        let _reserved = next()?;
+        let _watts = next()?;
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid_data("no angles"));
+        }
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<_>>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<_>>>()?;
+        let candela = (0..horizontal_count)
+            .map(|_| {
+                (0..vertical_count)
+                    .map(|_| Ok(next()? * multiplier * ballast_factor))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    pub(crate) fn max_candela(&self) -> f64 {
+        self.candela
+            .iter()
+            .flatten()
+            .fold(0.0, |max: f64, &candela| max.max(candela))
+    }
+
+    /// The luminous intensity at vertical angle `theta` from the nadir and
+    /// horizontal angle `phi`, in degrees, interpolated between the
+    /// measurements and folded by the symmetry the file implies.
+    pub(crate) fn candela(&self, theta: f64, phi: f64) -> f64 {
+        let last = *self.horizontal_angles.last().unwrap();
+        let phi = if last <= 0.0 {
+            0.0
+        } else if last <= 90.0 {
+            // the same in each quadrant
+            let phi = if phi > 180.0 { 360.0 - phi } else { phi };
+            if phi > 90.0 {
+                180.0 - phi
+            } else {
+                phi
+            }
+        } else if last <= 180.0 && phi > 180.0 {
+            // mirrored about the plane through 0 and 180 degrees
+            360.0 - phi
+        } else {
+            phi
+        };
+
+        let Some((i0, i1, s)) = bracket(&self.vertical_angles, theta) else {
+            return 0.0;
+        };
+        let row = |j: usize| self.candela[j][i0] * (1.0 - s) + self.candela[j][i1] * s;
+        match bracket(&self.horizontal_angles, phi) {
+            Some((j0, j1, t)) => row(j0) * (1.0 - t) + row(j1) * t,
+            None => row(0),
+        }
+    }
+}
+
+// The indices of the ascending `angles` on either side of `x`, and how far
+// it is from the first to the second, or `None` outside them all.
+fn bracket(angles: &[f64], x: f64) -> Option<(usize, usize, f64)> {
+    if angles.len() == 1 {
+        return (x == angles[0]).then_some((0, 0, 0.0));
+    }
+    if x < angles[0] || x > angles[angles.len() - 1] {
+        return None;
+    }
+    let i = (angles.partition_point(|&a| a <= x).max(1) - 1).min(angles.len() - 2);
+    let width = angles[i + 1] - angles[i];
+    let s = if width > 0.0 {
+        (x - angles[i]) / width
+    } else {
+        0.0
+    };
+    Some((i, i + 1, s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::temp_file;
+
+    // Type C photometry over vertical angles 0, 45 and 90 and horizontal
+    // angles 0 and 90, repeated in every quadrant, with a multiplier and
+    // ballast factor that cancel out.
+    const IES: &str = "IESNA:LM-63-2002\n\
+                       [TEST] two planes\n\
+                       TILT=NONE\n\
+                       1 1000 2 3 2 1 1 0.5 0.5 0\n\
+                       0.5 1 100\n\
+                       0 45 90\n\
+                       0 90\n\
+                       100 50 0\n\
+                       200 100 0\n";
+
+    fn load(name: &str, text: &str) -> io::Result<IesProfile> {
+        let path = temp_file(name, text.as_bytes());
+        let profile = IesProfile::load(&path);
+        fs::remove_file(path).unwrap();
+        profile
+    }
+
+    #[test]
+    fn ies_profiles_interpolate_and_fold() {
+        let tilted = IES.replace("TILT=NONE\n", "TILT=INCLUDE\n1\n2\n0 90\n1, 0.5\n");
+        for profile in [load("plain.ies", IES), load("tilted.ies", &tilted)] {
+            let profile = profile.unwrap();
+            assert_eq!(profile.max_candela(), 200.0);
+            assert_eq!(profile.candela(0.0, 0.0), 100.0);
+            assert_eq!(profile.candela(22.5, 0.0), 75.0);
+            assert_eq!(profile.candela(45.0, 45.0), 75.0);
+            // folded back into the first quadrant
+            assert_eq!(profile.candela(45.0, 180.0), 50.0);
+            assert_eq!(profile.candela(45.0, 270.0), 100.0);
+            assert_eq!(profile.candela(120.0, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn invalid_ies_files_are_errors() {
+        for (name, text) in [
+            ("untilted.ies", IES.replace("TILT=NONE\n", "")),
+            ("type_b.ies", IES.replace(" 3 2 1 1 ", " 3 2 2 1 ")),
+            ("short.ies", IES.replace("200 100 0\n", "200\n")),
+            ("number.ies", IES.replace("100 50 0", "100 fifty 0")),
+        ] {
+            let error = load(name, &text).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{name}");
+        }
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{a} vs {b}");
+    }
+
+    #[test]
+    fn delta_lights_fall_off_with_distance_and_angle() {
+        let origin = Point::new(0.0, 0.0, 0.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let white = |value: f64| Vec3::new(value, value, value);
+
+        let point = Light::point(Point::new(0.0, 2.0, 0.0), white(4.0));
+        let sample = point.sample(origin).unwrap();
+        assert_close(sample.direction, up);
+        assert_eq!(sample.distance, 2.0);
+        assert_close(sample.radiance, white(1.0));
+        assert!(sample.pdf.is_none());
+
+        // full strength inside 20 degrees, none beyond 30
+        let spot = Light::spot(Point::new(0.0, 2.0, 0.0), -up, 30.0, 10.0, white(4.0));
+        let at_angle = |degrees: f64| Point::new(2.0 * degrees.to_radians().tan(), 0.0, 0.0);
+        // intensity times squared distance
+        let intensity = |p: Point| {
+            spot.sample(p).map_or(0.0, |sample| {
+                sample.radiance.x * sample.distance * sample.distance
+            })
+        };
+        assert!((intensity(at_angle(10.0)) - 4.0).abs() < 1e-9);
+        let partial = intensity(at_angle(25.0));
+        assert!(partial > 0.0 && partial < 4.0);
+        assert!(spot.sample(at_angle(35.0)).is_none());
+
+        let directional = Light::directional(-up, white(3.0));
+        let sample = directional.sample(origin).unwrap();
+        assert_close(sample.direction, up);
+        assert!(sample.distance.is_infinite());
+        assert_close(sample.radiance, white(3.0));
+        assert!(directional.bounds().is_none());
+
+        // pointing down, so straight below is the profile's nadir
+        let profile = Arc::new(load("photometric.ies", IES).unwrap());
+        let photometric = Light::photometric(Point::new(0.0, 2.0, 0.0), -up, profile, white(0.01));
+        assert_close(photometric.sample(origin).unwrap().radiance, white(0.25));
+        assert!(photometric.sample(Point::new(0.0, 3.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn visible_lights_show_up_as_spheres_and_disks() {
+        let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let point = Light::point(Point::new(0.0, 2.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        assert!(point.visible_radiance(&ray, f64::INFINITY).is_none());
+        let visible = point.with_visible_size(0.1);
+        assert!(visible.visible_radiance(&ray, f64::INFINITY).is_some());
+        assert!(visible.visible_radiance(&ray, 1.0).is_none());
+
+        let sun = Light::directional(Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 1.0))
+            .with_visible_size(1.0);
+        assert!(sun.visible_radiance(&ray, f64::INFINITY).is_some());
+        let aside = Ray::new(ray.origin, Vec3::new(0.1, 1.0, 0.0));
+        assert!(sun.visible_radiance(&aside, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn triangle_samples_agree_with_hits_and_pdf() {
+        let vertices = [
+            Point::new(-1.0, 2.0, -1.0),
+            Point::new(1.0, 2.0, 0.0),
+            Point::new(-1.0, 2.0, 1.0),
+        ];
+        let triangle = Light::triangle(vertices, Vec3::new(2.0, 2.0, 2.0));
+        let p = Point::new(0.3, 0.0, 0.2);
+
+        // the mean of 1 / pdf is the solid angle the triangle covers
+        let n = 20000;
+        let mut solid_angle = 0.0;
+        for _ in 0..n {
+            let sample = triangle.sample(p).unwrap();
+            let ray = Ray::new(p, sample.direction);
+            let t = triangle
+                .hit(&ray, Interval::new(0.0, f64::INFINITY))
+                .unwrap();
+            assert!((t - sample.distance).abs() < 1e-9);
+            assert_close(triangle.emitted(&ray), sample.radiance);
+            assert!((triangle.pdf(&ray, t) - sample.pdf.unwrap()).abs() < 1e-9);
+            solid_angle += 1.0 / sample.pdf.unwrap() / n as f64;
+        }
+        // Van Oosterom and Strackee
+        let [a, b, c] = vertices.map(|v| v - p);
+        let (la, lb, lc) = (a.length(), b.length(), c.length());
+        let expected = 2.0
+            * a.dot(b.cross(c))
+                .abs()
+                .atan2(la * lb * lc + a.dot(b) * lc + a.dot(c) * lb + b.dot(c) * la);
+        assert!(
+            (solid_angle - expected).abs() < 0.01 * expected,
+            "{solid_angle} vs {expected}"
+        );
+
+        // only the front, facing down here, gives off light
+        assert!(triangle.sample(Point::new(0.0, 3.0, 0.0)).is_none());
+    }
+}
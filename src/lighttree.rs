@@ -0,0 +1,589 @@
+use std::f64::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::common::{random_f64, Point};
+use crate::distribution::Distribution1D;
+use crate::interval::Interval;
+use crate::light::Light;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// How to choose the one light that next event estimation samples at each
+/// shading point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightSampling {
+    // every light alike
+    Uniform,
+    // in proportion to their power
+    Power,
+    // in proportion to an estimate of their light at the point, from a tree
+    // of clusters of lights
+    Tree,
+}
+
+/// Bounds on where lights are, which way and how much they shine. Light
+/// leaves their surfaces within `cos_theta_o` of the direction `w`, and from
+/// each point within a further `cos_theta_e`, after Conty Estevez and Kulla,
+/// "Importance Sampling of Many Lights with Adaptive Tree Splitting", as in
+/// pbrt-v4.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LightBounds {
+    bbox: Aabb,
+    phi: f64,
+    w: Vec3,
+    cos_theta_o: f64,
+    cos_theta_e: f64,
+}
+
+impl LightBounds {
+    pub(crate) fn new(bbox: Aabb, phi: f64, w: Vec3, cos_theta_o: f64, cos_theta_e: f64) -> Self {
+        Self {
+            bbox,
+            phi,
+            w: w.unit_vector(),
+            cos_theta_o,
+            cos_theta_e,
+        }
+    }
+
+    fn union(a: &Self, b: &Self) -> Self {
+        if a.phi <= 0.0 {
+            return *b;
+        }
+        if b.phi <= 0.0 {
+            return *a;
+        }
+        let (w, cos_theta_o) = cone_union((a.w, a.cos_theta_o), (b.w, b.cos_theta_o));
+        Self {
+            bbox: Aabb::enclosing(a.bbox, b.bbox),
+            phi: a.phi + b.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+        }
+    }
+
+    fn centroid(&self) -> Point {
+        let center = |interval: Interval| (interval.min + interval.max) / 2.0;
+        Point::new(
+            center(self.bbox.x),
+            center(self.bbox.y),
+            center(self.bbox.z),
+        )
+    }
+
+    // An estimate of the light at `p`, on a surface with normal `n`, from
+    // anywhere within the bounds: the power over the squared distance,
+    // times the cosines at either end with each angle reduced by as much as
+    // the bounds could turn it.
+    fn importance(&self, p: Point, n: Vec3) -> f64 {
+        let center = self.centroid();
+        let diagonal = Vec3::new(self.bbox.x.size(), self.bbox.y.size(), self.bbox.z.size());
+        let d2 = (p - center).length_squared().max(diagonal.length() / 2.0);
+
+        let cos_sub_clamped = |sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64| {
+            if cos_a > cos_b {
+                1.0
+            } else {
+                cos_a * cos_b + sin_a * sin_b
+            }
+        };
+        let sin_sub_clamped = |sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64| {
+            if cos_a > cos_b {
+                0.0
+            } else {
+                sin_a * cos_b - cos_a * sin_b
+            }
+        };
+        let sin_of = |cos: f64| (1.0 - cos * cos).max(0.0).sqrt();
+
+        let wi = (p - center).unit_vector();
+        let cos_theta_w = self.w.dot(wi);
+        let sin_theta_w = sin_of(cos_theta_w);
+        // the angle the bounds subtend from p, all around once p is inside
+        let radius = diagonal.length() / 2.0;
+        let cos_theta_b = if (p - center).length_squared() < radius * radius {
+            -1.0
+        } else {
+            sin_of(radius / (p - center).length())
+        };
+        let sin_theta_b = sin_of(cos_theta_b);
+        let sin_theta_o = sin_of(self.cos_theta_o);
+
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / d2;
+        if n.length_squared() > 0.0 {
+            let cos_theta_i = wi.dot(n).abs();
+            let sin_theta_i = sin_of(cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        importance.max(0.0)
+    }
+
+    // The surface area orientation heuristic: how costly the bounds are to
+    // have as a node, split along `axis` of `parent`.
+    fn cost(&self, parent: &Aabb, axis: usize) -> f64 {
+        let theta_o = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_e = self.cos_theta_e.clamp(-1.0, 1.0).acos();
+        let theta_w = (theta_o + theta_e).min(PI);
+        let sin_theta_o = (1.0 - self.cos_theta_o * self.cos_theta_o).max(0.0).sqrt();
+        let m_omega = 2.0 * PI * (1.0 - self.cos_theta_o)
+            + PI / 2.0
+                * (2.0 * theta_w * sin_theta_o
+                    - (theta_o - 2.0 * theta_w).cos()
+                    - 2.0 * theta_o * sin_theta_o
+                    + self.cos_theta_o);
+
+        let sizes = [0, 1, 2].map(|i| parent.axis(i).size());
+        let k_r = sizes.iter().fold(0.0, |max: f64, &size| max.max(size)) / sizes[axis];
+        let [x, y, z] = [0, 1, 2].map(|i| self.bbox.axis(i).size());
+        let surface_area = 2.0 * (x * y + y * z + z * x);
+        self.phi * m_omega * k_r * surface_area
+    }
+}
+
+// The smallest cone around both cones, given as axis and cosine of the
+// half-angle.
+fn cone_union(a: (Vec3, f64), b: (Vec3, f64)) -> (Vec3, f64) {
+    let theta_a = a.1.clamp(-1.0, 1.0).acos();
+    let theta_b = b.1.clamp(-1.0, 1.0).acos();
+    let theta_d = a.0.dot(b.0).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+
+    let entire_sphere = (Vec3::new(0.0, 0.0, 1.0), -1.0);
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+        return entire_sphere;
+    }
+    // turn a's axis towards b's by the difference in half-angles
+    let axis = a.0.cross(b.0);
+    if axis.length_squared() == 0.0 {
+        return entire_sphere;
+    }
+    let k = axis.unit_vector();
+    let (sin, cos) = (theta_o - theta_a).sin_cos();
+    let w = a.0 * cos + k.cross(a.0) * sin;
+    (w.unit_vector(), theta_o.cos())
+}
+
+struct Node {
+    bounds: LightBounds,
+    parent: Option<usize>,
+    // a leaf's light; interior nodes have their first child right after
+    // them and their second at `second`
+    light: Option<usize>,
+    second: usize,
+}
+
+/// Picks lights from a world's lights for next event estimation, one per
+/// shading point, with any of the `LightSampling` strategies. Directional
+/// lights, which bound no region, are picked apart from the others, each as
+/// often as all the others together.
+pub(crate) struct LightTree {
+    count: usize,
+    directional: Vec<usize>,
+    // over all the lights but the directional ones, if there are any lights
+    power: Option<Distribution1D>,
+    nodes: Vec<Node>,
+    // the leaf each light is in, if any
+    leaves: Vec<Option<usize>>,
+}
+
+impl LightTree {
+    pub(crate) fn new(lights: &[Light]) -> Self {
+        let mut directional = Vec::new();
+        let mut bounded = Vec::new();
+        let mut power = vec![0.0; lights.len()];
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.phi > 0.0 => {
+                    power[i] = bounds.phi;
+                    bounded.push((i, bounds));
+                }
+                Some(_) => {}
+                None => directional.push(i),
+            }
+        }
+
+        let mut tree = Self {
+            count: lights.len(),
+            directional,
+            power: (!power.is_empty()).then(|| Distribution1D::new(power)),
+            nodes: Vec::new(),
+            leaves: vec![None; lights.len()],
+        };
+        if !bounded.is_empty() {
+            tree.build(&mut bounded, None);
+        }
+        tree
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        if let [(light, bounds)] = *lights {
+            self.nodes.push(Node {
+                bounds,
+                parent,
+                light: Some(light),
+                second: 0,
+            });
+            self.leaves[light] = Some(index);
+            return index;
+        }
+
+        let bounds = lights
+            .iter()
+            .skip(1)
+            .fold(lights[0].1, |bounds, (_, other)| {
+                LightBounds::union(&bounds, other)
+            });
+        self.nodes.push(Node {
+            bounds,
+            parent,
+            light: None,
+            second: 0,
+        });
+
+        let mid = split(lights, &bounds.bbox);
+        self.build(&mut lights[..mid], Some(index));
+        let second = self.build(&mut lights[mid..], Some(index));
+        self.nodes[index].second = second;
+        index
+    }
+
+    /// Picks a light to sample at `p`, on a surface with normal `n`, or zero
+    /// for a point in a volume. Returns its index with the probability it
+    /// was picked with.
+    pub(crate) fn sample(
+        &self,
+        p: Point,
+        n: Vec3,
+        strategy: LightSampling,
+    ) -> Option<(usize, f64)> {
+        if self.count == 0 {
+            return None;
+        }
+        if strategy == LightSampling::Uniform {
+            let i = ((random_f64() * self.count as f64) as usize).min(self.count - 1);
+            return Some((i, 1.0 / self.count as f64));
+        }
+
+        let p_directional = self.directional_probability();
+        if random_f64() < p_directional {
+            let count = self.directional.len();
+            let i = ((random_f64() * count as f64) as usize).min(count - 1);
+            return Some((self.directional[i], p_directional / count as f64));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let (light, pmf) = match strategy {
+            LightSampling::Power => {
+                let power = self.power.as_ref()?;
+                let (_, _, light) = power.sample(random_f64());
+                (light, power.discrete_pmf(light))
+            }
+            _ => self.traverse(p, n)?,
+        };
+        Some((light, (1.0 - p_directional) * pmf))
+    }
+
+    /// The probability that `sample` picks `light`.
+    pub(crate) fn pmf(&self, p: Point, n: Vec3, light: usize, strategy: LightSampling) -> f64 {
+        if strategy == LightSampling::Uniform {
+            return 1.0 / self.count as f64;
+        }
+        let p_directional = self.directional_probability();
+        if self.directional.contains(&light) {
+            return p_directional / self.directional.len() as f64;
+        }
+        let Some(leaf) = self.leaves[light] else {
+            return 0.0;
+        };
+        let pmf = match strategy {
+            LightSampling::Power => self.power.as_ref().map_or(0.0, |p| p.discrete_pmf(light)),
+            _ => {
+                if self.nodes[leaf].bounds.importance(p, n) <= 0.0 {
+                    return 0.0;
+                }
+                // the chances of taking each step on the way down to the leaf
+                let mut pmf = 1.0;
+                let mut index = leaf;
+                while let Some(parent) = self.nodes[index].parent {
+                    let [first, second] = self.child_importances(parent, p, n);
+                    let importance = if index == parent + 1 { first } else { second };
+                    pmf *= importance / (first + second);
+                    index = parent;
+                }
+                pmf
+            }
+        };
+        (1.0 - p_directional) * pmf
+    }
+
+    /// The closest triangle light `ray` hits within `ray_t`, and where.
+    pub(crate) fn hit(&self, ray: &Ray, ray_t: Interval, lights: &[Light]) -> Option<(usize, f64)> {
+        let mut closest = None;
+        let mut ray_t = ray_t;
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.bbox.hit(ray, ray_t) {
+                continue;
+            }
+            match node.light {
+                Some(light) => {
+                    if let Some(t) = lights[light].hit(ray, ray_t) {
+                        ray_t.max = t;
+                        closest = Some((light, t));
+                    }
+                }
+                None => {
+                    stack.push(node.second);
+                    stack.push(index + 1);
+                }
+            }
+        }
+        closest
+    }
+
+    // Zero when there are no lights to pick from at all, as when none of
+    // them gives off any light.
+    fn directional_probability(&self) -> f64 {
+        let others = if self.nodes.is_empty() { 0 } else { 1 };
+        match self.directional.len() + others {
+            0 => 0.0,
+            total => self.directional.len() as f64 / total as f64,
+        }
+    }
+
+    // Walks down from the root, picking either child in proportion to its
+    // importance.
+    fn traverse(&self, p: Point, n: Vec3) -> Option<(usize, f64)> {
+        let mut index = 0;
+        let mut pmf = 1.0;
+        loop {
+            let node = &self.nodes[index];
+            if let Some(light) = node.light {
+                return (node.bounds.importance(p, n) > 0.0).then_some((light, pmf));
+            }
+            let [first, second] = self.child_importances(index, p, n);
+            if first + second <= 0.0 {
+                return None;
+            }
+            let p_first = first / (first + second);
+            if random_f64() < p_first {
+                index += 1;
+                pmf *= p_first;
+            } else {
+                index = node.second;
+                pmf *= 1.0 - p_first;
+            }
+        }
+    }
+
+    fn child_importances(&self, index: usize, p: Point, n: Vec3) -> [f64; 2] {
+        [index + 1, self.nodes[index].second].map(|child| self.nodes[child].bounds.importance(p, n))
+    }
+}
+
+// Orders the lights and returns where to split them in two, into buckets
+// along whichever axis costs least by the surface area orientation
+// heuristic, or in half along the longest axis if no split does better.
+fn split(lights: &mut [(usize, LightBounds)], bbox: &Aabb) -> usize {
+    const BUCKETS: usize = 12;
+
+    let centroids = lights.iter().fold(Aabb::EMPTY, |bbox, (_, bounds)| {
+        let c = bounds.centroid();
+        Aabb::enclosing(bbox, Aabb::from_points(c, c))
+    });
+    let coordinate = |bounds: &LightBounds, axis: usize| match axis {
+        1 => bounds.centroid().y,
+        2 => bounds.centroid().z,
+        _ => bounds.centroid().x,
+    };
+    let bucket = |bounds: &LightBounds, axis: usize| {
+        let interval = centroids.axis(axis);
+        let offset = (coordinate(bounds, axis) - interval.min) / interval.size();
+        ((offset * BUCKETS as f64) as usize).min(BUCKETS - 1)
+    };
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroids.axis(axis).size() <= 1e-9 || bbox.axis(axis).size() <= 0.0 {
+            continue;
+        }
+        let mut buckets: [Option<LightBounds>; BUCKETS] = [None; BUCKETS];
+        for (_, bounds) in lights.iter() {
+            let b = &mut buckets[bucket(bounds, axis)];
+            *b = Some(b.map_or(*bounds, |other| LightBounds::union(&other, bounds)));
+        }
+        let merged = |range: &[Option<LightBounds>]| {
+            range
+                .iter()
+                .flatten()
+                .fold(None, |merged: Option<LightBounds>, bounds| {
+                    Some(merged.map_or(*bounds, |other| LightBounds::union(&other, bounds)))
+                })
+        };
+        for i in 1..BUCKETS {
+            let (Some(below), Some(above)) = (merged(&buckets[..i]), merged(&buckets[i..])) else {
+                continue;
+            };
+            let cost = below.cost(bbox, axis) + above.cost(bbox, axis);
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    match best {
+        Some((_, axis, i)) => {
+            lights.sort_by(|a, b| coordinate(&a.1, axis).total_cmp(&coordinate(&b.1, axis)));
+            lights
+                .iter()
+                .filter(|(_, bounds)| bucket(bounds, axis) < i)
+                .count()
+        }
+        None => {
+            let axis = (0..3)
+                .max_by(|&a, &b| {
+                    centroids
+                        .axis(a)
+                        .size()
+                        .total_cmp(&centroids.axis(b).size())
+                })
+                .unwrap();
+            lights.sort_by(|a, b| coordinate(&a.1, axis).total_cmp(&coordinate(&b.1, axis)));
+            lights.len() / 2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRATEGIES: [LightSampling; 3] = [
+        LightSampling::Uniform,
+        LightSampling::Power,
+        LightSampling::Tree,
+    ];
+
+    // A ceiling of small triangles facing down, of growing power, with two
+    // point lights, a directional light and a light giving off nothing.
+    fn lights() -> Vec<Light> {
+        let mut lights = Vec::new();
+        for i in 0..6 {
+            for j in 0..4 {
+                let corner = Point::new(i as f64 - 3.0, 3.0, j as f64 - 2.0);
+                let vertices = [
+                    corner,
+                    corner + Vec3::new(0.5, 0.0, 0.0),
+                    corner + Vec3::new(0.0, 0.0, 0.5),
+                ];
+                let radiance = (1 + i + j) as f64;
+                lights.push(Light::triangle(
+                    vertices,
+                    Vec3::new(radiance, radiance, radiance),
+                ));
+            }
+        }
+        lights.push(Light::point(
+            Point::new(2.0, 1.0, 0.0),
+            Vec3::new(5.0, 5.0, 5.0),
+        ));
+        lights.push(Light::point(
+            Point::new(-4.0, 0.5, 3.0),
+            Vec3::new(1.0, 2.0, 3.0),
+        ));
+        lights.push(Light::directional(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ));
+        lights.push(Light::point(
+            Point::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+        ));
+        lights
+    }
+
+    #[test]
+    fn empty_trees_pick_no_light() {
+        let tree = LightTree::new(&[]);
+        let (p, n) = (Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        for strategy in STRATEGIES {
+            assert!(tree.sample(p, n, strategy).is_none());
+        }
+        let ray = Ray::new(p, n);
+        assert!(tree
+            .hit(&ray, Interval::new(0.0, f64::INFINITY), &[])
+            .is_none());
+
+        // lights that give off nothing leave nothing to pick either
+        let dark = [Light::point(p, Vec3::new(0.0, 0.0, 0.0))];
+        let tree = LightTree::new(&dark);
+        assert!(tree.sample(p, n, LightSampling::Tree).is_none());
+        assert_eq!(tree.pmf(p, n, 0, LightSampling::Tree), 0.0);
+    }
+
+    #[test]
+    fn samples_are_picked_with_their_pmf() {
+        let lights = lights();
+        let tree = LightTree::new(&lights);
+        let (p, n) = (Point::new(0.5, 0.0, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        for strategy in STRATEGIES {
+            let pmfs: Vec<f64> = (0..lights.len())
+                .map(|light| tree.pmf(p, n, light, strategy))
+                .collect();
+            assert!(
+                (pmfs.iter().sum::<f64>() - 1.0).abs() < 1e-9,
+                "{strategy:?}"
+            );
+
+            let count = 100_000;
+            let mut picked = vec![0; lights.len()];
+            for _ in 0..count {
+                let (light, pmf) = tree.sample(p, n, strategy).unwrap();
+                assert!((pmf - pmfs[light]).abs() < 1e-12);
+                picked[light] += 1;
+            }
+            for (light, picked) in picked.into_iter().enumerate() {
+                let share = picked as f64 / count as f64;
+                assert!((share - pmfs[light]).abs() < 0.005, "{strategy:?} {light}");
+            }
+        }
+
+        // nothing on the ceiling lights a point above it
+        let above = Point::new(0.0, 4.0, 0.0);
+        assert_eq!(tree.pmf(above, n, 0, LightSampling::Tree), 0.0);
+    }
+
+    #[test]
+    fn rays_hit_the_closest_triangle() {
+        let lights = lights();
+        let tree = LightTree::new(&lights);
+        let ray = Ray::new(Point::new(0.1, 0.0, 0.1), Vec3::new(0.0, 1.0, 0.0));
+        let (light, t) = tree
+            .hit(&ray, Interval::new(0.0, f64::INFINITY), &lights)
+            .unwrap();
+        assert_eq!(light, 3 * 4 + 2);
+        assert!((t - 3.0).abs() < 1e-12);
+        assert!(tree.hit(&ray, Interval::new(0.0, 2.5), &lights).is_none());
+    }
+}
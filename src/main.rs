@@ -1,26 +1,60 @@
+mod aabb;
+mod bsdf;
 mod camera;
 mod color;
 mod common;
+mod csg;
+mod curve;
+mod distribution;
+mod environment;
+mod hair;
+mod heightfield;
 mod interval;
+mod lens;
+mod light;
+mod lighttree;
 mod material;
+mod medium;
+mod mesh;
+mod microfacet;
+mod onb;
+mod perlin;
+mod planar;
+mod principled;
+mod quadric;
 mod ray;
 mod scene;
+mod scenes;
+mod sdf;
+mod sky;
+mod spectrum;
+mod texture;
+mod transform;
 mod vec3;
+mod volume;
 use std::{env, sync::Arc};
 
 use camera::Camera;
+use environment::Environment;
+use heightfield::Heightfield;
+use lens::RealisticLens;
+use light::{IesProfile, Light};
+use lighttree::LightSampling;
 
 use common::{random_f64, random_f64_in_range};
 use material::{Material, MaterialKind};
+use medium::Atmosphere;
+use planar::Plane;
 use scene::{Sphere, World};
+use sky::Sky;
 use vec3::Vec3;
+use volume::{GridMedium, VoxelGrid};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let samples_per_pixel = if args.len() > 1 {
-        args[1].parse::<u16>().unwrap()
-    } else {
-        10
+    let samples_per_pixel = match args.get(1) {
+        Some(arg) if !arg.starts_with("--") => arg.parse::<u16>().unwrap(),
+        _ => 10,
     };
 
     let aspect_ratio = 16.0 / 9.0;
@@ -36,19 +70,106 @@ fn main() {
         0.6,
         10.0,
     );
+    let camera = match flag(&args, "--lens") {
+        Some(path) => {
+            camera.with_realistic_lens(RealisticLens::from_file(path, 8.0, 35.0).unwrap())
+        }
+        None => camera,
+    };
+    let camera = match flag(&args, "--fog") {
+        Some(density) => camera.with_atmosphere(Atmosphere {
+            density: density.parse().unwrap(),
+            albedo: Vec3::new(0.9, 0.9, 0.9),
+            g: 0.3,
+            radius: 100.0,
+        }),
+        None => camera,
+    };
+    let camera = match flag(&args, "--environment") {
+        Some(path) => camera.with_environment(Environment::load(path, 1.0, 0.0).unwrap()),
+        None => camera,
+    };
+    let sun = match (flag(&args, "--sun"), flag(&args, "--location")) {
+        (Some(angles), _) => {
+            let (elevation, azimuth) = angles.split_once(',').unwrap();
+            Some(sky::sun_direction(
+                elevation.parse().unwrap(),
+                azimuth.parse().unwrap(),
+            ))
+        }
+        (None, Some(place)) => {
+            let values: Vec<f64> = place.split(',').map(|v| v.parse().unwrap()).collect();
+            let [latitude, longitude, day, hour, utc_offset] = values[..] else {
+                panic!("--location takes latitude,longitude,day,hour,utc_offset");
+            };
+            Some(sky::solar_direction(
+                latitude, longitude, day as u32, hour, utc_offset,
+            ))
+        }
+        (None, None) => None,
+    };
+    let camera = match sun {
+        Some(sun) => {
+            camera.with_sky(Sky::new(sun, 3.0, Vec3::new(0.3, 0.3, 0.3)).with_intensity(0.02))
+        }
+        None => camera,
+    };
+    let camera = match flag(&args, "--light-sampling") {
+        Some("uniform") => camera.with_light_sampling(LightSampling::Uniform),
+        Some("power") => camera.with_light_sampling(LightSampling::Power),
+        _ => camera,
+    };
+    let camera = if args.iter().any(|arg| arg == "--spectral") {
+        camera.with_spectral()
+    } else {
+        camera
+    };
 
-    let mut world: World = World::new();
+    let mut world = match flag(&args, "--scene") {
+        Some(name) => scenes::scene(name),
+        None => random_spheres(),
+    };
+    for description in flags(&args, "--light") {
+        world.add_light(light(description));
+    }
+    if let Some(path) = flag(&args, "--curves") {
+        let hair = Arc::new(Material {
+            kind: MaterialKind::Hair {
+                sigma_a: hair::sigma_a_from_melanin(0.8, 0.1),
+                beta_m: 0.3,
+                beta_n: 0.3,
+                alpha: 2.0,
+                eta: 1.55,
+            },
+            albedo: Vec3::new(1.0, 1.0, 1.0),
+        });
+        world.add(curve::load_curves(path, hair).unwrap());
+    }
+    if let Some(description) = flag(&args, "--obj") {
+        world.add(obj(description));
+    }
+    if let Some(description) = flag(&args, "--heightfield") {
+        world.add(heightfield(description));
+    }
+    if let Some(description) = flag(&args, "--volume") {
+        world.add(volume(description));
+    }
 
-    // World
+    camera.render(&world);
+}
+
+// The cover image: three big spheres among many small random ones.
+fn random_spheres() -> World {
+    let mut world = World::new();
 
     // ground
     let material_ground = Arc::new(Material {
         kind: MaterialKind::Lambertian,
         albedo: Vec3::new(0.5, 0.5, 0.5),
     });
-    world.add(Sphere::new(
-        Vec3::new(0.0, -1000.0, 0.0),
-        1000.0,
+    world.add(Plane::new(
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
         material_ground,
     ));
 
@@ -62,8 +183,8 @@ fn main() {
                 b as f64 + 0.9 * random_f64(),
             );
 
-            if ((center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9) {
-                if (choose_mat < 0.8) {
+            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                if choose_mat < 0.8 {
                     // diffuse
                     let albedo = Vec3::random() * Vec3::random();
                     let material = Arc::new(Material {
@@ -71,21 +192,26 @@ fn main() {
                         albedo,
                     });
                     world.add(Sphere::new(center, 0.2, material));
-                } else if (choose_mat < 0.95) {
+                } else if choose_mat < 0.95 {
                     // metal
                     let albedo = Vec3::random_in_range(0.5, 1.0);
-                    let fuzz = random_f64_in_range(0.0, 0.5);
+                    let roughness = random_f64_in_range(0.0, 0.5);
                     let material = Arc::new(Material {
-                        kind: MaterialKind::Metal { fuzz },
+                        kind: MaterialKind::Metal { roughness },
                         albedo,
                     });
                     world.add(Sphere::new(center, 0.2, material));
                 } else {
-                    // glass
-                    let dummy_albedo = Vec3::random_in_range(0.5, 1.0);
+                    // colored glass
+                    let albedo = Vec3::random_in_range(0.5, 1.0);
                     let material = Arc::new(Material {
-                        kind: MaterialKind::Dielectric { ref_idx: 1.5 },
-                        albedo: dummy_albedo,
+                        kind: MaterialKind::Dielectric {
+                            ref_idx: 1.5,
+                            roughness: 0.0,
+                            absorption_depth: 0.4,
+                            dispersion: None,
+                        },
+                        albedo,
                     });
                     world.add(Sphere::new(center, 0.2, material));
                 }
@@ -95,8 +221,13 @@ fn main() {
 
     // big spheres
     let material1 = Arc::new(Material {
-        kind: material::MaterialKind::Dielectric { ref_idx: 1.5 },
-        albedo: Vec3::new(0.8, 0.8, 0.8),
+        kind: material::MaterialKind::Dielectric {
+            ref_idx: 1.5,
+            roughness: 0.0,
+            absorption_depth: f64::INFINITY,
+            dispersion: None,
+        },
+        albedo: Vec3::new(1.0, 1.0, 1.0),
     });
     world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, material1));
 
@@ -107,10 +238,122 @@ fn main() {
     world.add(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, material2));
 
     let material3 = Arc::new(Material {
-        kind: material::MaterialKind::Metal { fuzz: 0.0 },
+        kind: material::MaterialKind::Metal { roughness: 0.0 },
         albedo: Vec3::new(0.7, 0.6, 0.5),
     });
     world.add(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, material3));
+    world
+}
 
-    camera.render(&world);
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+// Every value given for a flag that may be repeated.
+fn flags<'a>(args: &'a [String], name: &'a str) -> impl Iterator<Item = &'a str> {
+    args.windows(2)
+        .filter(move |pair| pair[0] == name)
+        .map(|pair| pair[1].as_str())
+}
+
+// A mesh from the description given to --obj, its OBJ file and optionally an
+// MTL file and the name of the material in it to shade the mesh with.
+fn obj(description: &str) -> mesh::Mesh {
+    let fields: Vec<&str> = description.split(',').collect();
+    let material = match fields[..] {
+        [_, mtl, name] => {
+            let mut materials = principled::load_mtl(mtl).unwrap();
+            let principled = materials
+                .remove(name)
+                .unwrap_or_else(|| panic!("no material {name} in {mtl}"));
+            Material {
+                kind: MaterialKind::Principled(Arc::new(principled)),
+                albedo: Vec3::new(1.0, 1.0, 1.0),
+            }
+        }
+        _ => Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        },
+    };
+    mesh::load_obj(fields[0], Arc::new(material)).unwrap()
+}
+
+// Terrain from the description given to --heightfield, its file and height,
+// spanning 16 by 16 units around the origin. Text files are read as grids of
+// heights and anything else as a grayscale image.
+fn heightfield(description: &str) -> Heightfield {
+    let (path, height) = description.split_once(',').unwrap();
+    let corner = Vec3::new(-8.0, 0.0, -8.0);
+    let size = Vec3::new(16.0, height.parse().unwrap(), 16.0);
+    let material = Arc::new(Material {
+        kind: MaterialKind::Lambertian,
+        albedo: Vec3::new(0.5, 0.5, 0.5),
+    });
+    if path.ends_with(".txt") {
+        Heightfield::load_grid(path, corner, size, material).unwrap()
+    } else {
+        Heightfield::from_image(path, corner, size, material).unwrap()
+    }
+}
+
+// A voxel grid from the description given to --volume, its file, density
+// scale and optional emission scale, filling a box on the ground at the
+// origin.
+fn volume(description: &str) -> GridMedium {
+    let mut fields = description.split(',');
+    let grid = VoxelGrid::load(fields.next().unwrap()).unwrap();
+    let density: f64 = fields.next().unwrap().parse().unwrap();
+    let medium = GridMedium::new(
+        Arc::new(grid),
+        Vec3::new(-1.0, 0.0, -1.0),
+        Vec3::new(1.0, 2.0, 1.0),
+        density,
+        Vec3::new(0.5, 0.5, 0.5),
+        0.0,
+    );
+    match fields.next() {
+        Some(emission) => medium.with_emission(emission.parse().unwrap(), 1.0),
+        None => medium,
+    }
+}
+
+// A light from the description given to --light: its kind, then its
+// parameters, all separated by commas, as listed in the README.
+fn light(description: &str) -> Light {
+    let (kind, rest) = description.split_once(',').unwrap_or((description, ""));
+    let (path, rest) = match kind {
+        "ies" => rest.split_once(',').unwrap(),
+        _ => ("", rest),
+    };
+    let values: Vec<f64> = rest
+        .split(',')
+        .map(|value| value.parse().unwrap())
+        .collect();
+    let vector = |i: usize| Vec3::new(values[i], values[i + 1], values[i + 2]);
+
+    // each kind may end with the size it shows up with to the camera
+    let (light, size) = match kind {
+        "point" => (Light::point(vector(0), vector(3)), 6),
+        "spot" => (
+            Light::spot(vector(0), vector(3), values[6], values[7], vector(8)),
+            11,
+        ),
+        "directional" => (Light::directional(vector(0), vector(3)), 6),
+        "ies" => {
+            let profile = Arc::new(IesProfile::load(path).unwrap());
+            (
+                Light::photometric(vector(0), vector(3), profile, vector(6)),
+                9,
+            )
+        }
+        _ => panic!("unknown kind of light: {kind}"),
+    };
+    match values.get(size) {
+        Some(&size) => light.with_visible_size(size),
+        None => light,
+    }
 }
@@ -1,4 +1,23 @@
-use crate::{common::random_f64, ray::Ray, scene::HitRecord, vec3::Vec3};
+use std::{
+    f64::consts::PI,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use crate::{
+    bsdf::{Bsdf, Film, Fresnel, Lobe},
+    common::random_f64,
+    hair::HairBsdf,
+    microfacet::{MetalPreset, TrowbridgeReitz},
+    onb::Onb,
+    principled::Principled,
+    ray::Ray,
+    scene::HitRecord,
+    spectrum::{sample_wavelength, Dispersion, RGB_WAVELENGTHS},
+    texture::Texture,
+    vec3::Vec3,
+    volume::GridEmission,
+};
 
 pub struct Material {
     pub kind: MaterialKind,
@@ -7,75 +26,780 @@ pub struct Material {
 
 pub enum MaterialKind {
     Lambertian,
-    Metal { fuzz: f64 },
-    Dielectric { ref_idx: f64 },
+    // GGX microfacet reflection with albedo as the reflectance at normal
+    // incidence
+    Metal {
+        roughness: f64,
+    },
+    // GGX microfacet reflection with the Fresnel reflectance of a complex
+    // index of refraction eta + ik, per color channel, and a measured metal
+    // that overrides them at the wavelengths of spectral rendering
+    Conductor {
+        eta: Vec3,
+        k: Vec3,
+        roughness: f64,
+        measured: Option<MetalPreset>,
+    },
+    // smooth glass when roughness is zero, frosted glass above. Light
+    // inside fades to albedo over `absorption_depth`, which is infinite for
+    // clear glass, and a dispersion model overrides `ref_idx` for rays that
+    // carry a wavelength.
+    Dielectric {
+        ref_idx: f64,
+        roughness: f64,
+        absorption_depth: f64,
+        dispersion: Option<Dispersion>,
+    },
+    // phase functions for participating media
+    Isotropic,
+    HenyeyGreenstein {
+        g: f64,
+    },
+    // emits albedo as radiance and doesn't scatter
+    DiffuseLight,
+    // the light a glowing voxel medium gives off where it absorbs, times
+    // albedo
+    MediumEmission(Arc<GridEmission>),
+    // hair fibers, colored by their absorption `sigma_a` rather than albedo;
+    // see hair.rs for the other parameters
+    Hair {
+        sigma_a: Vec3,
+        beta_m: f64,
+        beta_n: f64,
+        alpha: f64,
+        eta: f64,
+    },
+    // the layered uber-material in principled.rs, whose base color takes
+    // the place of albedo
+    Principled(Arc<Principled>),
+    // a thin film over a base material, like a soap bubble over clear
+    // glass or an oil slick over a puddle, whose interference colors the
+    // reflectance of a conductor or dielectric base. Thickness is in
+    // nanometers, and ior is the film's own index of refraction.
+    ThinFilm {
+        thickness: Texture,
+        ior: f64,
+        base: Arc<Material>,
+    },
+    // a clear dielectric coat, like the lacquer on car paint, over a base
+    // material, tinted towards albedo on each pass through it at normal
+    // incidence
+    Coated {
+        base: Arc<Material>,
+        ior: f64,
+        roughness: f64,
+    },
+    // a translucent solid like skin, wax or marble, behind a dielectric
+    // boundary, that light enters and wanders through before leaving. Its
+    // inside scatters isotropically with the given mean free path per color
+    // channel, and albedo is the color of the object as a whole after
+    // multiple scattering.
+    Subsurface {
+        mean_free_path: Vec3,
+        ior: f64,
+        roughness: f64,
+    },
+    // the microfacets of a conductor, dielectric or coated base stretched
+    // to roughnesses alpha_x along the surface's dpdu and alpha_y across
+    // it, like brushed metal, after turning that direction about the normal
+    // by `rotation` full turns
+    Anisotropic {
+        base: Arc<Material>,
+        alpha_x: Texture,
+        alpha_y: Texture,
+        rotation: Texture,
+    },
+    // a base material shaded with the normals of a tangent-space normal map,
+    // whose red and green channels point along dpdu and dpdv and blue along
+    // the surface normal
+    NormalMap {
+        base: Arc<Material>,
+        map: Texture,
+    },
+    // a base material shaded as if its surface were displaced along the
+    // normal by `height`, in world units
+    Bump {
+        base: Arc<Material>,
+        height: Texture,
+    },
+    // a base material with holes where `opacity` is below `threshold`, like
+    // the leaves and fences in alpha-masked textures, or that rays pass
+    // through with probability one minus the opacity without a threshold
+    Cutout {
+        base: Arc<Material>,
+        opacity: Texture,
+        threshold: Option<f64>,
+    },
+    // `a` blended into `b` by `weight`, like rust over metal, by shading
+    // each hit with one or the other at random in that proportion
+    Mix {
+        a: Arc<Material>,
+        b: Arc<Material>,
+        weight: Texture,
+    },
+    // `front` on the front face and `back` on the back, which sees the back
+    // face as its front
+    TwoSided {
+        front: Arc<Material>,
+        back: Arc<Material>,
+    },
 }
 
 pub struct Scatter {
     pub attenuation: Vec3,
     pub scattered: Ray,
+    // set when light scattered off a surface, not in a volume
+    pub(crate) surface: Option<SurfaceScatter>,
+}
+
+/// What it takes to sample lights at the surface a ray scattered off, with
+/// the same BSDF.
+pub(crate) struct SurfaceScatter {
+    pub(crate) bsdf: Bsdf,
+    // the incoming ray, carrying any wavelength the material picked
+    pub(crate) ray_in: Ray,
+    // what attenuates light scattered in any direction besides the BSDF
+    pub(crate) weight: Vec3,
+    // the density the scattered direction was sampled with, or none if
+    // light could only have scattered that way
+    pub(crate) pdf: Option<f64>,
+}
+
+impl MaterialKind {
+    pub(crate) fn conductor(preset: MetalPreset, roughness: f64) -> Self {
+        let (eta, k) = preset.eta_k();
+        MaterialKind::Conductor {
+            eta,
+            k,
+            roughness,
+            measured: Some(preset),
+        }
+    }
 }
 
 impl Material {
-    pub fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scatter> {
+    pub fn emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3 {
+        if let Some((material, hit)) = self.select(hit) {
+            return material.emitted(ray_in, &hit);
+        }
         match self.kind {
-            MaterialKind::Lambertian => {
-                let target = hit.p + hit.normal + Vec3::random_unit();
-                let mut direction = target - hit.p;
-                if direction.is_near_zero() {
-                    direction = hit.normal;
-                }
-                let scattered = Ray::new(hit.p, direction);
+            MaterialKind::DiffuseLight if hit.front_face => ray_in.color(self.albedo),
+            MaterialKind::MediumEmission(ref emission) => {
+                ray_in.color(emission.radiance(hit.p) * self.albedo)
+            }
+            MaterialKind::ThinFilm { ref base, .. }
+            | MaterialKind::Coated { ref base, .. }
+            | MaterialKind::Anisotropic { ref base, .. }
+            | MaterialKind::NormalMap { ref base, .. }
+            | MaterialKind::Bump { ref base, .. }
+            | MaterialKind::Cutout { ref base, .. } => base.emitted(ray_in, hit),
+            _ => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
 
-                Some(Scatter {
-                    attenuation: self.albedo,
-                    scattered,
-                })
+    /// Whether rays go through the surface at `hit` as if it weren't there,
+    /// where it's cut out or, randomly, where it's partly transparent.
+    pub(crate) fn passes_through(&self, hit: &HitRecord) -> bool {
+        if let Some((material, hit)) = self.select(hit) {
+            return material.passes_through(&hit);
+        }
+        match self.kind {
+            MaterialKind::Cutout {
+                ref base,
+                ref opacity,
+                threshold,
+            } => {
+                let opacity = opacity.scalar(hit.u, hit.v, hit.p);
+                let cut = match threshold {
+                    Some(threshold) => opacity < threshold,
+                    None => opacity < 1.0 && hash_f64(hit, 0) >= opacity,
+                };
+                cut || base.passes_through(hit)
             }
-            MaterialKind::Metal { fuzz } => {
-                let reflected = ray_in.direction.reflect(hit.normal);
-                let reflected = reflected.unit_vector() + Vec3::random_unit() * fuzz;
-                let scattered = Ray::new(hit.p, reflected);
-
-                Some(Scatter {
-                    attenuation: self.albedo,
-                    scattered,
-                })
+            MaterialKind::Principled(ref principled) => {
+                let opacity = principled.opacity.scalar(hit.u, hit.v, hit.p);
+                opacity < 1.0 && hash_f64(hit, 0) >= opacity
             }
-            MaterialKind::Dielectric { ref_idx } => {
-                let attenuation = Vec3::new(1.0, 1.0, 1.0);
-                let ref_idx = if hit.front_face {
-                    1.0 / ref_idx
+            MaterialKind::ThinFilm { ref base, .. }
+            | MaterialKind::Coated { ref base, .. }
+            | MaterialKind::Anisotropic { ref base, .. }
+            | MaterialKind::NormalMap { ref base, .. }
+            | MaterialKind::Bump { ref base, .. } => base.passes_through(hit),
+            _ => false,
+        }
+    }
+
+    /// The BSDF at `hit` for the light `ray_in` carries, in the shading
+    /// frame of its normal and tangent. `None` for materials that don't
+    /// scatter.
+    pub(crate) fn bsdf(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Bsdf> {
+        // Mixes and two-sided materials choose once, for the layers that
+        // build the lobe and frame, since the hit they hand on may no longer
+        // lead to the same choice.
+        let mut layers = Vec::new();
+        let hit = &self.shading_hit(hit, &mut layers);
+        let (leaf, wrappers) = layers.split_last()?;
+        let mut frame = Onb::from_normal_tangent(hit.normal, hit.dpdu);
+        let mut lobe = leaf.lobe(ray_in, hit)?;
+        for wrapper in wrappers.iter().rev() {
+            frame = wrapper.wrap_frame(frame, hit);
+            lobe = wrapper.wrap_lobe(lobe, ray_in, hit);
+        }
+        Some(Bsdf::new(frame, lobe))
+    }
+
+    // The material of a mix or two-sided material that shades `hit`, and the
+    // hit as that material sees it.
+    fn select(&self, hit: &HitRecord) -> Option<(&Material, HitRecord)> {
+        match self.kind {
+            MaterialKind::Mix {
+                ref a,
+                ref b,
+                ref weight,
+            } => {
+                // Nested mixes would choose alike with the same number, so
+                // each mix hashes its own.
+                let salt = self as *const Material as u64;
+                let chosen = if hash_f64(hit, salt) < weight.scalar(hit.u, hit.v, hit.p) {
+                    b
                 } else {
-                    ref_idx
+                    a
                 };
+                Some((chosen, hit.clone()))
+            }
+            MaterialKind::TwoSided {
+                ref front,
+                ref back,
+            } => Some(if hit.front_face {
+                (front, hit.clone())
+            } else {
+                let mut hit = hit.clone();
+                hit.front_face = true;
+                (back, hit)
+            }),
+            _ => None,
+        }
+    }
 
-                let unit_direction = ray_in.direction.unit_vector();
-                let cos_theta = (-unit_direction).dot(hit.normal);
-                let cos_theta = cos_theta.min(1.0);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    // The hit as shading sees it, with its normal perturbed by any normal or
+    // bump maps, after pushing the materials that shade it onto `layers`:
+    // the wrappers from the outside in, then the material they wrap, with
+    // mixes and two-sided materials replaced by the one they chose.
+    fn shading_hit<'a>(&'a self, hit: &HitRecord, layers: &mut Vec<&'a Material>) -> HitRecord {
+        if let Some((material, hit)) = self.select(hit) {
+            return material.shading_hit(&hit, layers);
+        }
+        layers.push(self);
+        match self.kind {
+            MaterialKind::NormalMap { ref base, ref map } => {
+                base.shading_hit(&normal_mapped(hit, map), layers)
+            }
+            MaterialKind::Bump {
+                ref base,
+                ref height,
+            } => base.shading_hit(&bumped(hit, height), layers),
+            MaterialKind::ThinFilm { ref base, .. }
+            | MaterialKind::Coated { ref base, .. }
+            | MaterialKind::Anisotropic { ref base, .. }
+            | MaterialKind::Cutout { ref base, .. } => base.shading_hit(hit, layers),
+            _ => hit.clone(),
+        }
+    }
 
-                let cannot_refract = ref_idx * sin_theta > 1.0;
-                let direction = if cannot_refract || reflectance(cos_theta, ref_idx) > random_f64()
-                {
-                    unit_direction.reflect(hit.normal)
-                } else {
-                    unit_direction.refract(hit.normal, ref_idx)
+    // The shading frame of a wrapper over its base's `frame`, which has u
+    // along the surface's dpdu unless a wrapper turns it.
+    fn wrap_frame(&self, frame: Onb, hit: &HitRecord) -> Onb {
+        match self.kind {
+            MaterialKind::Anisotropic { ref rotation, .. } => {
+                let angle = 2.0 * PI * rotation.scalar(hit.u, hit.v, hit.p);
+                let tangent = frame.u * angle.cos() + frame.v * angle.sin();
+                Onb::from_normal_tangent(frame.w, tangent)
+            }
+            _ => frame,
+        }
+    }
+
+    // The lobe of a wrapper over its base's `lobe`.
+    fn wrap_lobe(&self, lobe: Lobe, ray_in: &Ray, hit: &HitRecord) -> Lobe {
+        match self.kind {
+            MaterialKind::ThinFilm {
+                ref thickness, ior, ..
+            } => {
+                let film = Film {
+                    ior,
+                    thickness: thickness.scalar(hit.u, hit.v, hit.p).max(0.0),
+                    lambda: ray_in
+                        .wavelengths
+                        .map_or(RGB_WAVELENGTHS, |wavelengths| wavelengths.lambda),
                 };
+                lobe.with_film(film, hit.front_face)
+            }
+            MaterialKind::Coated { ior, roughness, .. } => Lobe::Coated {
+                eta: ior,
+                distribution: TrowbridgeReitz::from_roughness(roughness),
+                tint: ray_in.color(self.albedo),
+                base: Box::new(lobe),
+            },
+            MaterialKind::Anisotropic {
+                ref alpha_x,
+                ref alpha_y,
+                ..
+            } => {
+                let alpha = |texture: &Texture| texture.scalar(hit.u, hit.v, hit.p).max(0.0);
+                lobe.with_distribution(TrowbridgeReitz::new(alpha(alpha_x), alpha(alpha_y)))
+            }
+            _ => lobe,
+        }
+    }
 
-                let scattered = Ray::new(hit.p, direction);
+    // The lobe of a material that doesn't wrap another. `None` for those
+    // that don't scatter.
+    fn lobe(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Lobe> {
+        let albedo = ray_in.color(self.albedo);
+        let lobe = match self.kind {
+            MaterialKind::Lambertian => Lobe::Lambertian { albedo },
+            MaterialKind::Metal { roughness } => Lobe::Conductor {
+                distribution: TrowbridgeReitz::from_roughness(roughness),
+                fresnel: Fresnel::Schlick(albedo),
+            },
+            MaterialKind::Conductor {
+                eta,
+                k,
+                roughness,
+                measured,
+            } => Lobe::Conductor {
+                distribution: TrowbridgeReitz::from_roughness(roughness),
+                fresnel: match (ray_in.wavelengths, measured) {
+                    (Some(wavelengths), Some(metal)) => {
+                        let [a, b, c] = wavelengths.lambda.map(|lambda| metal.eta_k_at(lambda));
+                        Fresnel::Complex {
+                            eta: Vec3::new(a.0, b.0, c.0),
+                            k: Vec3::new(a.1, b.1, c.1),
+                        }
+                    }
+                    (Some(wavelengths), None) => Fresnel::Complex {
+                        eta: wavelengths.interpolate(eta),
+                        k: wavelengths.interpolate(k),
+                    },
+                    (None, _) => Fresnel::Complex { eta, k },
+                },
+            },
+            MaterialKind::Dielectric {
+                ref_idx,
+                roughness,
+                dispersion,
+                ..
+            } => {
+                let ref_idx = match (dispersion, ray_in.wavelength) {
+                    (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+                    _ => ref_idx,
+                };
+                Lobe::Dielectric {
+                    eta: if hit.front_face {
+                        ref_idx
+                    } else {
+                        1.0 / ref_idx
+                    },
+                    distribution: TrowbridgeReitz::from_roughness(roughness),
+                    film: None,
+                }
+            }
+            MaterialKind::Subsurface { ior, roughness, .. } => Lobe::Dielectric {
+                eta: if hit.front_face { ior } else { 1.0 / ior },
+                distribution: TrowbridgeReitz::from_roughness(roughness),
+                film: None,
+            },
+            MaterialKind::Isotropic => Lobe::Phase { albedo, g: 0.0 },
+            MaterialKind::HenyeyGreenstein { g } => Lobe::Phase { albedo, g },
+            MaterialKind::DiffuseLight | MaterialKind::MediumEmission(_) => return None,
+            MaterialKind::Hair {
+                sigma_a,
+                beta_m,
+                beta_n,
+                alpha,
+                eta,
+            } => {
+                // Curves report where across their width they were hit in v.
+                Lobe::Hair(HairBsdf::new(
+                    2.0 * hit.v - 1.0,
+                    eta,
+                    ray_in.color(sigma_a),
+                    beta_m,
+                    beta_n,
+                    alpha,
+                ))
+            }
+            MaterialKind::Principled(ref principled) => {
+                Lobe::Principled(Box::new(principled.bsdf(ray_in, hit)))
+            }
+            // shading_hit leaves these to wrap_lobe or resolves them
+            MaterialKind::ThinFilm { .. }
+            | MaterialKind::Coated { .. }
+            | MaterialKind::Anisotropic { .. }
+            | MaterialKind::NormalMap { .. }
+            | MaterialKind::Bump { .. }
+            | MaterialKind::Cutout { .. }
+            | MaterialKind::Mix { .. }
+            | MaterialKind::TwoSided { .. } => return None,
+        };
+        Some(lobe)
+    }
 
-                Some(Scatter {
-                    attenuation,
-                    scattered,
-                })
+    // Whether rays that hit this material follow a single wavelength.
+    fn is_dispersive(&self) -> bool {
+        match self.kind {
+            MaterialKind::Dielectric { dispersion, .. } => dispersion.is_some(),
+            MaterialKind::ThinFilm { ref base, .. }
+            | MaterialKind::Coated { ref base, .. }
+            | MaterialKind::Anisotropic { ref base, .. }
+            | MaterialKind::NormalMap { ref base, .. }
+            | MaterialKind::Bump { ref base, .. }
+            | MaterialKind::Cutout { ref base, .. } => base.is_dispersive(),
+            MaterialKind::Mix { ref a, ref b, .. } => a.is_dispersive() || b.is_dispersive(),
+            MaterialKind::TwoSided {
+                ref front,
+                ref back,
+            } => front.is_dispersive() || back.is_dispersive(),
+            _ => false,
+        }
+    }
+
+    /// The fraction of light that survives the segment of `ray_in` up to
+    /// `hit`, which is absorbed along the way if it ran through the inside
+    /// of a colored dielectric.
+    pub(crate) fn transmittance(&self, ray_in: &Ray, hit: &HitRecord) -> Vec3 {
+        if let Some((material, hit)) = self.select(hit) {
+            return material.transmittance(ray_in, &hit);
+        }
+        match self.kind {
+            MaterialKind::Dielectric {
+                absorption_depth, ..
+            } if !hit.front_face && absorption_depth.is_finite() => {
+                // Beer-Lambert, with the absorption coefficient that leaves
+                // albedo after absorption_depth
+                let distance = hit.t * ray_in.direction.length() / absorption_depth;
+                let channel = |albedo: f64| albedo.clamp(1e-8, 1.0).powf(distance);
+                let albedo = ray_in.color(self.albedo);
+                Vec3::new(channel(albedo.x), channel(albedo.y), channel(albedo.z))
             }
+            MaterialKind::ThinFilm { ref base, .. }
+            | MaterialKind::Coated { ref base, .. }
+            | MaterialKind::Anisotropic { ref base, .. }
+            | MaterialKind::NormalMap { ref base, .. }
+            | MaterialKind::Bump { ref base, .. }
+            | MaterialKind::Cutout { ref base, .. } => base.transmittance(ray_in, hit),
+            // given that interior_scatter let the ray through, which it did
+            // with probability the average of the channels' transmittances
+            MaterialKind::Subsurface { mean_free_path, .. } if !hit.front_face => {
+                let (sigma_t, _) = subsurface_coefficients(ray_in, mean_free_path, self.albedo);
+                let distance = hit.t * ray_in.direction.length();
+                let transmittance = sigma_t.map(|sigma_t| (-sigma_t * distance).exp());
+                let pdf = transmittance.iter().sum::<f64>() / 3.0;
+                if pdf <= 0.0 {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                let [r, g, b] = transmittance.map(|transmittance| transmittance / pdf);
+                Vec3::new(r, g, b)
+            }
+            _ => Vec3::new(1.0, 1.0, 1.0),
         }
     }
+
+    /// A scattering event inside a subsurface material on the segment of
+    /// `ray_in` up to `hit`, where the ray leaves it through its boundary.
+    /// `None` if the ray makes it to `hit`, or for other materials.
+    ///
+    /// Free flights are sampled with the extinction of a randomly chosen
+    /// channel and weighted by the average density over all three, so that
+    /// colors with very different mean free paths all converge.
+    pub(crate) fn interior_scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scatter> {
+        if let Some((material, hit)) = self.select(hit) {
+            return material.interior_scatter(ray_in, &hit);
+        }
+        let mean_free_path = match self.kind {
+            MaterialKind::Subsurface { mean_free_path, .. } => mean_free_path,
+            MaterialKind::ThinFilm { ref base, .. }
+            | MaterialKind::Coated { ref base, .. }
+            | MaterialKind::Anisotropic { ref base, .. }
+            | MaterialKind::NormalMap { ref base, .. }
+            | MaterialKind::Bump { ref base, .. }
+            | MaterialKind::Cutout { ref base, .. } => return base.interior_scatter(ray_in, hit),
+            _ => return None,
+        };
+        if hit.front_face {
+            return None;
+        }
+
+        let (sigma_t, albedo) = subsurface_coefficients(ray_in, mean_free_path, self.albedo);
+        let ray_length = ray_in.direction.length();
+        let channel = ((3.0 * random_f64()) as usize).min(2);
+        let distance = -(1.0 - random_f64()).ln() / sigma_t[channel];
+        if distance >= hit.t * ray_length {
+            return None;
+        }
+
+        let density = sigma_t.map(|sigma_t| sigma_t * (-sigma_t * distance).exp());
+        let pdf = density.iter().sum::<f64>() / 3.0;
+        let weight = |i: usize| albedo[i] * density[i] / pdf;
+        let direction = ray_in.direction / ray_length;
+        Some(Scatter {
+            attenuation: Vec3::new(weight(0), weight(1), weight(2)),
+            scattered: ray_in.continued(
+                ray_in.origin + direction * distance,
+                Vec3::random_unit().unit_vector(),
+            ),
+            surface: None,
+        })
+    }
+
+    pub fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<Scatter> {
+        if let Some((material, hit)) = self.select(hit) {
+            return material.scatter(ray_in, &hit);
+        }
+        // A dispersive material splits white light, so the ray picks one
+        // wavelength to follow from here on: when rendering spectrally, the
+        // hero wavelength, and otherwise a random one weighted by its color.
+        let (wavelength, tint) = if self.is_dispersive() && ray_in.wavelength.is_none() {
+            match ray_in.wavelengths {
+                Some(wavelengths) => (Some(wavelengths.hero()), Vec3::new(3.0, 0.0, 0.0)),
+                None => {
+                    let (lambda, tint) = sample_wavelength();
+                    (Some(lambda), tint)
+                }
+            }
+        } else {
+            (ray_in.wavelength, Vec3::new(1.0, 1.0, 1.0))
+        };
+        let ray_in = &Ray {
+            wavelength,
+            ..*ray_in
+        };
+
+        let bsdf = self.bsdf(ray_in, hit)?;
+        let wo = bsdf.to_local(-ray_in.direction.unit_vector());
+        let sample = bsdf.sample(wo, random_f64(), (random_f64(), random_f64()))?;
+        let weight = tint * self.transmittance(ray_in, hit);
+        let direction = bsdf.to_world(sample.wi);
+        Some(Scatter {
+            attenuation: sample.weight() * weight,
+            scattered: ray_in.continued(hit.spawn_point(direction), direction),
+            surface: Some(SurfaceScatter {
+                pdf: (!sample.flags.is_specular()).then_some(sample.pdf),
+                bsdf,
+                ray_in: *ray_in,
+                weight,
+            }),
+        })
+    }
+}
+
+// The extinction coefficients and single-scattering albedos, per channel, of
+// the inside of a subsurface material with the given mean free path and
+// multiple-scattering albedo, inverting the fit in Chiang et al., "Practical
+// and Controllable Subsurface Scattering for Production Path Tracing".
+fn subsurface_coefficients(
+    ray_in: &Ray,
+    mean_free_path: Vec3,
+    albedo: Vec3,
+) -> ([f64; 3], [f64; 3]) {
+    let mean_free_path = match ray_in.wavelengths {
+        Some(wavelengths) => wavelengths.interpolate(mean_free_path),
+        None => mean_free_path,
+    };
+    let albedo = ray_in.color(albedo);
+    let invert = |a: f64| {
+        let a = a.clamp(0.0, 1.0);
+        let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+        1.0 - s * s
+    };
+    (
+        [mean_free_path.x, mean_free_path.y, mean_free_path.z].map(|mfp| 1.0 / mfp.max(1e-6)),
+        [albedo.x, albedo.y, albedo.z].map(invert),
+    )
+}
+
+// The surface's outward normal and tangents along u and v, with a frame of
+// the normal standing in for shapes without derivatives.
+fn tangent_frame(hit: &HitRecord) -> (Vec3, Vec3, Vec3) {
+    let normal = if hit.front_face {
+        hit.normal
+    } else {
+        -hit.normal
+    };
+    if hit.dpdu.is_near_zero() || hit.dpdv.is_near_zero() {
+        let Onb { u, v, w } = Onb::new(normal);
+        return (w, u, v);
+    }
+    (normal, hit.dpdu, hit.dpdv)
 }
 
-fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    let r0 = r0 * r0;
-    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+fn normal_mapped(hit: &HitRecord, map: &Texture) -> HitRecord {
+    let (normal, dpdu, _) = tangent_frame(hit);
+    let frame = Onb::from_normal_tangent(normal, dpdu);
+    let value = map.value(hit.u, hit.v, hit.p) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+    let mapped = frame.to_world(value);
+    if mapped.is_near_zero() {
+        return hit.clone();
+    }
+    hit.clone().with_shading_normal(mapped.unit_vector())
+}
+
+// Blinn's bump mapping, with the height's derivatives taken by finite
+// differences as in pbrt, but over a fixed step in u and v since rays carry
+// no differentials.
+fn bumped(hit: &HitRecord, height: &Texture) -> HitRecord {
+    const DELTA: f64 = 0.0005;
+    let (normal, dpdu, dpdv) = tangent_frame(hit);
+    let displace = height.scalar(hit.u, hit.v, hit.p);
+    let displace_u = height.scalar(hit.u + DELTA, hit.v, hit.p + dpdu * DELTA);
+    let displace_v = height.scalar(hit.u, hit.v + DELTA, hit.p + dpdv * DELTA);
+    let dpdu = dpdu + normal * ((displace_u - displace) / DELTA);
+    let dpdv = dpdv + normal * ((displace_v - displace) / DELTA);
+    let bumped = dpdu.cross(dpdv);
+    if bumped.is_near_zero() {
+        return hit.clone();
+    }
+    let bumped = bumped.unit_vector();
+    let bumped = if bumped.dot(normal) < 0.0 {
+        -bumped
+    } else {
+        bumped
+    };
+    hit.clone()
+        .with_shading_normal(bumped)
+        .with_derivatives(dpdu, dpdv)
+}
+
+// A number in [0, 1) that varies like a random one, but is the same each
+// time the same ray meets `hit`, so that nested worlds and instances testing
+// a hit for transparency agree, and the parts of a mix choose alike. It goes
+// by what their hits share, the ray parameter and the UVs, and `salt` keeps
+// different uses apart.
+fn hash_f64(hit: &HitRecord, salt: u64) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    salt.hash(&mut hasher);
+    for value in [hit.t, hit.u, hit.v] {
+        value.to_bits().hash(&mut hasher);
+    }
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Point;
+
+    fn lambertian(albedo: Vec3) -> Arc<Material> {
+        Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo,
+        })
+    }
+
+    // A hit at the origin of the plane facing +z, by a ray going `direction`.
+    fn hit_plane(material: Arc<Material>, direction: Vec3, t: f64) -> (Ray, HitRecord) {
+        let ray = Ray::new(Point::new(0.0, 0.0, 0.0) - direction * t, direction);
+        let hit = HitRecord::new(
+            &ray,
+            Point::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            t,
+            (0.5, 0.5),
+            material,
+        )
+        .with_derivatives(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        (ray, hit)
+    }
+
+    #[test]
+    fn flat_maps_leave_the_normal_alone() {
+        let flat = Texture::Constant(Vec3::new(0.5, 0.5, 1.0));
+        let level = Texture::constant(0.3);
+        for direction in [Vec3::new(0.3, 0.1, -1.0), Vec3::new(0.3, 0.1, 1.0)] {
+            let (_, hit) = hit_plane(lambertian(Vec3::new(0.5, 0.5, 0.5)), direction, 1.0);
+            let mapped = normal_mapped(&hit, &flat);
+            assert!(
+                (mapped.normal - hit.normal).length() < 1e-12,
+                "{:?}",
+                mapped.normal
+            );
+            let bumped = bumped(&hit, &level);
+            assert!(
+                (bumped.normal - hit.normal).length() < 1e-12,
+                "{:?}",
+                bumped.normal
+            );
+        }
+    }
+
+    #[test]
+    fn wrapped_two_sided_shades_the_back_with_the_back_material() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let blue = Vec3::new(0.0, 0.0, 1.0);
+        let leaf = Arc::new(Material {
+            kind: MaterialKind::Cutout {
+                base: Arc::new(Material {
+                    kind: MaterialKind::TwoSided {
+                        front: lambertian(red),
+                        back: lambertian(blue),
+                    },
+                    albedo: Vec3::new(0.0, 0.0, 0.0),
+                }),
+                opacity: Texture::Constant(Vec3::new(1.0, 1.0, 1.0)),
+                threshold: Some(0.5),
+            },
+            albedo: Vec3::new(0.0, 0.0, 0.0),
+        });
+
+        for (direction, albedo) in [
+            (Vec3::new(0.0, 0.0, -1.0), red),
+            (Vec3::new(0.0, 0.0, 1.0), blue),
+        ] {
+            let (ray, hit) = hit_plane(leaf.clone(), direction, 1.0);
+            let bsdf = leaf.bsdf(&ray, &hit).unwrap();
+            let wo = bsdf.to_local(-direction);
+            assert!(wo.z > 0.99);
+            let f = bsdf.eval(wo, Vec3::new(0.0, 0.0, 1.0));
+            assert!((f - albedo / PI).length() < 1e-12, "{f:?}");
+        }
+    }
+
+    #[test]
+    fn mix_chooses_alike_for_the_same_hit() {
+        // `a` is a light cut out of the surface, `b` a diffuse surface, so
+        // each query tells which one the mix chose
+        let a = Arc::new(Material {
+            kind: MaterialKind::Cutout {
+                base: Arc::new(Material {
+                    kind: MaterialKind::DiffuseLight,
+                    albedo: Vec3::new(1.0, 1.0, 1.0),
+                }),
+                opacity: Texture::Constant(Vec3::new(0.0, 0.0, 0.0)),
+                threshold: Some(0.5),
+            },
+            albedo: Vec3::new(0.0, 0.0, 0.0),
+        });
+        let mix = Arc::new(Material {
+            kind: MaterialKind::Mix {
+                a,
+                b: lambertian(Vec3::new(0.5, 0.5, 0.5)),
+                weight: Texture::Constant(Vec3::new(0.5, 0.5, 0.5)),
+            },
+            albedo: Vec3::new(0.0, 0.0, 0.0),
+        });
+
+        let mut chose_a = 0;
+        let n = 200;
+        for i in 0..n {
+            let t = 1.0 + i as f64 * 0.01;
+            let (ray, hit) = hit_plane(mix.clone(), Vec3::new(0.0, 0.0, -1.0), t);
+            let passes = mix.passes_through(&hit);
+            let glows = mix.emitted(&ray, &hit).x > 0.0;
+            let scatters = mix.bsdf(&ray, &hit).is_some();
+            assert_eq!(passes, glows);
+            assert_eq!(passes, !scatters);
+            chose_a += passes as usize;
+        }
+        assert!(chose_a > n / 4 && chose_a < 3 * n / 4, "{chose_a}");
+    }
 }
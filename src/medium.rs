@@ -0,0 +1,172 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::{random_f64, Point};
+use crate::interval::Interval;
+use crate::material::{Material, MaterialKind, Scatter};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::vec3::Vec3;
+
+/// Samples a new direction for light travelling along `direction` that
+/// scatters with the Henyey-Greenstein phase function, from uniform samples
+/// `u`. `g` is the mean cosine of the scattering angle: 0 scatters
+/// uniformly, positive values forward.
+pub(crate) fn sample_henyey_greenstein(direction: Vec3, g: f64, u: (f64, f64)) -> Vec3 {
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * u.0
+    } else {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.0);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Onb::new(direction).to_world(Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ))
+}
+
+/// A volume of constant density filling a closed, convex boundary, like fog
+/// or smoke. Rays scatter after exponentially distributed distances inside
+/// it, according to the phase function of `phase_function`.
+pub(crate) struct ConstantMedium {
+    boundary: Arc<dyn Hittable + Send + Sync>,
+    neg_inv_density: f64,
+    phase_function: Arc<Material>,
+}
+
+impl ConstantMedium {
+    /// An isotropic medium when `g` is zero, otherwise Henyey-Greenstein.
+    pub(crate) fn new(
+        boundary: Arc<dyn Hittable + Send + Sync>,
+        density: f64,
+        albedo: Vec3,
+        g: f64,
+    ) -> Self {
+        let kind = if g == 0.0 {
+            MaterialKind::Isotropic
+        } else {
+            MaterialKind::HenyeyGreenstein { g }
+        };
+        Self {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function: Arc::new(Material { kind, albedo }),
+        }
+    }
+}
+
+impl ConstantMedium {
+    // The part of `ray_t` inside the boundary.
+    fn inside(&self, ray: &Ray, ray_t: Interval) -> Option<(f64, f64)> {
+        // Where the ray's line enters and leaves the boundary, which may be
+        // behind the ray origin when it starts inside.
+        let enter = self.boundary.hit(ray, Interval::UNIVERSE)?;
+        let exit = self
+            .boundary
+            .hit(ray, Interval::new(enter.t + 0.0001, f64::INFINITY))?;
+
+        let t_enter = enter.t.max(ray_t.min).max(0.0);
+        let t_exit = exit.t.min(ray_t.max);
+        (t_enter < t_exit).then_some((t_enter, t_exit))
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t_enter, t_exit) = self.inside(ray, ray_t)?;
+        let ray_length = ray.direction.length();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * random_f64().ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        // The normal and face are arbitrary for a scattering event.
+        let mut hit = HitRecord::new(
+            ray,
+            ray.at(t),
+            Vec3::new(1.0, 0.0, 0.0),
+            t,
+            (0.0, 0.0),
+            self.phase_function.clone(),
+        );
+        hit.front_face = true;
+        Some(hit)
+    }
+
+    fn transmittance(&self, ray: &Ray, ray_t: Interval) -> Option<f64> {
+        let distance_inside = self.inside(ray, ray_t).map_or(0.0, |(t_enter, t_exit)| {
+            (t_exit - t_enter) * ray.direction.length()
+        });
+        Some((distance_inside / self.neg_inv_density).exp())
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+/// Homogeneous haze around the camera that every ray segment passes
+/// through, out to `radius` from the camera.
+#[derive(Debug, Clone, Copy)]
+pub struct Atmosphere {
+    pub density: f64,
+    pub albedo: Vec3,
+    pub g: f64,
+    pub radius: f64,
+}
+
+impl Atmosphere {
+    /// Returns the scattering event, if any, on the part of the ray up to
+    /// `t_max` that lies within the atmosphere centered at `center`.
+    pub(crate) fn scatter(&self, ray: &Ray, t_max: f64, center: Point) -> Option<Scatter> {
+        let (start, end) = self.segment(ray, t_max, center)?;
+        let distance = start - random_f64().ln() / self.density;
+        if distance >= end {
+            return None;
+        }
+
+        let direction = ray.direction.unit_vector();
+        Some(Scatter {
+            attenuation: ray.color(self.albedo),
+            scattered: ray.continued(
+                ray.origin + direction * distance,
+                sample_henyey_greenstein(direction, self.g, (random_f64(), random_f64())),
+            ),
+            surface: None,
+        })
+    }
+
+    /// The fraction of light that makes it through the same part of the ray
+    /// without scattering.
+    pub(crate) fn transmittance(&self, ray: &Ray, t_max: f64, center: Point) -> f64 {
+        match self.segment(ray, t_max, center) {
+            Some((start, end)) => (-self.density * (end - start)).exp(),
+            None => 1.0,
+        }
+    }
+
+    // The distances along the ray, not parameters, between which it runs
+    // through the atmosphere before t_max.
+    fn segment(&self, ray: &Ray, t_max: f64, center: Point) -> Option<(f64, f64)> {
+        let ray_length = ray.direction.length();
+        let direction = ray.direction / ray_length;
+
+        let oc = ray.origin - center;
+        let h = -oc.dot(direction);
+        let discriminant = self.radius * self.radius - (oc + direction * h).length_squared();
+        if discriminant < 0.0 {
+            return None;
+        }
+        let half_chord = discriminant.sqrt();
+        let start = (h - half_chord).max(0.0);
+        let end = (h + half_chord).min(t_max * ray_length);
+        (start < end).then_some((start, end))
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::{invalid_data, Point};
+use crate::interval::Interval;
+use crate::light::Light;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+
+// One corner of a triangle, indexing into the mesh's attribute lists.
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+// A node of the mesh's bounding volume hierarchy over `triangles`. Leaves
+// hold `count` triangles from `start`, and interior nodes have no triangles
+// and their first child right after them, so `start` is the second.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bbox: Aabb,
+    start: usize,
+    count: usize,
+}
+
+/// A triangle mesh, front facing where its triangles' corners appear
+/// counterclockwise. Vertex normals, where given, are interpolated into a
+/// smooth shading normal, and vertex UVs into the hit's UV coordinates,
+/// which otherwise run over each triangle from (0, 0) to (1, 0) and (1, 1).
+pub(crate) struct Mesh {
+    positions: Vec<Point>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vec3>,
+    triangles: Vec<[Corner; 3]>,
+    nodes: Vec<Node>,
+    material: Arc<Material>,
+}
+
+impl Mesh {
+    /// A mesh of the triangles with corners at `positions[i]`, with neither
+    /// vertex normals nor UVs.
+    pub(crate) fn new(
+        positions: Vec<Point>,
+        triangles: Vec<[usize; 3]>,
+        material: Arc<Material>,
+    ) -> Self {
+        let triangles = triangles
+            .into_iter()
+            .map(|triangle| {
+                triangle.map(|position| Corner {
+                    position,
+                    uv: None,
+                    normal: None,
+                })
+            })
+            .collect();
+        Self::from_corners(positions, Vec::new(), Vec::new(), triangles, material)
+    }
+
+    fn from_corners(
+        positions: Vec<Point>,
+        uvs: Vec<(f64, f64)>,
+        normals: Vec<Vec3>,
+        triangles: Vec<[Corner; 3]>,
+        material: Arc<Material>,
+    ) -> Self {
+        let mut mesh = Self {
+            positions,
+            uvs,
+            normals,
+            triangles,
+            nodes: Vec::new(),
+            material,
+        };
+        let count = mesh.triangles.len();
+        mesh.build(0, count);
+        mesh
+    }
+
+    /// Displaces the surface along its normals by `height`, in world units,
+    /// after splitting every triangle into four `levels` times over for the
+    /// detail to land on. The height is looked up at each vertex's UVs and
+    /// position. Shading normals come from the displaced surface, smooth
+    /// everywhere, replacing any the mesh had.
+    pub(crate) fn displaced(self, height: &Texture, levels: u32) -> Self {
+        let Self {
+            mut positions,
+            mut uvs,
+            mut normals,
+            mut triangles,
+            material,
+            ..
+        } = self;
+
+        for _ in 0..levels {
+            // Corners on shared edges share their midpoints, which keeps
+            // the mesh watertight.
+            let mut position_midpoints = HashMap::new();
+            let mut uv_midpoints = HashMap::new();
+            let mut normal_midpoints = HashMap::new();
+            let mut split = |a: Corner, b: Corner| Corner {
+                position: midpoint(
+                    &mut positions,
+                    &mut position_midpoints,
+                    (a.position, b.position),
+                    |p, q| (p + q) / 2.0,
+                ),
+                uv: a.uv.zip(b.uv).map(|indices| {
+                    midpoint(&mut uvs, &mut uv_midpoints, indices, |p, q| {
+                        ((p.0 + q.0) / 2.0, (p.1 + q.1) / 2.0)
+                    })
+                }),
+                normal: a.normal.zip(b.normal).map(|indices| {
+                    midpoint(&mut normals, &mut normal_midpoints, indices, |p, q| {
+                        let n = p + q;
+                        if n.is_near_zero() {
+                            p
+                        } else {
+                            n.unit_vector()
+                        }
+                    })
+                }),
+            };
+            triangles = triangles
+                .into_iter()
+                .flat_map(|[a, b, c]| {
+                    let (ab, bc, ca) = (split(a, b), split(b, c), split(c, a));
+                    [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        // Each position moves along the area-weighted average of the normals
+        // around it, at the UVs of the first corner there.
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let mut directions = vec![zero; positions.len()];
+        let mut position_uvs = vec![None; positions.len()];
+        for triangle in &triangles {
+            let [p0, p1, p2] = triangle.map(|corner| positions[corner.position]);
+            let face = (p1 - p0).cross(p2 - p0);
+            for corner in triangle {
+                directions[corner.position] +=
+                    corner.normal.map_or(face, |i| normals[i] * face.length());
+                if position_uvs[corner.position].is_none() {
+                    position_uvs[corner.position] = corner.uv.map(|i| uvs[i]);
+                }
+            }
+        }
+        for (i, p) in positions.iter_mut().enumerate() {
+            if directions[i].is_near_zero() {
+                continue;
+            }
+            let (u, v) = position_uvs[i].unwrap_or((0.0, 0.0));
+            *p += directions[i].unit_vector() * height.scalar(u, v, *p);
+        }
+
+        let mut smooth = vec![zero; positions.len()];
+        for triangle in &triangles {
+            let [p0, p1, p2] = triangle.map(|corner| positions[corner.position]);
+            let face = (p1 - p0).cross(p2 - p0);
+            for corner in triangle {
+                smooth[corner.position] += face;
+            }
+        }
+        let normals = smooth
+            .into_iter()
+            .zip(directions)
+            .map(|(n, direction)| {
+                if n.is_near_zero() {
+                    direction
+                } else if n.dot(direction) < 0.0 {
+                    -n.unit_vector()
+                } else {
+                    n.unit_vector()
+                }
+            })
+            .collect();
+        for triangle in &mut triangles {
+            for corner in triangle {
+                corner.normal = Some(corner.position);
+            }
+        }
+
+        Self::from_corners(positions, uvs, normals, triangles, material)
+    }
+
+    fn corners(&self, triangle: &[Corner; 3]) -> [Point; 3] {
+        triangle.map(|corner| self.positions[corner.position])
+    }
+
+    fn triangle_bbox(&self, triangle: &[Corner; 3]) -> Aabb {
+        let [a, b, c] = self.corners(triangle);
+        Aabb::enclosing(Aabb::from_points(a, b), Aabb::from_points(a, c))
+    }
+
+    // Builds the subtree over triangles[start..end], splitting at the median
+    // centroid along the axis where the centroids spread the most.
+    fn build(&mut self, start: usize, end: usize) {
+        const LEAF_SIZE: usize = 4;
+
+        let bbox = self.triangles[start..end]
+            .iter()
+            .fold(Aabb::EMPTY, |bbox, triangle| {
+                Aabb::enclosing(bbox, self.triangle_bbox(triangle))
+            });
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bbox,
+            start,
+            count: end - start,
+        });
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+
+        let centroid = |mesh: &Self, triangle: &[Corner; 3]| {
+            let [a, b, c] = mesh.corners(triangle);
+            (a + b + c) / 3.0
+        };
+        let centroids = self.triangles[start..end]
+            .iter()
+            .fold(Aabb::EMPTY, |bbox, triangle| {
+                let c = centroid(self, triangle);
+                Aabb::enclosing(bbox, Aabb::from_points(c, c))
+            });
+        let axis = (0..3)
+            .max_by(|&a, &b| {
+                centroids
+                    .axis(a)
+                    .size()
+                    .total_cmp(&centroids.axis(b).size())
+            })
+            .unwrap_or(0);
+        let key = |c: Point| match axis {
+            0 => c.x,
+            1 => c.y,
+            _ => c.z,
+        };
+
+        let mid = start + (end - start) / 2;
+        let mut triangles = std::mem::take(&mut self.triangles);
+        triangles[start..end].select_nth_unstable_by(mid - start, |a, b| {
+            key(centroid(self, a)).total_cmp(&key(centroid(self, b)))
+        });
+        self.triangles = triangles;
+
+        self.build(start, mid);
+        let second = self.nodes.len();
+        self.build(mid, end);
+        self.nodes[index] = Node {
+            bbox,
+            start: second,
+            count: 0,
+        };
+    }
+
+    // Möller and Trumbore, "Fast, Minimum Storage Ray/Triangle
+    // Intersection": the ray parameter and barycentric coordinates of the
+    // second and third corners.
+    fn intersect(
+        &self,
+        triangle: &[Corner; 3],
+        ray: &Ray,
+        ray_t: Interval,
+    ) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.corners(triangle);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let h = ray.direction.cross(edge2);
+        let det = edge1.dot(h);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = ray.origin - a;
+        let b1 = s.dot(h) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let b2 = ray.direction.dot(q) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inv_det;
+        ray_t.surrounds(t).then_some((t, b1, b2))
+    }
+
+    fn hit_record(
+        &self,
+        triangle: &[Corner; 3],
+        ray: &Ray,
+        (t, b1, b2): (f64, f64, f64),
+    ) -> HitRecord {
+        let [p0, p1, p2] = self.corners(triangle);
+        let b0 = 1.0 - b1 - b2;
+        let p = p0 * b0 + p1 * b1 + p2 * b2;
+
+        let uv = |corner: &Corner| corner.uv.map(|i| self.uvs[i]);
+        let [uv0, uv1, uv2] = match triangle.each_ref().map(uv) {
+            [Some(uv0), Some(uv1), Some(uv2)] => [uv0, uv1, uv2],
+            _ => [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)],
+        };
+        let u = b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0;
+        let v = b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1;
+
+        let normal = |corner: &Corner| corner.normal.map(|i| self.normals[i]);
+        let shading_normal = match triangle.each_ref().map(normal) {
+            [Some(n0), Some(n1), Some(n2)] => {
+                Some(n0 * b0 + n1 * b1 + n2 * b2).filter(|n| !n.is_near_zero())
+            }
+            _ => None,
+        };
+        // Vertex normals outvote the winding order on which side is out.
+        let geometric_normal = (p1 - p0).cross(p2 - p0).unit_vector();
+        let geometric_normal = match shading_normal {
+            Some(n) if n.dot(geometric_normal) < 0.0 => -geometric_normal,
+            _ => geometric_normal,
+        };
+
+        // Solve for the derivatives from the triangle's edges in position
+        // and in UV, as in pbrt.
+        let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+        let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+        let (dp02, dp12) = (p0 - p2, p1 - p2);
+        let determinant = du02 * dv12 - dv02 * du12;
+        let (dpdu, dpdv) = if determinant.abs() < 1e-12 {
+            let Onb { u, v, .. } = Onb::new(geometric_normal);
+            (u, v)
+        } else {
+            let inv = 1.0 / determinant;
+            (
+                (dp02 * dv12 - dp12 * dv02) * inv,
+                (dp12 * du02 - dp02 * du12) * inv,
+            )
+        };
+
+        let hit = HitRecord::new(ray, p, geometric_normal, t, (u, v), self.material.clone())
+            .with_derivatives(dpdu, dpdv);
+        match shading_normal {
+            Some(n) => hit.with_shading_normal(n.unit_vector()),
+            None => hit,
+        }
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let mut closest: Option<(usize, (f64, f64, f64))> = None;
+        let mut ray_t = ray_t;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = self.nodes[index];
+            if !node.bbox.hit(ray, ray_t) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(index + 1);
+                continue;
+            }
+            for i in node.start..node.start + node.count {
+                if let Some(hit) = self.intersect(&self.triangles[i], ray, ray_t) {
+                    ray_t.max = hit.0;
+                    closest = Some((i, hit));
+                }
+            }
+        }
+        let (i, hit) = closest?;
+        Some(self.hit_record(&self.triangles[i], ray, hit))
+    }
+
+    fn triangle_lights(&self) -> Option<Vec<Light>> {
+        let vertices = self
+            .triangles
+            .iter()
+            .map(|triangle| triangle.map(|corner| self.positions[corner.position]));
+        Light::emissive_triangles(&self.material, vertices)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| node.bbox)
+    }
+}
+
+/// Loads the geometry of a Wavefront OBJ file: `v`, `vt` and `vn`
+/// statements and `f` faces, with polygons split into fans of triangles.
+/// Everything else, such as groups and material names, is ignored.
+pub(crate) fn load_obj(path: impl AsRef<Path>, material: Arc<Material>) -> io::Result<Mesh> {
+    let text = fs::read_to_string(path)?;
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut triangles = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(keyword) = fields.next() else {
+            continue;
+        };
+        let error = |message: &str| invalid_data(&format!("line {}: {message}", number + 1));
+        let numbers = |fields: std::str::SplitWhitespace, count: usize| {
+            let numbers = fields
+                .take(count)
+                .map(|field| {
+                    field
+                        .parse::<f64>()
+                        .map_err(|_| error(&format!("invalid number: {field}")))
+                })
+                .collect::<io::Result<Vec<f64>>>()?;
+            if numbers.len() < count {
+                return Err(error(&format!("expected {count} numbers")));
+            }
+            Ok(numbers)
+        };
+
+        match keyword {
+            "v" => {
+                let p = numbers(fields, 3)?;
+                positions.push(Point::new(p[0], p[1], p[2]));
+            }
+            "vt" => {
+                let uv = numbers(fields, 2)?;
+                uvs.push((uv[0], uv[1]));
+            }
+            "vn" => {
+                let n = numbers(fields, 3)?;
+                normals.push(Vec3::new(n[0], n[1], n[2]));
+            }
+            "f" => {
+                // Indices start at 1, and negative ones count back from the
+                // latest vertex.
+                let resolve = |field: &str, count: usize| -> io::Result<Option<usize>> {
+                    if field.is_empty() {
+                        return Ok(None);
+                    }
+                    let index = field
+                        .parse::<i64>()
+                        .map_err(|_| error(&format!("invalid index: {field}")))?;
+                    let resolved = if index < 0 {
+                        count as i64 + index
+                    } else {
+                        index - 1
+                    };
+                    if resolved < 0 || resolved >= count as i64 {
+                        return Err(error(&format!("index out of range: {field}")));
+                    }
+                    Ok(Some(resolved as usize))
+                };
+                let corners = fields
+                    .map(|field| {
+                        let mut indices = field.split('/');
+                        let position = resolve(indices.next().unwrap_or(""), positions.len())?
+                            .ok_or_else(|| error("face corner without a position"))?;
+                        let uv = resolve(indices.next().unwrap_or(""), uvs.len())?;
+                        let normal = resolve(indices.next().unwrap_or(""), normals.len())?;
+                        Ok(Corner {
+                            position,
+                            uv,
+                            normal,
+                        })
+                    })
+                    .collect::<io::Result<Vec<Corner>>>()?;
+                if corners.len() < 3 {
+                    return Err(error("face with fewer than three corners"));
+                }
+                for i in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Mesh::from_corners(
+        positions, uvs, normals, triangles, material,
+    ))
+}
+
+// The index of the value halfway between values[a] and values[b], added
+// the first time it's asked for.
+fn midpoint<T: Copy>(
+    values: &mut Vec<T>,
+    midpoints: &mut HashMap<(usize, usize), usize>,
+    (a, b): (usize, usize),
+    mix: impl Fn(T, T) -> T,
+) -> usize {
+    *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+        values.push(mix(values[a], values[b]));
+        values.len() - 1
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::temp_file;
+    use crate::material::MaterialKind;
+
+    fn load(name: &str, obj: &str) -> io::Result<Mesh> {
+        let path = temp_file(name, obj.as_bytes());
+        let material = Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        });
+        let mesh = load_obj(&path, material);
+        fs::remove_file(path).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn obj_faces_resolve_their_indices() {
+        // a unit square in the xy plane as one quad, with relative indices
+        let mesh = load(
+            "square.obj",
+            "# square\n\
+             o square\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             vn 0 0 1\n\
+             usemtl ignored\n\
+             f -4/1/1 -3/2/1 -2/3/1 -1/4/1\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+
+        let ray = Ray::new(Point::new(0.25, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = mesh.hit(&ray, Interval::new(0.0, 10.0)).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-12);
+        assert!((hit.u - 0.25).abs() < 1e-12 && (hit.v - 0.75).abs() < 1e-12);
+        assert!(hit.front_face);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn displacement_splits_triangles_without_cracks() {
+        let material = Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        });
+        let square = Mesh::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(1.0, 1.0, 0.0),
+                Point::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            material,
+        );
+        let mesh = square.displaced(&Texture::constant(0.1), 1);
+        assert_eq!(mesh.triangles.len(), 8);
+        // the corners, and one midpoint for each of the four sides and the
+        // diagonal the two triangles share
+        assert_eq!(mesh.positions.len(), 9);
+
+        // Inner edges belong to two triangles and the square's sides to one.
+        let mut edges = HashMap::new();
+        for triangle in &mesh.triangles {
+            for k in 0..3 {
+                let (a, b) = (triangle[k].position, triangle[(k + 1) % 3].position);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        assert_eq!(edges.values().filter(|&&count| count == 1).count(), 8);
+        assert!(edges.values().all(|&count| count == 1 || count == 2));
+
+        for p in &mesh.positions {
+            assert!((p.z - 0.1).abs() < 1e-12, "{p:?}");
+        }
+        let ray = Ray::new(Point::new(0.3, 0.6, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = mesh.hit(&ray, Interval::new(0.0, 10.0)).unwrap();
+        assert!((hit.t - 0.9).abs() < 1e-12);
+    }
+
+    #[test]
+    fn invalid_obj_lines_are_errors() {
+        for (name, obj) in [
+            ("number.obj", "v 0 zero 0\n"),
+            ("short.obj", "v 0 0\n"),
+            ("range.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n"),
+            ("corners.obj", "v 0 0 0\nv 1 0 0\nf 1 2\n"),
+            ("position.obj", "v 0 0 0\nvt 0 0\nf /1 /1 /1\n"),
+        ] {
+            let error = load(name, obj).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{name}");
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use std::f64::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::vec3::Vec3;
+
+// Below this alpha, surfaces are treated as perfectly smooth.
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+/// The Trowbridge-Reitz (GGX) distribution of microfacet normals, with
+/// separate roughness along the x and y axes of the local shading frame.
+/// Directions are local, with z along the surface normal.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub(crate) fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    /// An isotropic distribution for a perceptual roughness in [0, 1],
+    /// squared into alpha so that roughness looks roughly linear.
+    pub(crate) fn from_roughness(roughness: f64) -> Self {
+        let alpha = roughness * roughness;
+        Self::new(alpha, alpha)
+    }
+
+    pub(crate) fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    /// The density of microfacets with normal `wm`.
+    pub(crate) fn d(&self, wm: Vec3) -> f64 {
+        let cos2_theta = wm.z * wm.z;
+        let sin2_theta = (1.0 - cos2_theta).max(0.0);
+        if cos2_theta == 0.0 {
+            return 0.0;
+        }
+        let tan2_theta = sin2_theta / cos2_theta;
+        let (cos2_phi, sin2_phi) = cos2_sin2_phi(wm);
+        let e = tan2_theta
+            * (cos2_phi / (self.alpha_x * self.alpha_x) + sin2_phi / (self.alpha_y * self.alpha_y));
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2_theta * cos2_theta * (1.0 + e) * (1.0 + e))
+    }
+
+    // Smith's auxiliary function, the ratio of masked to visible microfacet
+    // area seen from `w`.
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2_theta = w.z * w.z;
+        if cos2_theta == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2_theta = (1.0 - cos2_theta).max(0.0) / cos2_theta;
+        let (cos2_phi, sin2_phi) = cos2_sin2_phi(w);
+        let alpha2 =
+            cos2_phi * self.alpha_x * self.alpha_x + sin2_phi * self.alpha_y * self.alpha_y;
+        ((1.0 + alpha2 * tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    /// The fraction of microfacets visible from `w`.
+    pub(crate) fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of microfacets visible from both `wo` and `wi`, with
+    /// height-correlated Smith masking-shadowing.
+    pub(crate) fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// The density of microfacet normals `wm` as seen from `w`.
+    pub(crate) fn d_visible(&self, w: Vec3, wm: Vec3) -> f64 {
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Samples a microfacet normal visible from `w`, following Heitz,
+    /// "Sampling the GGX Distribution of Visible Normals", from uniform
+    /// samples `u`.
+    pub(crate) fn sample_wm(&self, w: Vec3, u: (f64, f64)) -> Vec3 {
+        // Stretch the view direction into the hemisphere configuration.
+        let wh = Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).unit_vector();
+        let wh = if wh.z < 0.0 { -wh } else { wh };
+        let t1 = if wh.z < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(wh).unit_vector()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(t1);
+
+        // Sample the projected area of the visible hemisphere.
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let (px, py) = (r * phi.cos(), r * phi.sin());
+        let h = (1.0 - px * px).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        let py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = t1 * px + t2 * py + wh * pz;
+
+        // and unstretch the normal
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+}
+
+fn cos2_sin2_phi(w: Vec3) -> (f64, f64) {
+    let sin2_theta = w.x * w.x + w.y * w.y;
+    if sin2_theta == 0.0 {
+        (1.0, 0.0)
+    } else {
+        (w.x * w.x / sin2_theta, w.y * w.y / sin2_theta)
+    }
+}
+
+/// Mirrors `w` about `n`.
+pub(crate) fn reflect(w: Vec3, n: Vec3) -> Vec3 {
+    -w + n * (2.0 * w.dot(n))
+}
+
+/// Refracts `w`, pointing away from the surface on the side `n` faces, into
+/// a medium with relative index of refraction `eta`. `None` on total
+/// internal reflection.
+pub(crate) fn refract(w: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = w.dot(n);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-w / eta + n * (cos_theta_i / eta - cos_theta_t))
+}
+
+/// The microfacet normal that refracts `wo` into `wi` through a surface
+/// with relative index of refraction `eta`, oriented along +z, and the
+/// square of the denominator of the change of variables between them.
+/// `None` when no microfacet facing both directions does so.
+pub(crate) fn refracted_half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<(Vec3, f64)> {
+    let wm = wi * eta + wo;
+    if wm.is_near_zero() {
+        return None;
+    }
+    let wm = wm.unit_vector();
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+    if wo.dot(wm) <= 0.0 || wi.dot(wm) >= 0.0 {
+        return None;
+    }
+    let denominator = (wi.dot(wm) + wo.dot(wm) / eta).powi(2);
+    Some((wm, denominator))
+}
+
+/// Unpolarized Fresnel reflectance from outside a dielectric with relative
+/// index of refraction `eta`.
+pub(crate) fn fr_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt() / eta;
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction
+// eta + ik, for one wavelength.
+fn fr_complex_channel(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = ((a2_plus_b2 + t0) / 2.0).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
+
+/// Fresnel reflectance of a conductor, per color channel.
+pub(crate) fn fr_complex(cos_theta_i: f64, eta: Vec3, k: Vec3) -> Vec3 {
+    Vec3::new(
+        fr_complex_channel(cos_theta_i, eta.x, k.x),
+        fr_complex_channel(cos_theta_i, eta.y, k.y),
+        fr_complex_channel(cos_theta_i, eta.z, k.z),
+    )
+}
+
+/// Schlick's approximation of Fresnel reflectance with normal incidence
+/// reflectance `f0`.
+pub(crate) fn fr_schlick(cos_theta_i: f64, f0: Vec3) -> Vec3 {
+    let weight = (1.0 - cos_theta_i.clamp(0.0, 1.0)).powi(5);
+    f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * weight
+}
+
+/// Reflectance of a film of relative index of refraction `film_eta` and
+/// `thickness` nanometers over a base with complex index `eta + ik`, at
+/// `lambda` nanometers, from the Airy sum over the light bouncing inside the
+/// film. Reduces to plain Fresnel reflectance off the base for a film of
+/// zero thickness.
+pub(crate) fn fr_thin_film(
+    cos_theta_i: f64,
+    film_eta: f64,
+    thickness: f64,
+    eta: f64,
+    k: f64,
+    lambda: f64,
+) -> f64 {
+    let cos0 = Complex::real(cos_theta_i.clamp(0.0, 1.0));
+    let sin2 = Complex::real(1.0 - cos_theta_i.clamp(0.0, 1.0).powi(2));
+    let (n0, n1, n2) = (
+        Complex::real(1.0),
+        Complex::real(film_eta),
+        Complex::new(eta, k),
+    );
+    // Snell's law, with complex angles past total internal reflection and
+    // into the absorbing base
+    let cos_in = |n: Complex| (Complex::real(1.0) - sin2 / (n * n)).sqrt();
+    let (cos1, cos2) = (cos_in(n1), cos_in(n2));
+
+    // the phase difference between successive bounces
+    let phase = Complex::new(0.0, 4.0 * PI * thickness / lambda) * n1 * cos1;
+    let delay = phase.exp();
+    let airy = |r01: Complex, r12: Complex| {
+        let r = (r01 + r12 * delay) / (Complex::real(1.0) + r01 * r12 * delay);
+        r.norm_squared()
+    };
+    let rs = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        (na * ca - nb * cb) / (na * ca + nb * cb)
+    };
+    let rp = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        (nb * ca - na * cb) / (nb * ca + na * cb)
+    };
+    let reflectance = (airy(rs(n0, cos0, n1, cos1), rs(n1, cos1, n2, cos2))
+        + airy(rp(n0, cos0, n1, cos1), rp(n1, cos1, n2, cos2)))
+        / 2.0;
+    reflectance.clamp(0.0, 1.0)
+}
+
+// Just enough complex arithmetic for thin films.
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn real(re: f64) -> Self {
+        Self::new(re, 0.0)
+    }
+
+    fn norm_squared(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    // the principal square root
+    fn sqrt(self) -> Self {
+        let norm = self.norm_squared().sqrt();
+        let re = ((norm + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((norm - self.re) / 2.0).max(0.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(self) -> Self {
+        let scale = self.re.exp();
+        Self::new(scale * self.im.cos(), scale * self.im.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let denominator = other.norm_squared();
+        Self::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
+
+/// Measured metals, with their complex index of refraction sampled at the
+/// red, green and blue wavelengths and tabulated over the visible spectrum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetalPreset {
+    Gold,
+    Copper,
+    Aluminum,
+    Silver,
+}
+
+impl MetalPreset {
+    /// The real (eta) and imaginary (k) parts of the index of refraction.
+    pub(crate) fn eta_k(self) -> (Vec3, Vec3) {
+        match self {
+            MetalPreset::Gold => (
+                Vec3::new(0.143, 0.374, 1.442),
+                Vec3::new(3.983, 2.385, 1.603),
+            ),
+            MetalPreset::Copper => (
+                Vec3::new(0.200, 0.924, 1.102),
+                Vec3::new(3.912, 2.452, 2.142),
+            ),
+            MetalPreset::Aluminum => (
+                Vec3::new(1.657, 0.880, 0.521),
+                Vec3::new(9.224, 6.270, 4.837),
+            ),
+            MetalPreset::Silver => (
+                Vec3::new(0.155, 0.117, 0.138),
+                Vec3::new(4.828, 3.122, 2.147),
+            ),
+        }
+    }
+
+    /// The real (eta) and imaginary (k) parts of the index of refraction at
+    /// `lambda` nanometers, interpolated linearly between measurements and
+    /// held constant beyond them.
+    pub(crate) fn eta_k_at(self, lambda: f64) -> (f64, f64) {
+        let table = match self {
+            MetalPreset::Gold => GOLD,
+            MetalPreset::Copper => COPPER,
+            MetalPreset::Aluminum => ALUMINUM,
+            MetalPreset::Silver => SILVER,
+        };
+        let i = table.partition_point(|&(l, _, _)| l < lambda);
+        if i == 0 {
+            let (_, eta, k) = table[0];
+            return (eta, k);
+        }
+        if i == table.len() {
+            let (_, eta, k) = table[i - 1];
+            return (eta, k);
+        }
+        let (l0, eta0, k0) = table[i - 1];
+        let (l1, eta1, k1) = table[i];
+        let t = (lambda - l0) / (l1 - l0);
+        (eta0 + (eta1 - eta0) * t, k0 + (k1 - k0) * t)
+    }
+}
+
+// Measured complex indices of refraction as (nanometers, eta, k), over the
+// visible range: gold and silver from Johnson and Christy, "Optical
+// Constants of the Noble Metals" (1972), copper from pbrt's tables and
+// aluminum from Rakić, "Algorithm for the determination of intrinsic optical
+// constants of metal films" (1995).
+const GOLD: &[(f64, f64, f64)] = &[
+    (354.2, 1.5, 1.866),
+    (367.9, 1.48, 1.895),
+    (381.5, 1.46, 1.933),
+    (397.4, 1.47, 1.952),
+    (413.3, 1.46, 1.958),
+    (430.5, 1.45, 1.948),
+    (450.9, 1.38, 1.914),
+    (471.4, 1.31, 1.849),
+    (495.9, 1.04, 1.833),
+    (520.9, 0.62, 2.081),
+    (548.6, 0.43, 2.455),
+    (582.1, 0.29, 2.863),
+    (616.8, 0.21, 3.272),
+    (659.5, 0.14, 3.697),
+    (704.5, 0.13, 4.103),
+    (756.0, 0.14, 4.542),
+    (821.1, 0.16, 5.083),
+    (892.0, 0.17, 5.663),
+];
+const COPPER: &[(f64, f64, f64)] = &[
+    (354.2, 1.29, 1.916),
+    (359.4, 1.281563, 1.931688),
+    (364.7, 1.27, 1.95),
+    (370.1, 1.249062, 1.972438),
+    (375.7, 1.225, 2.015),
+    (381.5, 1.2, 2.121562),
+    (387.5, 1.18, 2.21),
+    (393.6, 1.174375, 2.177188),
+    (399.9, 1.175, 2.13),
+    (406.5, 1.1775, 2.160063),
+    (413.3, 1.18, 2.21),
+    (420.3, 1.178125, 2.249938),
+    (427.5, 1.175, 2.289),
+    (435.0, 1.172812, 2.326),
+    (442.8, 1.17, 2.362),
+    (450.9, 1.165312, 2.397625),
+    (459.2, 1.16, 2.433),
+    (467.9, 1.155312, 2.469187),
+    (476.9, 1.15, 2.504),
+    (486.2, 1.142812, 2.535875),
+    (495.9, 1.135, 2.564),
+    (506.1, 1.131562, 2.589625),
+    (516.6, 1.12, 2.605),
+    (527.6, 1.092437, 2.595562),
+    (539.1, 1.04, 2.583),
+    (551.0, 0.950375, 2.5765),
+    (563.6, 0.826, 2.599),
+    (576.7, 0.645875, 2.678062),
+    (590.4, 0.468, 2.809),
+    (604.8, 0.35125, 3.01075),
+    (619.9, 0.272, 3.24),
+    (635.8, 0.230813, 3.458187),
+    (652.5, 0.214, 3.67),
+    (670.2, 0.20925, 3.863125),
+    (688.8, 0.213, 4.05),
+    (708.5, 0.21625, 4.239563),
+    (729.3, 0.223, 4.43),
+    (751.4, 0.2365, 4.619563),
+    (774.9, 0.25, 4.817),
+    (799.9, 0.254188, 5.034125),
+    (826.6, 0.26, 5.26),
+    (855.1, 0.28, 5.485625),
+    (885.6, 0.3, 5.717),
+];
+const ALUMINUM: &[(f64, f64, f64)] = &[
+    (300.0, 0.276, 3.61),
+    (350.0, 0.375, 4.24),
+    (400.0, 0.49, 4.86),
+    (450.0, 0.618, 5.47),
+    (500.0, 0.769, 6.08),
+    (550.0, 0.958, 6.69),
+    (600.0, 1.2, 7.26),
+    (650.0, 1.47, 7.79),
+    (700.0, 1.83, 8.31),
+    (750.0, 2.4, 8.62),
+    (800.0, 2.8, 8.45),
+    (850.0, 2.58, 8.3),
+];
+const SILVER: &[(f64, f64, f64)] = &[
+    (354.2, 0.1, 1.419),
+    (367.9, 0.07, 1.657),
+    (381.5, 0.05, 1.864),
+    (397.4, 0.05, 2.07),
+    (413.3, 0.05, 2.275),
+    (430.5, 0.04, 2.462),
+    (450.9, 0.04, 2.657),
+    (471.4, 0.05, 2.869),
+    (495.9, 0.05, 3.093),
+    (520.9, 0.05, 3.324),
+    (548.6, 0.06, 3.586),
+    (582.1, 0.05, 3.858),
+    (616.8, 0.06, 4.152),
+    (659.5, 0.05, 4.483),
+    (704.5, 0.04, 4.838),
+    (756.0, 0.03, 5.242),
+    (821.1, 0.04, 5.727),
+    (892.0, 0.04, 6.312),
+];
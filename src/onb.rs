@@ -0,0 +1,46 @@
+use crate::vec3::Vec3;
+
+/// An orthonormal basis with `w` along a given direction.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Onb {
+    pub(crate) u: Vec3,
+    pub(crate) v: Vec3,
+    pub(crate) w: Vec3,
+}
+
+impl Onb {
+    pub(crate) fn new(w: Vec3) -> Self {
+        // Duff et al., "Building an Orthonormal Basis, Revisited"
+        let w = w.unit_vector();
+        let sign = 1.0_f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        let u = Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x);
+        let v = Vec3::new(b, sign + w.y * w.y * a, -w.y);
+        Self { u, v, w }
+    }
+
+    /// A frame with `w` along the unit `normal` and `u` along the part of
+    /// `tangent` perpendicular to it, for anisotropic shading. Falls back to
+    /// an arbitrary `u` when the tangent is zero or parallel to the normal.
+    pub(crate) fn from_normal_tangent(normal: Vec3, tangent: Vec3) -> Self {
+        let tangent = tangent - normal * normal.dot(tangent);
+        if tangent.is_near_zero() {
+            return Self::new(normal);
+        }
+        let u = tangent.unit_vector();
+        Self {
+            u,
+            v: normal.cross(u),
+            w: normal,
+        }
+    }
+
+    pub(crate) fn to_world(self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    pub(crate) fn to_local(self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
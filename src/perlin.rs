@@ -0,0 +1,93 @@
+use crate::common::Point;
+use crate::vec3::Vec3;
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient noise over 3D space, smooth and roughly in [-1, 1].
+pub(crate) struct Perlin {
+    random_vectors: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub(crate) fn new() -> Self {
+        Self {
+            random_vectors: (0..POINT_COUNT)
+                .map(|_| Vec3::random_in_range(-1.0, 1.0).unit_vector())
+                .collect(),
+            perm_x: generate_permutation(),
+            perm_y: generate_permutation(),
+            perm_z: generate_permutation(),
+        }
+    }
+
+    pub(crate) fn noise(&self, p: Point) -> f64 {
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
+
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+
+        let mut c = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, value) in row.iter_mut().enumerate() {
+                    *value = self.random_vectors[self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize]];
+                }
+            }
+        }
+
+        perlin_interpolation(&c, u, v, w)
+    }
+
+    /// Sums `depth` octaves of noise, each at twice the frequency and half
+    /// the amplitude of the last.
+    pub(crate) fn turbulence(&self, p: Point, depth: usize) -> f64 {
+        let mut accumulated = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accumulated += weight * self.noise(p);
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        accumulated.abs()
+    }
+}
+
+fn generate_permutation() -> Vec<usize> {
+    let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+    for i in (1..POINT_COUNT).rev() {
+        let target = rand::random::<usize>() % (i + 1);
+        p.swap(i, target);
+    }
+    p
+}
+
+fn perlin_interpolation(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    // Hermite smoothing hides the grid.
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+
+    let mut accumulated = 0.0;
+    for (i, plane) in c.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, gradient) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let weight = Vec3::new(u - fi, v - fj, w - fk);
+                accumulated += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * gradient.dot(weight);
+            }
+        }
+    }
+    accumulated
+}
@@ -0,0 +1,298 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::Point;
+use crate::interval::Interval;
+use crate::light::Light;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable, World};
+use crate::vec3::Vec3;
+
+// The plane through `q` spanned by `u` and `v`. Hits are reported in the
+// (alpha, beta) coordinates of that basis, so that p = q + alpha u + beta v.
+#[derive(Debug, Clone, Copy)]
+struct PlaneFrame {
+    q: Point,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    d: f64,
+    w: Vec3,
+}
+
+impl PlaneFrame {
+    fn new(q: Point, u: Vec3, v: Vec3) -> Self {
+        let n = u.cross(v);
+        let normal = n.unit_vector();
+        Self {
+            q,
+            u,
+            v,
+            normal,
+            d: normal.dot(q),
+            w: n / n.dot(n),
+        }
+    }
+
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<(f64, Point, f64, f64)> {
+        let denom = self.normal.dot(ray.direction);
+        if denom.abs() < 1e-8 {
+            // parallel to the plane
+            return None;
+        }
+
+        let t = (self.d - self.normal.dot(ray.origin)) / denom;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let p = ray.at(t);
+        let planar_hit = p - self.q;
+        let alpha = self.w.dot(planar_hit.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar_hit));
+        Some((t, p, alpha, beta))
+    }
+}
+
+/// A parallelogram with corner `q` and edges `u` and `v`. The front face is
+/// on the side of `u x v`.
+pub(crate) struct Quad {
+    frame: PlaneFrame,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Quad {
+    pub(crate) fn new(q: Point, u: Vec3, v: Vec3, material: Arc<Material>) -> Self {
+        let bbox = Aabb::enclosing(
+            Aabb::from_points(q, q + u + v),
+            Aabb::from_points(q + u, q + v),
+        );
+        Self {
+            frame: PlaneFrame::new(q, u, v),
+            material,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t, p, alpha, beta) = self.frame.hit(ray, &ray_t)?;
+        let unit = Interval::new(0.0, 1.0);
+        if !unit.contains(alpha) || !unit.contains(beta) {
+            return None;
+        }
+        Some(
+            HitRecord::new(
+                ray,
+                p,
+                self.frame.normal,
+                t,
+                (alpha, beta),
+                self.material.clone(),
+            )
+            .with_derivatives(self.frame.u, self.frame.v),
+        )
+    }
+
+    // split along the diagonal from q, keeping the front face
+    fn triangle_lights(&self) -> Option<Vec<Light>> {
+        let PlaneFrame { q, u, v, .. } = self.frame;
+        Light::emissive_triangles(
+            &self.material,
+            [[q, q + u, q + u + v], [q, q + u + v, q + v]],
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// A triangle with vertices `a`, `b` and `c`, front facing when they appear
+/// counterclockwise. UVs are the barycentric coordinates of `b` and `c`.
+pub(crate) struct Triangle {
+    frame: PlaneFrame,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Triangle {
+    pub(crate) fn new(a: Point, b: Point, c: Point, material: Arc<Material>) -> Self {
+        let bbox = Aabb::enclosing(Aabb::from_points(a, b), Aabb::from_points(a, c));
+        Self {
+            frame: PlaneFrame::new(a, b - a, c - a),
+            material,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t, p, alpha, beta) = self.frame.hit(ray, &ray_t)?;
+        if alpha < 0.0 || beta < 0.0 || alpha + beta > 1.0 {
+            return None;
+        }
+        Some(
+            HitRecord::new(
+                ray,
+                p,
+                self.frame.normal,
+                t,
+                (alpha, beta),
+                self.material.clone(),
+            )
+            .with_derivatives(self.frame.u, self.frame.v),
+        )
+    }
+
+    fn triangle_lights(&self) -> Option<Vec<Light>> {
+        let PlaneFrame { q, u, v, .. } = self.frame;
+        Light::emissive_triangles(&self.material, [[q, q + u, q + v]])
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// A flat disk facing `normal`. u is the angle around the center and v runs
+/// from 1 at the center to 0 on the rim.
+pub(crate) struct Disk {
+    frame: PlaneFrame,
+    radius: f64,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Disk {
+    pub(crate) fn new(center: Point, normal: Vec3, radius: f64, material: Arc<Material>) -> Self {
+        let normal = normal.unit_vector();
+        let Onb { u, v, .. } = Onb::new(normal);
+        Self {
+            frame: PlaneFrame::new(center, u, v),
+            radius,
+            material,
+            bbox: Aabb::from_disk(center, normal, radius),
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t, p, alpha, beta) = self.frame.hit(ray, &ray_t)?;
+        let r = (alpha * alpha + beta * beta).sqrt();
+        if r > self.radius {
+            return None;
+        }
+        let phi = beta.atan2(alpha);
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+        let uv = (phi / (2.0 * PI), 1.0 - r / self.radius);
+        let dpdu = 2.0 * PI * (self.frame.v * alpha - self.frame.u * beta);
+        let dpdv = -self.radius * (self.frame.u * alpha + self.frame.v * beta) / r.max(1e-8);
+        Some(
+            HitRecord::new(ray, p, self.frame.normal, t, uv, self.material.clone())
+                .with_derivatives(dpdu, dpdv),
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// An infinite plane through `point` facing `normal`. UVs are the distances
+/// from `point` along a tangent basis, so they grow without bound.
+pub(crate) struct Plane {
+    frame: PlaneFrame,
+    material: Arc<Material>,
+}
+
+impl Plane {
+    pub(crate) fn new(point: Point, normal: Vec3, material: Arc<Material>) -> Self {
+        let Onb { u, v, .. } = Onb::new(normal);
+        Self {
+            frame: PlaneFrame::new(point, u, v),
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t, p, alpha, beta) = self.frame.hit(ray, &ray_t)?;
+        Some(
+            HitRecord::new(
+                ray,
+                p,
+                self.frame.normal,
+                t,
+                (alpha, beta),
+                self.material.clone(),
+            )
+            .with_derivatives(self.frame.u, self.frame.v),
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::UNIVERSE
+    }
+}
+
+/// The axis-aligned box with opposite corners `a` and `b`, made of six
+/// outward facing quads.
+pub(crate) fn cuboid(a: Point, b: Point, material: Arc<Material>) -> World {
+    let mut sides = World::new();
+
+    let min = Point::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+    let max = Point::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+
+    let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+    let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+    let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+
+    // front
+    sides.add(Quad::new(
+        Point::new(min.x, min.y, max.z),
+        dx,
+        dy,
+        material.clone(),
+    ));
+    // right
+    sides.add(Quad::new(
+        Point::new(max.x, min.y, max.z),
+        -dz,
+        dy,
+        material.clone(),
+    ));
+    // back
+    sides.add(Quad::new(
+        Point::new(max.x, min.y, min.z),
+        -dx,
+        dy,
+        material.clone(),
+    ));
+    // left
+    sides.add(Quad::new(
+        Point::new(min.x, min.y, min.z),
+        dz,
+        dy,
+        material.clone(),
+    ));
+    // top
+    sides.add(Quad::new(
+        Point::new(min.x, max.y, max.z),
+        dx,
+        -dz,
+        material.clone(),
+    ));
+    // bottom
+    sides.add(Quad::new(Point::new(min.x, min.y, min.z), dx, dz, material));
+
+    sides
+}
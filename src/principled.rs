@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::bsdf::LobeFlags;
+use crate::common::invalid_data;
+use crate::microfacet::{
+    fr_dielectric, fr_schlick, reflect, refract, refracted_half_vector, TrowbridgeReitz,
+};
+use crate::ray::Ray;
+use crate::scene::HitRecord;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+
+/// An uber-material after Burley's "Physically Based Shading at Disney",
+/// with a specular layer over a blend of metal, diffuse and transmissive
+/// bases, and an optional clearcoat on top. All parameters but the index of
+/// refraction are textures, with scalars read from the first channel and
+/// mostly in [0, 1]. `specular` scales the dielectric reflectance given by
+/// `ior`, as in OpenPBR. Where `opacity` is below one, rays randomly pass
+/// through the surface as if it weren't there.
+#[derive(Clone)]
+pub(crate) struct Principled {
+    pub(crate) base_color: Texture,
+    pub(crate) metallic: Texture,
+    pub(crate) roughness: Texture,
+    pub(crate) specular: Texture,
+    pub(crate) specular_tint: Texture,
+    pub(crate) anisotropic: Texture,
+    pub(crate) sheen: Texture,
+    pub(crate) sheen_tint: Texture,
+    pub(crate) clearcoat: Texture,
+    pub(crate) clearcoat_gloss: Texture,
+    pub(crate) transmission: Texture,
+    pub(crate) opacity: Texture,
+    pub(crate) ior: f64,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: Texture::constant(0.8),
+            metallic: Texture::constant(0.0),
+            roughness: Texture::constant(0.5),
+            specular: Texture::constant(1.0),
+            specular_tint: Texture::constant(0.0),
+            anisotropic: Texture::constant(0.0),
+            sheen: Texture::constant(0.0),
+            sheen_tint: Texture::constant(0.5),
+            clearcoat: Texture::constant(0.0),
+            clearcoat_gloss: Texture::constant(1.0),
+            transmission: Texture::constant(0.0),
+            opacity: Texture::constant(1.0),
+            ior: 1.5,
+        }
+    }
+}
+
+impl Principled {
+    /// Maps the glTF metallic-roughness model: the base color factor times
+    /// the base color texture, and the metallic and roughness factors times
+    /// the blue and green channels of the metallic-roughness texture.
+    pub(crate) fn from_gltf_metallic_roughness(
+        base_color_factor: Vec3,
+        base_color_texture: Option<Texture>,
+        metallic_factor: f64,
+        roughness_factor: f64,
+        metallic_roughness_texture: Option<Texture>,
+    ) -> Self {
+        let scaled = |texture: Option<Texture>, factor: Vec3| match texture {
+            Some(texture) => Texture::Scale {
+                texture: Arc::new(texture),
+                factor,
+            },
+            None => Texture::Constant(factor),
+        };
+        let channel = |channel: usize, factor: f64| {
+            let texture = metallic_roughness_texture
+                .clone()
+                .map(|texture| Texture::Channel {
+                    texture: Arc::new(texture),
+                    channel,
+                });
+            scaled(texture, Vec3::new(factor, factor, factor))
+        };
+        Self {
+            base_color: scaled(base_color_texture, base_color_factor),
+            metallic: channel(2, metallic_factor),
+            roughness: channel(1, roughness_factor),
+            ..Self::default()
+        }
+    }
+
+    /// The BSDF with the textured parameters looked up at `hit`, and colors
+    /// in the channels `ray_in` carries.
+    pub(crate) fn bsdf(&self, ray_in: &Ray, hit: &HitRecord) -> PrincipledBsdf {
+        let scalar = |texture: &Texture| texture.scalar(hit.u, hit.v, hit.p).clamp(0.0, 1.0);
+        let base_color = self.base_color.value(hit.u, hit.v, hit.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let specular = scalar(&self.specular);
+        let sheen = scalar(&self.sheen);
+        let clearcoat = scalar(&self.clearcoat);
+        let transmission = scalar(&self.transmission);
+
+        let base_luminance = luminance(base_color);
+        let tint = if base_luminance > 0.0 {
+            base_color / base_luminance
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        };
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let lerp = |t: f64, a: Vec3, b: Vec3| a * (1.0 - t) + b * t;
+
+        let aspect = (1.0 - 0.9 * scalar(&self.anisotropic)).sqrt();
+        let alpha = roughness * roughness;
+        let distribution =
+            TrowbridgeReitz::new((alpha / aspect).max(0.001), (alpha * aspect).max(0.001));
+        let clearcoat_gloss = scalar(&self.clearcoat_gloss);
+        let transmission_color = Vec3::new(
+            base_color.x.max(0.0).sqrt(),
+            base_color.y.max(0.0).sqrt(),
+            base_color.z.max(0.0).sqrt(),
+        );
+
+        // Sampling only needs to roughly follow how much each lobe reflects.
+        let lobe_weights = [
+            (1.0 - metallic) * ((1.0 - transmission) * base_luminance + 0.25 * sheen),
+            metallic + (1.0 - metallic) * 0.25 * specular,
+            0.25 * clearcoat,
+            (1.0 - metallic) * transmission * luminance(transmission_color),
+        ];
+        let total: f64 = lobe_weights.iter().sum();
+
+        PrincipledBsdf {
+            base_color: ray_in.color(base_color),
+            transmission_color: ray_in.color(transmission_color),
+            metallic,
+            roughness,
+            specular_color: ray_in.color(lerp(scalar(&self.specular_tint), white, tint) * specular),
+            specular,
+            sheen_color: ray_in.color(lerp(scalar(&self.sheen_tint), white, tint) * sheen),
+            clearcoat,
+            transmission,
+            eta: if hit.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+            distribution,
+            clearcoat_alpha: 0.1 * (1.0 - clearcoat_gloss) + 0.001 * clearcoat_gloss,
+            lobe_weights: if total > 0.0 {
+                lobe_weights.map(|w| w / total)
+            } else {
+                [0.0; 4]
+            },
+        }
+    }
+}
+
+/// The principled BSDF with its parameters looked up at a hit. Directions
+/// are in the local shading frame, with wo on the side of +z.
+pub(crate) struct PrincipledBsdf {
+    base_color: Vec3,
+    transmission_color: Vec3,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    specular_color: Vec3,
+    sheen_color: Vec3,
+    clearcoat: f64,
+    transmission: f64,
+    // index of refraction on the far side over the near side
+    eta: f64,
+    distribution: TrowbridgeReitz,
+    clearcoat_alpha: f64,
+    // chances of sampling the diffuse and sheen, specular, clearcoat and
+    // transmission lobes
+    lobe_weights: [f64; 4],
+}
+
+impl PrincipledBsdf {
+    // Reflectance of the specular layer, which blends from tinted dielectric
+    // Fresnel to the base color at normal incidence as it gets metallic.
+    fn fresnel(&self, cos_theta: f64) -> Vec3 {
+        let dielectric = self.specular_color * fr_dielectric(cos_theta, self.eta);
+        let metal = fr_schlick(cos_theta, self.base_color);
+        dielectric * (1.0 - self.metallic) + metal * self.metallic
+    }
+
+    /// The BSDF value, without the cosine of `wi`.
+    pub(crate) fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+
+        if cos_i < 0.0 {
+            return match refracted_half_vector(wo, wi, self.eta) {
+                Some((wm, denominator)) => {
+                    let transmittance =
+                        (1.0 - self.specular * fr_dielectric(wo.dot(wm), self.eta)).max(0.0);
+                    self.transmission_color
+                        * ((1.0 - self.metallic)
+                            * self.transmission
+                            * transmittance
+                            * self.distribution.d(wm)
+                            * self.distribution.g(wo, wi)
+                            * (wi.dot(wm) * wo.dot(wm) / (cos_i * cos_o * denominator)).abs())
+                }
+                None => Vec3::new(0.0, 0.0, 0.0),
+            };
+        }
+
+        let wh = (wo + wi).unit_vector();
+        let cos_d = wi.dot(wh);
+
+        // Burley's diffuse, with retro-reflection at grazing angles for
+        // rough surfaces, and sheen for cloth.
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let schlick_weight = |cos: f64| (1.0 - cos).clamp(0.0, 1.0).powi(5);
+        let diffuse = self.base_color
+            * ((1.0 + (fd90 - 1.0) * schlick_weight(cos_i))
+                * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o))
+                / PI);
+        let sheen = self.sheen_color * schlick_weight(cos_d);
+        let mut f = (diffuse * (1.0 - self.transmission) + sheen) * (1.0 - self.metallic);
+
+        f += self.fresnel(wo.dot(wh))
+            * (self.distribution.d(wh) * self.distribution.g(wo, wi) / (4.0 * cos_o * cos_i));
+
+        if self.clearcoat > 0.0 {
+            let clearcoat_g = TrowbridgeReitz::new(0.25, 0.25).g(wo, wi);
+            let clearcoat_f = fr_schlick(wo.dot(wh), Vec3::new(0.04, 0.04, 0.04)).x;
+            f += Vec3::new(1.0, 1.0, 1.0)
+                * (0.25
+                    * self.clearcoat
+                    * gtr1(wh.z, self.clearcoat_alpha)
+                    * clearcoat_f
+                    * clearcoat_g
+                    / (4.0 * cos_o * cos_i));
+        }
+        f
+    }
+
+    pub(crate) fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let [diffuse, specular, clearcoat, transmission] = self.lobe_weights;
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        if wi.z < 0.0 {
+            return match refracted_half_vector(wo, wi, self.eta) {
+                Some((wm, denominator)) => {
+                    transmission * self.distribution.d_visible(wo, wm) * wi.dot(wm).abs()
+                        / denominator
+                }
+                None => 0.0,
+            };
+        }
+
+        let wh = (wo + wi).unit_vector();
+        let jacobian = 1.0 / (4.0 * wo.dot(wh));
+        diffuse * wi.z / PI
+            + specular * self.distribution.d_visible(wo, wh) * jacobian
+            + clearcoat * gtr1(wh.z, self.clearcoat_alpha) * wh.z * jacobian
+    }
+
+    /// Samples `wi` by picking a lobe in proportion to `lobe_weights` with
+    /// `uc`, and a direction in it with `u`, and returns it with the kind of
+    /// lobe picked.
+    pub(crate) fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<(Vec3, LobeFlags)> {
+        let [diffuse, specular, clearcoat, _] = self.lobe_weights;
+        let (wi, flags) = if uc < diffuse {
+            (Vec3::cosine_direction(u), LobeFlags::DIFFUSE)
+        } else if uc < diffuse + specular {
+            (
+                reflect(wo, self.distribution.sample_wm(wo, u)),
+                LobeFlags::GLOSSY,
+            )
+        } else if uc < diffuse + specular + clearcoat {
+            let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+            let cos_theta = ((1.0 - a2.powf(1.0 - u.0)) / (1.0 - a2)).max(0.0).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.1;
+            let wh = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            (reflect(wo, wh), LobeFlags::GLOSSY)
+        } else {
+            let wi = refract(wo, self.distribution.sample_wm(wo, u), self.eta)?;
+            return (wi.z < 0.0).then_some((wi, LobeFlags::GLOSSY | LobeFlags::TRANSMISSION));
+        };
+        (wi.z > 0.0).then_some((wi, flags | LobeFlags::REFLECTION))
+    }
+}
+
+// The generalized Trowbridge-Reitz distribution with exponent 1, which has
+// the long tails used for clearcoat.
+fn gtr1(cos_theta: f64, alpha: f64) -> f64 {
+    if alpha >= 1.0 {
+        return 1.0 / PI;
+    }
+    let a2 = alpha * alpha;
+    let t = 1.0 + (a2 - 1.0) * cos_theta * cos_theta;
+    (a2 - 1.0) / (PI * a2.ln() * t)
+}
+
+fn luminance(color: Vec3) -> f64 {
+    0.3 * color.x + 0.6 * color.y + 0.1 * color.z
+}
+
+/// Loads the materials of a Wavefront MTL file by name. Besides the classic
+/// statements (`Kd`, `Ks`, `Ns`, `Ni`, `illum`, `d`, `Tr`, `map_Kd` and
+/// `map_d`), it reads the
+/// PBR extension (`Pr`, `Pm`, `Ps`, `Pc`, `Pcr`, `aniso` and their `map_`
+/// forms), which take precedence. Texture paths are relative to the file.
+pub(crate) fn load_mtl(path: impl AsRef<Path>) -> io::Result<HashMap<String, Principled>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlStatements)> = None;
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let Some((keyword, rest)) = line
+            .split_once(char::is_whitespace)
+            .or((!line.is_empty()).then_some((line, "")))
+        else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            if let Some((name, statements)) = current.take() {
+                materials.insert(name, statements.to_principled(directory)?);
+            }
+            current = Some((rest.trim().to_string(), MtlStatements::default()));
+        } else if let Some((_, statements)) = current.as_mut() {
+            statements.add(keyword, rest)?;
+        }
+    }
+    if let Some((name, statements)) = current {
+        materials.insert(name, statements.to_principled(directory)?);
+    }
+    Ok(materials)
+}
+
+// The statements with numeric values that are mapped to a Principled.
+const NUMERIC_STATEMENTS: [&str; 13] = [
+    "Kd", "Ks", "Ns", "Ni", "illum", "d", "Tr", "Pr", "Pm", "Ps", "Pc", "Pcr", "aniso",
+];
+
+// The statements of one material, gathered before mapping them because
+// they may come in any order.
+#[derive(Default)]
+struct MtlStatements {
+    values: HashMap<String, Vec<f64>>,
+    maps: HashMap<String, String>,
+}
+
+impl MtlStatements {
+    fn add(&mut self, keyword: &str, rest: &str) -> io::Result<()> {
+        if let Some(map) = keyword.strip_prefix("map_") {
+            // Options such as -s come first, so the file name is last.
+            if let Some(file) = rest.split_whitespace().last() {
+                self.maps.insert(map.to_string(), file.to_string());
+            }
+            return Ok(());
+        }
+        // Other statements, like Ka or the names of reflection maps, aren't
+        // used, so they aren't checked either.
+        if !NUMERIC_STATEMENTS.contains(&keyword) {
+            return Ok(());
+        }
+        let values = rest
+            .split_whitespace()
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| invalid_data(&format!("invalid value for {keyword}: {value}")))
+            })
+            .collect::<io::Result<Vec<f64>>>()?;
+        self.values.insert(keyword.to_string(), values);
+        Ok(())
+    }
+
+    fn scalar(&self, keyword: &str) -> Option<f64> {
+        self.values.get(keyword)?.first().copied()
+    }
+
+    fn color(&self, keyword: &str) -> Option<Vec3> {
+        match self.values.get(keyword)?.as_slice() {
+            [r, g, b, ..] => Some(Vec3::new(*r, *g, *b)),
+            [value, ..] => Some(Vec3::new(*value, *value, *value)),
+            [] => None,
+        }
+    }
+
+    // A texture from `map_<keyword>` times the value of `keyword`, or just
+    // the value, or `default` without either.
+    fn texture(
+        &self,
+        directory: &Path,
+        keyword: &str,
+        default: Vec3,
+        srgb: bool,
+    ) -> io::Result<Texture> {
+        let factor = self.color(keyword).unwrap_or(default);
+        match self.maps.get(keyword) {
+            Some(file) => {
+                let texture =
+                    Texture::image(directory.join(file), srgb).map_err(io::Error::other)?;
+                Ok(Texture::Scale {
+                    texture: Arc::new(texture),
+                    factor,
+                })
+            }
+            None => Ok(Texture::Constant(factor)),
+        }
+    }
+
+    fn to_principled(&self, directory: &Path) -> io::Result<Principled> {
+        let gray = |value: f64| Vec3::new(value, value, value);
+        let mut principled = Principled {
+            base_color: self.texture(directory, "Kd", gray(0.8), true)?,
+            ..Principled::default()
+        };
+
+        // Phong exponents map to roughness through alpha = sqrt(2 / (n + 2)).
+        if let Some(shininess) = self.scalar("Ns") {
+            principled.roughness = Texture::constant((2.0 / (shininess.max(0.0) + 2.0)).powf(0.25));
+        }
+        if let Some(ks) = self.color("Ks") {
+            principled.specular = Texture::constant(ks.x.max(ks.y).max(ks.z).clamp(0.0, 1.0));
+        }
+        if let Some(ior) = self.scalar("Ni").filter(|&ior| ior > 0.0) {
+            principled.ior = ior;
+        }
+        // illumination models with refraction
+        if matches!(self.scalar("illum").map(|i| i as i64), Some(4 | 6 | 7 | 9)) {
+            principled.transmission = Texture::constant(1.0);
+        }
+
+        let pbr = [
+            ("Pr", &mut principled.roughness),
+            ("Pm", &mut principled.metallic),
+            ("Ps", &mut principled.sheen),
+            ("Pc", &mut principled.clearcoat),
+            ("aniso", &mut principled.anisotropic),
+        ];
+        for (keyword, texture) in pbr {
+            if self.values.contains_key(keyword) || self.maps.contains_key(keyword) {
+                *texture = self.texture(directory, keyword, gray(1.0), false)?;
+            }
+        }
+        if let Some(clearcoat_roughness) = self.scalar("Pcr") {
+            principled.clearcoat_gloss = Texture::constant(1.0 - clearcoat_roughness);
+        }
+
+        // Dissolve, or its complement transparency, scales the alpha channel
+        // of map_d, or its gray level for images without alpha.
+        let dissolve = self
+            .scalar("d")
+            .or(self.scalar("Tr").map(|tr| 1.0 - tr))
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+        principled.opacity = match self.maps.get("d") {
+            Some(file) => Texture::Scale {
+                texture: Arc::new(
+                    Texture::image_alpha(directory.join(file)).map_err(io::Error::other)?,
+                ),
+                factor: gray(dissolve),
+            },
+            None => Texture::constant(dissolve),
+        };
+        Ok(principled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{temp_file, Point};
+
+    fn at_origin(texture: &Texture) -> f64 {
+        texture.scalar(0.5, 0.5, Point::new(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn mtl_statements_map_to_principled() {
+        let image = image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 255, 255, 51]));
+        let alpha = temp_file("alpha.png", &[]);
+        image.save(&alpha).unwrap();
+        let mtl = format!(
+            "# two materials\n\
+             newmtl glass\n\
+             Kd 0.2 0.4 0.6\n\
+             Ka 0.1 0.1 0.1\n\
+             Ni 1.5\n\
+             illum 7\n\
+             refl -type sphere chrome.png\n\
+             newmtl metal\n\
+             Ns 98\n\
+             Pm 1\n\
+             Pcr 0.25\n\
+             d 0.5\n\
+             map_d -clamp on {}\n",
+            alpha.file_name().unwrap().to_str().unwrap(),
+        );
+        let path = temp_file("materials.mtl", mtl.as_bytes());
+        let materials = load_mtl(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        fs::remove_file(alpha).unwrap();
+
+        let glass = &materials["glass"];
+        let p = Point::new(0.0, 0.0, 0.0);
+        let base = glass.base_color.value(0.0, 0.0, p);
+        assert!((base - Vec3::new(0.2, 0.4, 0.6)).length() < 1e-12);
+        assert_eq!(glass.ior, 1.5);
+        assert_eq!(at_origin(&glass.transmission), 1.0);
+
+        let metal = &materials["metal"];
+        assert!((at_origin(&metal.roughness) - 0.02_f64.powf(0.25)).abs() < 1e-12);
+        assert_eq!(at_origin(&metal.metallic), 1.0);
+        assert_eq!(at_origin(&metal.clearcoat_gloss), 0.75);
+        assert!((at_origin(&metal.opacity) - 0.1).abs() < 1e-2);
+    }
+
+    #[test]
+    fn invalid_mtl_values_are_errors() {
+        let path = temp_file("invalid.mtl", b"newmtl broken\nNs shiny\n");
+        let error = load_mtl(&path).err().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn gltf_factors_scale_the_metallic_roughness_channels() {
+        let principled = Principled::from_gltf_metallic_roughness(
+            Vec3::new(1.0, 0.5, 0.25),
+            None,
+            0.5,
+            1.0,
+            Some(Texture::Constant(Vec3::new(0.1, 0.6, 0.8))),
+        );
+        assert!((at_origin(&principled.metallic) - 0.4).abs() < 1e-12);
+        assert!((at_origin(&principled.roughness) - 0.6).abs() < 1e-12);
+        let base = principled
+            .base_color
+            .value(0.0, 0.0, Point::new(0.0, 0.0, 0.0));
+        assert!((base - Vec3::new(1.0, 0.5, 0.25)).length() < 1e-12);
+    }
+}
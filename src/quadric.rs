@@ -0,0 +1,723 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::Point;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::vec3::Vec3;
+
+// The local frame of a shape built around an axis, with the shape's base at
+// the origin and the axis along +z.
+#[derive(Debug, Clone, Copy)]
+struct AxisFrame {
+    base: Point,
+    onb: Onb,
+}
+
+impl AxisFrame {
+    fn new(base: Point, axis: Vec3) -> Self {
+        Self {
+            base,
+            onb: Onb::new(axis),
+        }
+    }
+
+    fn to_local(self, ray: &Ray) -> (Vec3, Vec3) {
+        (
+            self.onb.to_local(ray.origin - self.base),
+            self.onb.to_local(ray.direction),
+        )
+    }
+}
+
+// A candidate intersection in local coordinates, with how the point moves
+// with v. Every shape here has u going around the axis, so the motion with u
+// is the same for all.
+struct Candidate {
+    t: f64,
+    normal: Vec3,
+    uv: (f64, f64),
+    dpdv: Vec3,
+}
+
+// Picks the closest candidate inside `ray_t` and builds its hit record.
+fn closest_hit(
+    ray: &Ray,
+    ray_t: Interval,
+    frame: &AxisFrame,
+    candidates: impl IntoIterator<Item = Candidate>,
+    material: &Arc<Material>,
+) -> Option<HitRecord> {
+    let closest = candidates
+        .into_iter()
+        .filter(|c| ray_t.surrounds(c.t))
+        .min_by(|a, b| a.t.total_cmp(&b.t))?;
+    let normal = frame.onb.to_world(closest.normal).unit_vector();
+    let (o, d) = frame.to_local(ray);
+    let p = o + d * closest.t;
+    let dpdu = frame.onb.to_world(2.0 * PI * Vec3::new(-p.y, p.x, 0.0));
+    Some(
+        HitRecord::new(
+            ray,
+            ray.at(closest.t),
+            normal,
+            closest.t,
+            closest.uv,
+            material.clone(),
+        )
+        .with_derivatives(dpdu, frame.onb.to_world(closest.dpdv)),
+    )
+}
+
+// The unit vector pointing away from the axis towards `p`, or zero on it.
+fn radial(p: Vec3) -> Vec3 {
+    let rho = (p.x * p.x + p.y * p.y).sqrt();
+    if rho > 0.0 {
+        Vec3::new(p.x / rho, p.y / rho, 0.0)
+    } else {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+}
+
+fn angle_around_axis(p: Vec3) -> f64 {
+    let phi = p.y.atan2(p.x);
+    if phi < 0.0 {
+        phi + 2.0 * PI
+    } else {
+        phi
+    }
+}
+
+// A cap at height `z` of the given radius, facing `normal_z` along the axis.
+fn cap(o: Vec3, d: Vec3, z: f64, radius: f64, normal_z: f64) -> Option<Candidate> {
+    if d.z == 0.0 || radius <= 0.0 {
+        return None;
+    }
+    let t = (z - o.z) / d.z;
+    let p = o + d * t;
+    let r2 = p.x * p.x + p.y * p.y;
+    if r2 > radius * radius {
+        return None;
+    }
+    Some(Candidate {
+        t,
+        normal: Vec3::new(0.0, 0.0, normal_z),
+        uv: (angle_around_axis(p) / (2.0 * PI), r2.sqrt() / radius),
+        dpdv: radial(p) * radius,
+    })
+}
+
+/// A cylinder of the given radius around the segment from `base` to
+/// `base + axis`, optionally closed by flat caps on both ends.
+pub(crate) struct Cylinder {
+    frame: AxisFrame,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Cylinder {
+    pub(crate) fn new(
+        base: Point,
+        axis: Vec3,
+        radius: f64,
+        capped: bool,
+        material: Arc<Material>,
+    ) -> Self {
+        let direction = axis.unit_vector();
+        let bbox = Aabb::enclosing(
+            Aabb::from_disk(base, direction, radius),
+            Aabb::from_disk(base + axis, direction, radius),
+        );
+        Self {
+            frame: AxisFrame::new(base, axis),
+            radius,
+            height: axis.length(),
+            capped,
+            material,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (o, d) = self.frame.to_local(ray);
+
+        let a = d.x * d.x + d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.y * d.y);
+        let c = o.x * o.x + o.y * o.y - self.radius * self.radius;
+        let side = solve_quadratic(a, b, c)
+            .into_iter()
+            .flatten()
+            .filter_map(|t| {
+                let p = o + d * t;
+                if p.z < 0.0 || p.z > self.height {
+                    return None;
+                }
+                Some(Candidate {
+                    t,
+                    normal: Vec3::new(p.x, p.y, 0.0),
+                    uv: (angle_around_axis(p) / (2.0 * PI), p.z / self.height),
+                    dpdv: Vec3::new(0.0, 0.0, self.height),
+                })
+            });
+
+        let caps = if self.capped {
+            [
+                cap(o, d, 0.0, self.radius, -1.0),
+                cap(o, d, self.height, self.radius, 1.0),
+            ]
+        } else {
+            [None, None]
+        };
+
+        closest_hit(
+            ray,
+            ray_t,
+            &self.frame,
+            side.chain(caps.into_iter().flatten()),
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// A cone or truncated cone around the segment from `base` to `base + axis`,
+/// with `base_radius` at the base and `top_radius` at the top. A top radius of
+/// zero makes a pointed cone.
+pub(crate) struct Cone {
+    frame: AxisFrame,
+    base_radius: f64,
+    top_radius: f64,
+    height: f64,
+    capped: bool,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Cone {
+    pub(crate) fn new(
+        base: Point,
+        axis: Vec3,
+        base_radius: f64,
+        top_radius: f64,
+        capped: bool,
+        material: Arc<Material>,
+    ) -> Self {
+        let direction = axis.unit_vector();
+        let bbox = Aabb::enclosing(
+            Aabb::from_disk(base, direction, base_radius),
+            Aabb::from_disk(base + axis, direction, top_radius),
+        );
+        Self {
+            frame: AxisFrame::new(base, axis),
+            base_radius,
+            top_radius,
+            height: axis.length(),
+            capped,
+            material,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (o, d) = self.frame.to_local(ray);
+
+        // x^2 + y^2 = r(z)^2 with r(z) = base_radius + slope z
+        let slope = (self.top_radius - self.base_radius) / self.height;
+        let r_o = self.base_radius + slope * o.z;
+        let a = d.x * d.x + d.y * d.y - slope * slope * d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.y * d.y - slope * d.z * r_o);
+        let c = o.x * o.x + o.y * o.y - r_o * r_o;
+        let side = solve_quadratic(a, b, c)
+            .into_iter()
+            .flatten()
+            .filter_map(|t| {
+                let p = o + d * t;
+                if p.z < 0.0 || p.z > self.height {
+                    return None;
+                }
+                let r = self.base_radius + slope * p.z;
+                Some(Candidate {
+                    t,
+                    normal: Vec3::new(p.x, p.y, -slope * r),
+                    uv: (angle_around_axis(p) / (2.0 * PI), p.z / self.height),
+                    // the radius changes by slope along the height
+                    dpdv: (radial(p) * slope + Vec3::new(0.0, 0.0, 1.0)) * self.height,
+                })
+            });
+
+        let caps = if self.capped {
+            [
+                cap(o, d, 0.0, self.base_radius, -1.0),
+                cap(o, d, self.height, self.top_radius, 1.0),
+            ]
+        } else {
+            [None, None]
+        };
+
+        closest_hit(
+            ray,
+            ray_t,
+            &self.frame,
+            side.chain(caps.into_iter().flatten()),
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// A torus around `center` in the plane perpendicular to `axis`. The tube of
+/// radius `minor_radius` follows a circle of radius `major_radius`.
+pub(crate) struct Torus {
+    frame: AxisFrame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Torus {
+    pub(crate) fn new(
+        center: Point,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Arc<Material>,
+    ) -> Self {
+        let direction = axis.unit_vector();
+        let ring = Aabb::from_disk(center, direction, major_radius);
+        let tube = Vec3::new(minor_radius, minor_radius, minor_radius);
+        let bbox = Aabb::from_points(
+            Point::new(ring.x.min, ring.y.min, ring.z.min) - tube,
+            Point::new(ring.x.max, ring.y.max, ring.z.max) + tube,
+        );
+        Self {
+            frame: AxisFrame::new(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (o, d) = self.frame.to_local(ray);
+        let length = d.length();
+        let d = d / length;
+
+        // Only solve over the part of the ray inside the bounding sphere, and
+        // measure t from where the ray enters it. Starting close to the torus
+        // keeps the quartic well conditioned for distant rays.
+        let bound = self.major_radius + self.minor_radius;
+        let h = -o.dot(d);
+        let discriminant = bound * bound - (o + d * h).length_squared();
+        if discriminant < 0.0 {
+            return None;
+        }
+        let half_chord = discriminant.sqrt();
+        let t_min = (h - half_chord).max(ray_t.min * length);
+        let t_max = (h + half_chord).min(ray_t.max * length);
+        if t_min >= t_max {
+            return None;
+        }
+        let o = o + d * t_min;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2) along p = o + t d
+        let r2 = self.major_radius * self.major_radius;
+        let m = o.dot(d);
+        let k = o.length_squared() + r2 - self.minor_radius * self.minor_radius;
+        let coefficients = [
+            k * k - 4.0 * r2 * (o.x * o.x + o.y * o.y),
+            4.0 * m * k - 8.0 * r2 * (o.x * d.x + o.y * d.y),
+            4.0 * m * m + 2.0 * k - 4.0 * r2 * (d.x * d.x + d.y * d.y),
+            4.0 * m,
+            1.0,
+        ];
+
+        let candidates = polynomial_roots(&coefficients, 0.0, t_max - t_min)
+            .into_iter()
+            .map(|t| {
+                let p = o + d * t;
+                let ring = Vec3::new(p.x, p.y, 0.0).unit_vector() * self.major_radius;
+                let normal = p - ring;
+                let phi = angle_around_axis(p);
+                let theta = normal
+                    .z
+                    .atan2(Vec3::new(p.x, p.y, 0.0).length() - self.major_radius);
+                let theta = if theta < 0.0 { theta + 2.0 * PI } else { theta };
+                // around the tube, where normal is r (cos theta, sin theta)
+                // across and along the axis
+                let across = Vec3::new(p.x, p.y, 0.0).length() - self.major_radius;
+                Candidate {
+                    t: (t + t_min) / length,
+                    normal,
+                    uv: (phi / (2.0 * PI), theta / (2.0 * PI)),
+                    dpdv: (radial(p) * -normal.z + Vec3::new(0.0, 0.0, across)) * (2.0 * PI),
+                }
+            });
+        closest_hit(ray, ray_t, &self.frame, candidates, &self.material)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// All points within `radius` of the segment from `a` to `b`: a cylinder
+/// closed by two hemispheres.
+pub(crate) struct Capsule {
+    frame: AxisFrame,
+    radius: f64,
+    height: f64,
+    material: Arc<Material>,
+    bbox: Aabb,
+}
+
+impl Capsule {
+    pub(crate) fn new(a: Point, b: Point, radius: f64, material: Arc<Material>) -> Self {
+        let r = Vec3::new(radius, radius, radius);
+        let bbox = Aabb::enclosing(
+            Aabb::from_points(a - r, a + r),
+            Aabb::from_points(b - r, b + r),
+        );
+        Self {
+            frame: AxisFrame::new(a, b - a),
+            radius,
+            height: (b - a).length(),
+            material,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (o, d) = self.frame.to_local(ray);
+        let r2 = self.radius * self.radius;
+        // v runs along the whole profile, from the bottom pole to the top one
+        let profile_length = self.height + 2.0 * self.radius;
+        let v_at = |z: f64| ((z + self.radius) / profile_length).clamp(0.0, 1.0);
+
+        let a = d.x * d.x + d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.y * d.y);
+        let c = o.x * o.x + o.y * o.y - r2;
+        let side = solve_quadratic(a, b, c)
+            .into_iter()
+            .flatten()
+            .filter_map(|t| {
+                let p = o + d * t;
+                if p.z < 0.0 || p.z > self.height {
+                    return None;
+                }
+                Some(Candidate {
+                    t,
+                    normal: Vec3::new(p.x, p.y, 0.0),
+                    uv: (angle_around_axis(p) / (2.0 * PI), v_at(p.z)),
+                    dpdv: Vec3::new(0.0, 0.0, profile_length),
+                })
+            });
+
+        let hemisphere = |z_center: f64, below: bool| {
+            let oc = o - Vec3::new(0.0, 0.0, z_center);
+            solve_quadratic(
+                d.length_squared(),
+                2.0 * oc.dot(d),
+                oc.length_squared() - r2,
+            )
+            .into_iter()
+            .flatten()
+            .filter_map(move |t| {
+                let p = o + d * t;
+                if (below && p.z > 0.0) || (!below && p.z < z_center) {
+                    return None;
+                }
+                // v moves the point up the profile and so, on a sphere of
+                // radius rho around the axis, in by dz / rho per unit height
+                let z = p.z - z_center;
+                let rho = (p.x * p.x + p.y * p.y).sqrt();
+                let inward = if rho > 1e-9 { z / rho } else { 0.0 };
+                Some(Candidate {
+                    t,
+                    normal: p - Vec3::new(0.0, 0.0, z_center),
+                    uv: (angle_around_axis(p) / (2.0 * PI), v_at(p.z)),
+                    dpdv: (Vec3::new(0.0, 0.0, 1.0) - radial(p) * inward) * profile_length,
+                })
+            })
+        };
+
+        let candidates = side
+            .chain(hemisphere(0.0, true))
+            .chain(hemisphere(self.height, false));
+        closest_hit(ray, ray_t, &self.frame, candidates, &self.material)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+// Real roots of a t^2 + b t + c, in ascending order. Uses the form of the
+// quadratic formula that avoids cancellation between b and the square root.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<[f64; 2]> {
+    if a.abs() < 1e-12 {
+        if b == 0.0 {
+            return None;
+        }
+        let t = -c / b;
+        return Some([t, t]);
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    let t0 = q / a;
+    let t1 = if q != 0.0 { c / q } else { t0 };
+    Some(if t0 < t1 { [t0, t1] } else { [t1, t0] })
+}
+
+// Evaluates the polynomial and its derivative. Coefficients go from the
+// lowest degree up.
+fn evaluate(coefficients: &[f64], t: f64) -> (f64, f64) {
+    let mut value = 0.0;
+    let mut derivative = 0.0;
+    for &c in coefficients.iter().rev() {
+        derivative = derivative * t + value;
+        value = value * t + c;
+    }
+    (value, derivative)
+}
+
+// Real roots of a polynomial in [lo, hi], in ascending order.
+//
+// The roots of the derivative split the interval into pieces on which the
+// polynomial is monotonic, so each piece holds at most one root, which is then
+// found by Newton's method safeguarded by bisection. Unlike closed form
+// solutions this stays accurate for nearly tangent rays.
+fn polynomial_roots(coefficients: &[f64], lo: f64, hi: f64) -> Vec<f64> {
+    let degree = coefficients.len() - 1;
+    if degree == 1 {
+        let t = -coefficients[0] / coefficients[1];
+        return if lo <= t && t <= hi { vec![t] } else { vec![] };
+    }
+
+    let derivative: Vec<f64> = coefficients
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, c)| i as f64 * c)
+        .collect();
+    let mut bounds = vec![lo];
+    bounds.extend(polynomial_roots(&derivative, lo, hi));
+    bounds.push(hi);
+
+    let mut roots = Vec::new();
+    for pair in bounds.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let (fa, _) = evaluate(coefficients, a);
+        let (fb, _) = evaluate(coefficients, b);
+        if fa == 0.0 {
+            if roots.last() != Some(&a) {
+                roots.push(a);
+            }
+        } else if fa.signum() != fb.signum() {
+            roots.push(bracketed_root(coefficients, a, b, fa));
+        }
+    }
+    if evaluate(coefficients, hi).0 == 0.0 && roots.last() != Some(&hi) {
+        roots.push(hi);
+    }
+    roots
+}
+
+fn bracketed_root(coefficients: &[f64], mut a: f64, mut b: f64, fa: f64) -> f64 {
+    let increasing = fa < 0.0;
+    let mut t = 0.5 * (a + b);
+    for _ in 0..64 {
+        let (f, df) = evaluate(coefficients, t);
+        if f == 0.0 {
+            return t;
+        }
+        if (f < 0.0) == increasing {
+            a = t;
+        } else {
+            b = t;
+        }
+
+        let newton = t - f / df;
+        let next = if newton > a && newton < b {
+            newton
+        } else {
+            0.5 * (a + b)
+        };
+        if (next - t).abs() <= 1e-12 * t.abs().max(1.0) {
+            return next;
+        }
+        t = next;
+    }
+    t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MaterialKind;
+
+    fn material() -> Arc<Material> {
+        Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        })
+    }
+
+    // Shapes around a tilted axis, each with a ray that hits it away from
+    // any seam.
+    fn shapes() -> Vec<(Box<dyn Hittable>, Ray)> {
+        let axis = Vec3::new(0.2, 1.0, 0.1);
+        let base = Point::new(0.1, -0.5, 0.2);
+        let ray = Ray::new(Point::new(3.0, 0.1, 2.0), Vec3::new(-3.0, 0.05, -1.9));
+        vec![
+            (
+                Box::new(Cylinder::new(base, axis, 0.6, false, material())),
+                ray,
+            ),
+            (
+                Box::new(Cone::new(base, axis, 0.8, 0.3, false, material())),
+                ray,
+            ),
+            (
+                Box::new(Torus::new(base, axis, 1.0, 0.3, material())),
+                Ray::new(Point::new(2.5, 0.2, 2.0), Vec3::new(-1.4, -0.7, -1.8)),
+            ),
+            (
+                Box::new(Capsule::new(base, base + axis, 0.5, material())),
+                Ray::new(Point::new(2.0, 1.5, 2.0), Vec3::new(-1.8, -0.8, -1.9)),
+            ),
+            // the capsule's top and a cylinder's bottom cap
+            (
+                Box::new(Capsule::new(base, base + axis, 0.5, material())),
+                Ray::new(Point::new(0.5, 3.0, 0.4), Vec3::new(0.0, -1.0, 0.0)),
+            ),
+            (
+                Box::new(Cylinder::new(base, axis, 0.6, true, material())),
+                Ray::new(Point::new(0.3, -3.0, 0.4), Vec3::new(0.0, 1.0, 0.0)),
+            ),
+        ]
+    }
+
+    #[test]
+    fn derivatives_follow_the_uv_parameterization() {
+        for (shape, ray) in shapes() {
+            let hit = shape.hit(&ray, Interval::new(0.0, f64::INFINITY)).unwrap();
+            assert!(hit.dpdu.dot(hit.normal).abs() < 1e-6);
+            assert!(hit.dpdv.dot(hit.normal).abs() < 1e-6);
+
+            // a nearby hit moves by the derivatives times the change in uv
+            for offset in [Vec3::new(1e-4, 0.0, 0.0), Vec3::new(0.0, 1e-4, 0.0)] {
+                let nearby = Ray::new(ray.origin + offset, ray.direction);
+                let other = shape
+                    .hit(&nearby, Interval::new(0.0, f64::INFINITY))
+                    .unwrap();
+                let predicted = hit.p + hit.dpdu * (other.u - hit.u) + hit.dpdv * (other.v - hit.v);
+                assert!(
+                    (predicted - other.p).length() < 1e-6,
+                    "{predicted} vs {}",
+                    other.p
+                );
+            }
+        }
+    }
+
+    fn first_hit(shape: &dyn Hittable, origin: Point, direction: Vec3) -> Option<HitRecord> {
+        shape.hit(
+            &Ray::new(origin, direction),
+            Interval::new(0.0, f64::INFINITY),
+        )
+    }
+
+    #[test]
+    fn cylinder_hits_tangent_and_axis_parallel_rays() {
+        let y = Vec3::new(0.0, 2.0, 0.0);
+        let open = Cylinder::new(Point::new(0.0, 0.0, 0.0), y, 1.0, false, material());
+        let capped = Cylinder::new(Point::new(0.0, 0.0, 0.0), y, 1.0, true, material());
+        let along_z = Vec3::new(0.0, 0.0, 1.0);
+
+        // just inside and just outside the line that touches the side
+        let hit = first_hit(&open, Point::new(1.0 - 1e-9, 1.0, -5.0), along_z).unwrap();
+        assert!((hit.t - 5.0).abs() < 1e-3);
+        assert!((hit.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-3);
+        assert!(first_hit(&open, Point::new(1.0 + 1e-9, 1.0, -5.0), along_z).is_none());
+
+        // grazing the rim from the side, inside the height and just above it
+        let hit = first_hit(
+            &open,
+            Point::new(-5.0, 2.0 - 1e-9, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+        );
+        assert!((hit.unwrap().t - 4.0).abs() < 1e-9);
+        assert!(first_hit(
+            &open,
+            Point::new(-5.0, 2.0 + 1e-9, 0.0),
+            Vec3::new(1.0, 0.0, 0.0)
+        )
+        .is_none());
+
+        // parallel to the axis, which only the caps can stop
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        assert!(first_hit(&open, Point::new(0.5, 5.0, 0.0), down).is_none());
+        let hit = first_hit(&capped, Point::new(0.5, 5.0, 0.0), down).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-12);
+        assert!((hit.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert!(first_hit(&capped, Point::new(1.0 + 1e-9, 5.0, 0.0), down).is_none());
+    }
+
+    #[test]
+    fn torus_hits_grazing_and_tangent_rays() {
+        let torus = Torus::new(
+            Point::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.25,
+            material(),
+        );
+        let along_x = Vec3::new(1.0, 0.0, 0.0);
+        let along_z = Vec3::new(0.0, 0.0, 1.0);
+
+        // through the middle, and down the axis through the hole
+        let hit = first_hit(&torus, Point::new(-5.0, 0.0, 0.0), along_x).unwrap();
+        assert!((hit.t - 3.75).abs() < 1e-9);
+        assert!((hit.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+        assert!(first_hit(&torus, Point::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+
+        // skimming the top of the tube
+        let hit = first_hit(&torus, Point::new(-5.0, 0.25 - 1e-7, 0.0), along_x).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-3);
+        assert!(hit.normal.y > 0.99);
+        assert!(first_hit(&torus, Point::new(-5.0, 0.25 + 1e-7, 0.0), along_x).is_none());
+
+        // touching the outer equator
+        let hit = first_hit(&torus, Point::new(1.25 - 1e-7, 0.0, -5.0), along_z).unwrap();
+        assert!((hit.t - 5.0).abs() < 1e-3);
+        assert!(hit.normal.x > 0.99);
+        assert!(first_hit(&torus, Point::new(1.25 + 1e-7, 0.0, -5.0), along_z).is_none());
+    }
+}
@@ -1,17 +1,48 @@
+use crate::spectrum::SampledWavelengths;
 use crate::vec3::Vec3;
 
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct Ray {
     pub(crate) origin: Vec3,
     pub(crate) direction: Vec3,
+    // the wavelength in nanometers carried by the ray once a dispersive
+    // material has picked one, after which it stands for that wavelength
+    // alone
+    pub(crate) wavelength: Option<f64>,
+    // the wavelengths that color channels stand for when rendering
+    // spectrally, or none for red, green and blue
+    pub(crate) wavelengths: Option<SampledWavelengths>,
 }
 
 impl Ray {
     pub(crate) fn new(origin: Vec3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            wavelength: None,
+            wavelengths: None,
+        }
+    }
+
+    /// A ray with this one's wavelengths in another place.
+    pub(crate) fn continued(&self, origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction,
+            ..*self
+        }
     }
 
     pub(crate) fn at(&self, t: f64) -> Vec3 {
         self.origin + self.direction * t
     }
+
+    /// A linear sRGB color in the channels this ray carries: unchanged, or
+    /// its upsampled spectrum at the ray's wavelengths.
+    pub(crate) fn color(&self, rgb: Vec3) -> Vec3 {
+        match self.wavelengths {
+            Some(wavelengths) => wavelengths.upsample(rgb),
+            None => rgb,
+        }
+    }
 }
@@ -1,35 +1,104 @@
-use std::sync::Arc;
+use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock};
 
+use crate::aabb::Aabb;
 use crate::common::Point;
 use crate::interval::Interval;
+use crate::light::Light;
+use crate::lighttree::LightTree;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 
+#[derive(Clone)]
 pub(crate) struct HitRecord {
     pub(crate) p: Point,
+    // the shading normal, which may be interpolated or perturbed away from
+    // the true surface, and the geometric normal of the surface itself, both
+    // on the side the ray came from
     pub(crate) normal: Vec3,
+    pub(crate) geometric_normal: Vec3,
     pub(crate) t: f64,
+    pub(crate) u: f64,
+    pub(crate) v: f64,
     pub(crate) front_face: bool,
+    // How the point moves with u and v, which orients anisotropic shading
+    // along the surface. Zero for shapes without a parameterization.
+    pub(crate) dpdu: Vec3,
+    pub(crate) dpdv: Vec3,
     pub(crate) material: Arc<Material>,
 }
 
 impl HitRecord {
-    pub(crate) fn new(ray: &Ray, p: Point, normal: Vec3, t: f64, material: Arc<Material>) -> Self {
+    pub(crate) fn new(
+        ray: &Ray,
+        p: Point,
+        normal: Vec3,
+        t: f64,
+        (u, v): (f64, f64),
+        material: Arc<Material>,
+    ) -> Self {
         let front_face = ray.direction.dot(normal) < 0.0;
         let normal = if front_face { normal } else { -normal };
         Self {
             p,
             normal,
+            geometric_normal: normal,
             t,
+            u,
+            v,
             front_face,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
             material,
         }
     }
+
+    pub(crate) fn with_derivatives(mut self, dpdu: Vec3, dpdv: Vec3) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    /// Where rays leaving the surface towards `direction` start: just off it
+    /// along the geometric normal, on the side they go, so that they neither
+    /// hit the surface again nor start behind it where the shading normal
+    /// is bent away from the true one.
+    pub(crate) fn spawn_point(&self, direction: Vec3) -> Point {
+        const OFFSET: f64 = 1e-4;
+        if direction.dot(self.geometric_normal) < 0.0 {
+            self.p - self.geometric_normal * OFFSET
+        } else {
+            self.p + self.geometric_normal * OFFSET
+        }
+    }
+
+    /// Shades with the outward unit normal `normal` in place of the
+    /// geometric one, which still decides the face that was hit.
+    pub(crate) fn with_shading_normal(mut self, normal: Vec3) -> Self {
+        self.normal = if self.front_face { normal } else { -normal };
+        self
+    }
 }
 
 pub(crate) trait Hittable {
     fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord>;
+
+    /// The fraction of light a participating medium lets through along `ray`
+    /// within `ray_t`, for shadow rays. `None` for solid objects, which
+    /// block the light where they are hit.
+    fn transmittance(&self, _ray: &Ray, _ray_t: Interval) -> Option<f64> {
+        None
+    }
+
+    /// The triangle lights that stand in for the object if it is emissive
+    /// geometry made of triangles, so that the world samples it as lights
+    /// and rays hit them in its place. `None` for all other objects.
+    fn triangle_lights(&self) -> Option<Vec<Light>> {
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb;
 }
 
 pub(crate) struct Sphere {
@@ -70,26 +139,130 @@ impl Hittable for Sphere {
         let t = root;
         let p = ray.at(t);
         let normal = (p - self.center) / self.radius;
-        Some(HitRecord::new(ray, p, normal, t, self.material.clone()))
+        let uv = sphere_uv(normal);
+        let (dpdu, dpdv) = sphere_derivatives(p - self.center);
+        Some(
+            HitRecord::new(ray, p, normal, t, uv, self.material.clone())
+                .with_derivatives(dpdu, dpdv),
+        )
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = self.radius.abs();
+        let radius = Vec3::new(r, r, r);
+        Aabb::from_points(self.center - radius, self.center + radius)
+    }
+}
+
+// Maps a point on the unit sphere to u = angle around the y axis from
+// x = -1, and v = angle from y = -1 to y = +1, both in [0, 1].
+pub(crate) fn sphere_uv(p: Point) -> (f64, f64) {
+    let theta = (-p.y).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+// How a point `p` on a sphere about the origin moves with the u and v of
+// `sphere_uv`.
+pub(crate) fn sphere_derivatives(p: Point) -> (Vec3, Vec3) {
+    let s = (p.x * p.x + p.z * p.z).sqrt().max(1e-8);
+    (
+        2.0 * PI * Vec3::new(p.z, 0.0, -p.x),
+        PI * Vec3::new(-p.x * p.y / s, s, -p.y * p.z / s),
+    )
 }
 
 pub(crate) struct World {
-    objects: Vec<Box<dyn Hittable + Sync>>,
+    objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    lights: Vec<Light>,
+    // built from the lights when first needed
+    light_tree: OnceLock<LightTree>,
+    bbox: Aabb,
 }
 
 impl World {
     pub(crate) fn new() -> Self {
         Self {
             objects: Vec::new(),
+            lights: Vec::new(),
+            light_tree: OnceLock::new(),
+            bbox: Aabb::EMPTY,
         }
     }
 
-    pub(crate) fn add(&mut self, object: impl Hittable + 'static + Sync) {
+    /// Adds an object, or the lights standing in for it if it is emissive
+    /// triangles.
+    pub(crate) fn add(&mut self, object: impl Hittable + 'static + Send + Sync) {
+        if let Some(lights) = object.triangle_lights() {
+            for light in lights {
+                self.add_light(light);
+            }
+            return;
+        }
+        self.bbox = Aabb::enclosing(self.bbox, object.bounding_box());
         self.objects.push(Box::new(object));
     }
 
-    pub(crate) fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    /// Adds a light, which lights the objects without being one of them.
+    pub(crate) fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+        self.light_tree = OnceLock::new();
+    }
+
+    pub(crate) fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub(crate) fn light_tree(&self) -> &LightTree {
+        self.light_tree.get_or_init(|| LightTree::new(&self.lights))
+    }
+
+    /// The closest light `ray` hits within `ray_t`, which only triangle
+    /// lights can be, and where.
+    pub(crate) fn hit_light(&self, ray: &Ray, ray_t: Interval) -> Option<(&Light, usize, f64)> {
+        let (index, t) = self.light_tree().hit(ray, ray_t, &self.lights)?;
+        Some((&self.lights[index], index, t))
+    }
+
+    /// The fraction of light that gets along `ray` within `ray_t`: none if
+    /// a surface is in the way, and otherwise what the media let through.
+    pub(crate) fn visibility(&self, ray: &Ray, ray_t: Interval) -> f64 {
+        let mut transmittance = 1.0;
+        let mut media = Vec::new();
+        for (i, object) in self.objects.iter().enumerate() {
+            if let Some(object_transmittance) = object.transmittance(ray, ray_t) {
+                transmittance *= object_transmittance;
+                media.push(i);
+            }
+        }
+        if transmittance <= 0.0 || self.hit_light(ray, ray_t).is_some() {
+            return 0.0;
+        }
+
+        let mut ray_t = ray_t;
+        loop {
+            let mut closest_so_far = ray_t.max;
+            let mut closest = None;
+            for (i, object) in self.objects.iter().enumerate() {
+                if media.contains(&i) {
+                    continue;
+                }
+                if let Some(record) = object.hit(ray, Interval::new(ray_t.min, closest_so_far)) {
+                    closest_so_far = record.t;
+                    closest = Some(record);
+                }
+            }
+            match closest {
+                Some(hit) if hit.material.passes_through(&hit) => {
+                    ray_t = Interval::new(hit.t, ray_t.max);
+                }
+                Some(_) => return 0.0,
+                None => return transmittance,
+            }
+        }
+    }
+
+    fn closest_hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
         let mut closest_so_far = ray_t.max;
         let mut hit_record = None;
         for object in &self.objects {
@@ -101,3 +274,153 @@ impl World {
         hit_record
     }
 }
+
+impl Hittable for World {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // Where the closest hit is cut out of its surface, search again
+        // beyond it.
+        let mut ray_t = ray_t;
+        loop {
+            let hit = self.closest_hit(ray, ray_t)?;
+            if !hit.material.passes_through(&hit) {
+                return Some(hit);
+            }
+            ray_t = Interval::new(hit.t, ray_t.max);
+        }
+    }
+
+    // A world of nothing but triangle lights, like a box of emissive quads,
+    // carries them into the world it is added to.
+    fn triangle_lights(&self) -> Option<Vec<Light>> {
+        self.objects.is_empty().then(|| self.lights.clone())
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MaterialKind;
+    use crate::planar::{cuboid, Quad, Triangle};
+    use crate::texture::Texture;
+    use crate::transform::{Instance, Transform};
+
+    // A quad at z = 1, cut out by `opacity` and `threshold`, in front of a
+    // sphere at z = 3.
+    fn cutout_in_front_of_sphere(opacity: f64, threshold: Option<f64>) -> World {
+        let lambertian = || {
+            Arc::new(Material {
+                kind: MaterialKind::Lambertian,
+                albedo: Vec3::new(0.5, 0.5, 0.5),
+            })
+        };
+        let cutout = Arc::new(Material {
+            kind: MaterialKind::Cutout {
+                base: lambertian(),
+                opacity: Texture::Constant(Vec3::new(opacity, opacity, opacity)),
+                threshold,
+            },
+            albedo: Vec3::new(0.0, 0.0, 0.0),
+        });
+        let mut world = World::new();
+        world.add(Quad::new(
+            Point::new(-1.0, -1.0, 1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            cutout,
+        ));
+        world.add(Sphere::new(Point::new(0.0, 0.0, 3.0), 0.5, lambertian()));
+        world
+    }
+
+    #[test]
+    fn rays_pass_through_cut_out_surfaces() {
+        let ray = Ray::new(Point::new(0.1, 0.2, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let everywhere = Interval::new(0.001, f64::INFINITY);
+        // up to just before the sphere
+        let in_front = Interval::new(0.001, 2.0);
+
+        let cut = cutout_in_front_of_sphere(0.2, Some(0.5));
+        let hit = cut.hit(&ray, everywhere).unwrap();
+        assert!(hit.t > 2.0 && hit.t < 3.0, "{}", hit.t);
+        assert_eq!(cut.visibility(&ray, in_front), 1.0);
+
+        let solid = cutout_in_front_of_sphere(0.8, Some(0.5));
+        assert!((solid.hit(&ray, everywhere).unwrap().t - 1.0).abs() < 1e-12);
+        assert_eq!(solid.visibility(&ray, in_front), 0.0);
+    }
+
+    #[test]
+    fn partly_transparent_surfaces_block_rays_and_shadow_rays_alike() {
+        let world = cutout_in_front_of_sphere(0.5, None);
+        let mut passed = 0;
+        let n = 200;
+        for i in 0..n {
+            let x = -0.2 + 0.4 * i as f64 / n as f64;
+            let ray = Ray::new(Point::new(x, 0.1, 0.0), Vec3::new(0.0, 0.0, 1.0));
+            let hit = world
+                .hit(&ray, Interval::new(0.001, f64::INFINITY))
+                .unwrap();
+            let visibility = world.visibility(&ray, Interval::new(0.001, 2.0));
+            let through = hit.t > 2.0;
+            assert_eq!(visibility, if through { 1.0 } else { 0.0 });
+            passed += through as usize;
+        }
+        assert!(passed > n / 4 && passed < 3 * n / 4, "{passed}");
+    }
+
+    #[test]
+    fn emissive_triangles_become_lights() {
+        let glow = Arc::new(Material {
+            kind: MaterialKind::DiffuseLight,
+            albedo: Vec3::new(2.0, 2.0, 2.0),
+        });
+        let mut world = World::new();
+        world.add(Quad::new(
+            Point::new(-1.0, 3.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            glow.clone(),
+        ));
+        world.add(cuboid(
+            Point::new(5.0, 0.0, 0.0),
+            Point::new(6.0, 1.0, 1.0),
+            glow.clone(),
+        ));
+        let triangle = Triangle::new(
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 0.0, 0.0),
+            Point::new(0.0, 1.0, 0.0),
+            glow.clone(),
+        );
+        world.add(Instance::new(
+            Arc::new(triangle),
+            Transform::translate(Vec3::new(0.0, 0.0, -4.0)),
+        ));
+        // other shapes glow as they are
+        world.add(Sphere::new(Point::new(0.0, 0.0, 4.0), 0.5, glow));
+
+        assert_eq!(world.lights().len(), 2 + 12 + 1);
+        assert_eq!(world.objects.len(), 1);
+
+        // the quad faces down, towards a ray from below
+        let up = Ray::new(Point::new(0.2, 0.0, 0.3), Vec3::new(0.0, 1.0, 0.0));
+        assert!(world
+            .hit(&up, Interval::new(0.001, f64::INFINITY))
+            .is_none());
+        let (light, _, t) = world
+            .hit_light(&up, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((t - 3.0).abs() < 1e-12);
+        assert_eq!(light.emitted(&up).x, 2.0);
+
+        let moved = Ray::new(Point::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let (_, _, t) = world
+            .hit_light(&moved, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((t - 5.0).abs() < 1e-12);
+    }
+}
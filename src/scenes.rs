@@ -0,0 +1,869 @@
+use std::sync::Arc;
+
+use crate::common::Point;
+use crate::csg::Csg;
+use crate::curve::{strand, CurveBasis, CurveShape};
+use crate::hair::{sigma_a_from_melanin, sigma_a_from_reflectance};
+use crate::heightfield::Heightfield;
+use crate::light::Light;
+use crate::material::{Material, MaterialKind};
+use crate::medium::ConstantMedium;
+use crate::mesh::Mesh;
+use crate::microfacet::MetalPreset;
+use crate::perlin::Perlin;
+use crate::planar::{cuboid, Disk, Plane, Quad, Triangle};
+use crate::principled::Principled;
+use crate::quadric::{Capsule, Cone, Cylinder, Torus};
+use crate::scene::{Sphere, World};
+use crate::sdf::{Sdf, SdfObject};
+use crate::spectrum::Dispersion;
+use crate::texture::{ImageData, Texture};
+use crate::transform::{Instance, Mat4, Quaternion, Transform};
+use crate::vec3::Vec3;
+use crate::volume::{GridMedium, VoxelGrid};
+
+/// The scene of the given name, for --scene, laid out around the origin for
+/// the default camera.
+pub(crate) fn scene(name: &str) -> World {
+    match name {
+        "emitters" => emitters(),
+        "hair" => hair(),
+        "layers" => layers(),
+        "materials" => materials(),
+        "sdf" => sdf(),
+        "shapes" => shapes(),
+        "surfaces" => surfaces(),
+        "terrain" => terrain(),
+        "volumes" => volumes(),
+        _ => panic!("unknown scene: {name}"),
+    }
+}
+
+fn material(kind: MaterialKind, albedo: Vec3) -> Arc<Material> {
+    Arc::new(Material { kind, albedo })
+}
+
+fn gray(value: f64) -> Vec3 {
+    Vec3::new(value, value, value)
+}
+
+// A fully saturated color around the color wheel, with hue in [0, 1).
+fn hue(h: f64) -> Vec3 {
+    let distance = |center: f64| (h * 6.0 - center).abs();
+    Vec3::new(
+        (distance(3.0) - 1.0).clamp(0.0, 1.0),
+        (2.0 - distance(2.0)).clamp(0.0, 1.0),
+        (2.0 - distance(4.0)).clamp(0.0, 1.0),
+    )
+}
+
+// A dark room around the camera, with a ground plane, which keeps the sky
+// from lighting the scene.
+fn room() -> World {
+    let mut world = World::new();
+    let walls = material(MaterialKind::Lambertian, gray(0.1));
+    world.add(cuboid(
+        Point::new(-20.0, -1.0, -20.0),
+        Point::new(20.0, 12.0, 20.0),
+        walls,
+    ));
+    world.add(Plane::new(
+        Point::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        material(MaterialKind::Lambertian, gray(0.5)),
+    ));
+    world
+}
+
+// A wall of 128 small colored panels and a glowing strip on the floor,
+// many more lights than can be sampled at once, to compare the
+// --light-sampling strategies on.
+fn emitters() -> World {
+    let mut world = room();
+    for i in 0..16 {
+        for j in 0..8 {
+            let radiance = hue((i + 2 * j) as f64 / 16.0 % 1.0) * 4.0;
+            world.add(Quad::new(
+                Point::new(-3.0, 0.3 + 0.3 * j as f64, -2.4 + 0.3 * i as f64),
+                Vec3::new(0.0, 0.22, 0.0),
+                Vec3::new(0.0, 0.0, 0.22),
+                material(MaterialKind::DiffuseLight, radiance),
+            ));
+        }
+    }
+
+    // a ribbon facing up, in 32 pieces
+    let positions = (0..=32)
+        .flat_map(|i| {
+            let z = -3.2 + 0.2 * i as f64;
+            [Point::new(2.0, 0.01, z), Point::new(2.1, 0.01, z)]
+        })
+        .collect();
+    let triangles = (0..32)
+        .flat_map(|i| [[2 * i, 2 * i + 3, 2 * i + 1], [2 * i, 2 * i + 2, 2 * i + 3]])
+        .collect();
+    world.add(Mesh::new(
+        positions,
+        triangles,
+        material(MaterialKind::DiffuseLight, Vec3::new(6.0, 5.0, 4.0)),
+    ));
+
+    world.add(Sphere::new(
+        Point::new(0.0, 0.7, -1.2),
+        0.7,
+        material(MaterialKind::Metal { roughness: 0.1 }, gray(0.8)),
+    ));
+    world.add(Sphere::new(
+        Point::new(0.5, 0.5, 1.2),
+        0.5,
+        material(MaterialKind::Lambertian, gray(0.7)),
+    ));
+    world
+}
+
+// The geometric primitives, one row of each kind, under the open sky.
+fn shapes() -> World {
+    let mut world = World::new();
+    world.add(Plane::new(
+        Point::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        material(MaterialKind::Lambertian, gray(0.5)),
+    ));
+
+    // flat shapes in front
+    let x = 3.0;
+    world.add(Quad::new(
+        Point::new(x, 0.0, 2.6),
+        Vec3::new(0.0, 0.0, -0.7),
+        Vec3::new(0.0, 0.7, 0.0),
+        material(MaterialKind::Lambertian, hue(0.0)),
+    ));
+    world.add(Triangle::new(
+        Point::new(x, 0.0, 1.1),
+        Point::new(x, 0.0, 0.4),
+        Point::new(x, 0.8, 0.75),
+        material(MaterialKind::Lambertian, hue(0.15)),
+    ));
+    world.add(Disk::new(
+        Point::new(x, 0.45, -0.75),
+        Vec3::new(1.0, 0.3, 0.0),
+        0.4,
+        material(MaterialKind::Lambertian, hue(0.3)),
+    ));
+    world.add(cuboid(
+        Point::new(x - 0.35, 0.0, -2.6),
+        Point::new(x + 0.35, 0.7, -1.9),
+        material(MaterialKind::Lambertian, hue(0.6)),
+    ));
+
+    // quadrics and the torus behind them
+    let x = 0.5;
+    world.add(Cylinder::new(
+        Point::new(x, 0.0, 2.4),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.35,
+        true,
+        material(MaterialKind::Lambertian, hue(0.05)),
+    ));
+    world.add(Cone::new(
+        Point::new(x, 0.0, 0.8),
+        Vec3::new(0.0, 1.2, 0.0),
+        0.45,
+        0.0,
+        true,
+        material(MaterialKind::Lambertian, hue(0.2)),
+    ));
+    world.add(Torus::new(
+        Point::new(x, 0.6, -0.8),
+        Vec3::new(1.0, 0.6, 0.0),
+        0.45,
+        0.15,
+        material(MaterialKind::Lambertian, hue(0.45)),
+    ));
+    world.add(Capsule::new(
+        Point::new(x, 0.3, -2.7),
+        Point::new(x, 1.0, -1.9),
+        0.3,
+        material(MaterialKind::Lambertian, hue(0.75)),
+    ));
+
+    // one box placed six times, as it is and under different transforms
+    let x = -4.0;
+    let block: Arc<World> = Arc::new(cuboid(
+        Point::new(-0.3, 0.0, -0.3),
+        Point::new(0.3, 1.0, 0.3),
+        material(MaterialKind::Lambertian, hue(0.9)),
+    ));
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let shear = Mat4::new([
+        [1.0, 0.4, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.6, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    let transforms = [
+        Transform::IDENTITY,
+        Transform::rotate(up, 30.0),
+        Transform::translate(Vec3::new(0.0, 0.4, 0.0)) * Transform::rotate_euler(30.0, 0.0, 20.0),
+        Transform::scale(Vec3::new(1.5, 1.3, 0.7)),
+        Transform::translate(Vec3::new(0.0, 0.3, 0.0))
+            * Transform::from_quaternion(
+                Quaternion::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), -25.0)
+                    * Quaternion::new(1.0, 0.0, 0.4, 0.0),
+            ),
+        Transform::from_matrix(shear).unwrap(),
+    ];
+    for (i, transform) in transforms.into_iter().enumerate() {
+        let offset = Vec3::new(x, 0.0, 3.2 - 1.35 * i as f64);
+        world.add(Instance::new(
+            block.clone(),
+            Transform::translate(offset) * transform,
+        ));
+    }
+
+    // boolean combinations close to the camera
+    let x = 5.5;
+    let orange = material(MaterialKind::Lambertian, hue(0.08));
+    let cube = |center: Point, half: f64| {
+        let half = Vec3::new(half, half, half);
+        Arc::new(cuboid(center - half, center + half, orange.clone()))
+    };
+    let ball = |center: Point, radius: f64| Arc::new(Sphere::new(center, radius, orange.clone()));
+
+    let center = Point::new(x, 0.25, 2.5);
+    world.add(Csg::difference(cube(center, 0.25), ball(center, 0.32)));
+    let center = Point::new(x, 0.25, 1.5);
+    world.add(Csg::intersection(cube(center, 0.25), ball(center, 0.33)));
+    let center = Point::new(x, 0.3, 0.5);
+    let cross = Csg::union(
+        Arc::new(Capsule::new(
+            center - Vec3::new(0.0, 0.0, 0.3),
+            center + Vec3::new(0.0, 0.0, 0.3),
+            0.12,
+            orange.clone(),
+        )),
+        Arc::new(Capsule::new(
+            center - Vec3::new(0.0, 0.3, 0.0),
+            center + Vec3::new(0.0, 0.3, 0.0),
+            0.12,
+            orange.clone(),
+        )),
+    );
+    world.add(Csg::difference(Arc::new(cross), ball(center, 0.2)));
+    world
+}
+
+// Participating media on the ground: a ball of fog, a box of smoke that
+// scatters light forward and a glowing cloud from a voxel grid.
+fn volumes() -> World {
+    let mut world = World::new();
+    world.add(Plane::new(
+        Point::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        material(MaterialKind::Lambertian, gray(0.5)),
+    ));
+
+    let boundary = material(MaterialKind::Lambertian, gray(1.0));
+    world.add(ConstantMedium::new(
+        Arc::new(Sphere::new(
+            Point::new(0.0, 1.0, 1.5),
+            1.0,
+            boundary.clone(),
+        )),
+        1.5,
+        gray(0.9),
+        0.0,
+    ));
+    world.add(ConstantMedium::new(
+        Arc::new(cuboid(
+            Point::new(-0.7, 0.0, -2.2),
+            Point::new(0.7, 1.4, -0.8),
+            boundary,
+        )),
+        3.0,
+        Vec3::new(0.6, 0.7, 0.9),
+        0.7,
+    ));
+
+    // a lumpy ball, densest at its center, that glows orange where it absorbs
+    let n = 32;
+    let density = (0..n * n * n)
+        .map(|i| {
+            let p = Vec3::new((i % n) as f64, (i / n % n) as f64, (i / (n * n)) as f64);
+            let p = (p + Vec3::new(0.5, 0.5, 0.5)) / n as f64 * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+            let lumps = 0.15 * (7.0 * p.x).sin() * (7.0 * p.y).sin() * (7.0 * p.z).sin();
+            (1.0 - p.length() + lumps).max(0.0) as f32
+        })
+        .collect();
+    world.add(
+        GridMedium::new(
+            Arc::new(VoxelGrid::new(n, n, n, density)),
+            Point::new(2.0, 0.0, -0.6),
+            Point::new(3.2, 1.2, 0.6),
+            8.0,
+            Vec3::new(0.3, 0.6, 0.8),
+            0.0,
+        )
+        .with_emission(0.8, 1.0),
+    );
+    world
+}
+
+// Signed distance fields, blended, carved, twisted, repeated and displaced
+// by noise, in two rows.
+fn sdf() -> World {
+    let mut world = World::new();
+    world.add(Plane::new(
+        Point::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        material(MaterialKind::Lambertian, gray(0.5)),
+    ));
+
+    let place = |shape: Sdf, x: f64, y: f64, z: f64| Sdf::Translate {
+        offset: Vec3::new(x, y, z),
+        shape: Box::new(shape),
+    };
+    let sphere = |radius: f64| Sdf::Sphere { radius };
+    let round_box = |half: f64, radius: f64| Sdf::RoundBox {
+        half_extents: Vec3::new(half, half, half),
+        radius,
+    };
+    let shapes = [
+        // front row
+        place(
+            Sdf::SmoothUnion {
+                a: Box::new(sphere(0.35)),
+                b: Box::new(place(sphere(0.25), 0.0, 0.4, 0.1)),
+                k: 0.2,
+            },
+            2.0,
+            0.35,
+            2.0,
+        ),
+        place(
+            Sdf::SmoothSubtraction {
+                a: Box::new(round_box(0.35, 0.08)),
+                b: Box::new(place(sphere(0.3), 0.0, 0.3, 0.0)),
+                k: 0.05,
+            },
+            2.0,
+            0.35,
+            0.5,
+        ),
+        place(
+            Sdf::SmoothIntersection {
+                a: Box::new(Sdf::Box {
+                    half_extents: Vec3::new(0.35, 0.35, 0.35),
+                }),
+                b: Box::new(sphere(0.45)),
+                k: 0.05,
+            },
+            2.0,
+            0.35,
+            -1.0,
+        ),
+        // back row
+        place(
+            Sdf::Twist {
+                rate: 2.0,
+                shape: Box::new(Sdf::Box {
+                    half_extents: Vec3::new(0.25, 0.6, 0.25),
+                }),
+            },
+            -1.5,
+            0.6,
+            2.2,
+        ),
+        place(
+            Sdf::Repeat {
+                spacing: Vec3::new(0.0, 0.0, 0.45),
+                count: [0, 0, 1],
+                shape: Box::new(Sdf::Capsule {
+                    a: Point::new(0.0, -0.4, 0.0),
+                    b: Point::new(0.0, 0.4, 0.0),
+                    radius: 0.15,
+                }),
+            },
+            -1.5,
+            0.55,
+            0.5,
+        ),
+        place(
+            Sdf::Displace {
+                amplitude: 0.05,
+                frequency: 6.0,
+                noise: Arc::new(Perlin::new()),
+                shape: Box::new(Sdf::Torus {
+                    major_radius: 0.45,
+                    minor_radius: 0.18,
+                }),
+            },
+            -1.5,
+            0.2,
+            -1.3,
+        ),
+    ];
+    for (i, shape) in shapes.into_iter().enumerate() {
+        world.add(SdfObject::new(
+            shape,
+            material(MaterialKind::Lambertian, hue(i as f64 / 6.0)),
+        ));
+    }
+    world
+}
+
+// Rolling hills from Perlin turbulence, as a heightfield, in low sunlight.
+fn terrain() -> World {
+    let mut world = World::new();
+    let n = 256;
+    let noise = Perlin::new();
+    let heights = (0..n * n)
+        .map(|i| {
+            let p = Point::new((i % n) as f64, 0.0, (i / n) as f64) * (3.0 / n as f64);
+            noise.turbulence(p, 5) as f32
+        })
+        .collect();
+    world.add(Heightfield::new(
+        n,
+        n,
+        heights,
+        Point::new(-8.0, -0.8, -8.0),
+        Vec3::new(16.0, 2.0, 16.0),
+        material(MaterialKind::Lambertian, Vec3::new(0.35, 0.5, 0.25)),
+    ));
+    world.add_light(Light::directional(
+        Vec3::new(-1.0, -0.4, 0.6),
+        Vec3::new(3.0, 2.7, 2.3),
+    ));
+    world
+}
+
+// Two balls of fur on the ground: dark brown hair colored by its melanin,
+// as round Bezier tubes, and hair dyed orange, as flat B-spline ribbons.
+fn hair() -> World {
+    let mut world = World::new();
+    world.add(Plane::new(
+        Point::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        material(MaterialKind::Lambertian, gray(0.5)),
+    ));
+
+    let hair = |sigma_a: Vec3| {
+        material(
+            MaterialKind::Hair {
+                sigma_a,
+                beta_m: 0.3,
+                beta_n: 0.3,
+                alpha: 2.0,
+                eta: 1.55,
+            },
+            gray(1.0),
+        )
+    };
+    let balls = [
+        (
+            Point::new(0.0, 0.45, 1.3),
+            CurveBasis::Bezier,
+            CurveShape::Cylinder,
+            hair(sigma_a_from_melanin(1.3, 0.2)),
+        ),
+        (
+            Point::new(0.0, 0.45, -1.3),
+            CurveBasis::BSpline,
+            CurveShape::Flat,
+            hair(sigma_a_from_reflectance(Vec3::new(0.6, 0.15, 0.02), 0.3)),
+        ),
+    ];
+    for (center, basis, shape, hair) in balls {
+        world.add(Sphere::new(
+            center,
+            0.45,
+            material(MaterialKind::Lambertian, gray(0.05)),
+        ));
+        for _ in 0..400 {
+            // out from the upper half of the ball, drooping as they grow
+            let direction = Vec3::random_unit_in_hemisphere(Vec3::new(0.0, 1.0, 0.0));
+            let root = center + direction * 0.45;
+            let points: Vec<Point> = (0..7)
+                .map(|k| {
+                    let t = k as f64 / 6.0;
+                    root + direction * (0.6 * t) - Vec3::new(0.0, 0.35 * t * t, 0.0)
+                })
+                .collect();
+            world.add(strand(&points, basis, (0.02, 0.005), shape, hair.clone()));
+        }
+    }
+    world.add_light(Light::directional(
+        Vec3::new(-1.0, -1.0, -0.5),
+        Vec3::new(2.0, 2.0, 2.0),
+    ));
+    world
+}
+
+// A checkered floor under the open sky.
+fn checkered_floor() -> World {
+    let mut world = World::new();
+    let checker = Texture::Checker {
+        scale: 1.0,
+        even: Arc::new(Texture::constant(0.6)),
+        odd: Arc::new(Texture::constant(0.3)),
+    };
+    let ground = Principled {
+        base_color: checker,
+        roughness: Texture::constant(1.0),
+        ..Principled::default()
+    };
+    world.add(Plane::new(
+        Point::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        material(MaterialKind::Principled(Arc::new(ground)), gray(1.0)),
+    ));
+    world
+}
+
+// A row of spheres on the ground at `x`, one of each material, centered
+// where the camera looks across it.
+fn row_of_spheres(world: &mut World, x: f64, materials: Vec<Arc<Material>>) {
+    let spacing = 1.2;
+    let first = x * 3.0 / 13.0 + spacing * (materials.len() - 1) as f64 / 2.0;
+    for (i, material) in materials.into_iter().enumerate() {
+        let center = Point::new(x, 0.5, first - spacing * i as f64);
+        world.add(Sphere::new(center, 0.5, material));
+    }
+}
+
+// Rows of spheres on the ground, each of a different material.
+fn materials() -> World {
+    let mut world = checkered_floor();
+    let mut row = |x: f64, materials: Vec<Arc<Material>>| row_of_spheres(&mut world, x, materials);
+
+    // measured metals, from polished to brushed
+    row(
+        2.0,
+        [
+            MetalPreset::Gold,
+            MetalPreset::Copper,
+            MetalPreset::Aluminum,
+            MetalPreset::Silver,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, preset)| {
+            let roughness = 0.05 + 0.1 * i as f64;
+            material(MaterialKind::conductor(preset, roughness), gray(1.0))
+        })
+        .collect(),
+    );
+
+    // principled materials: plastic and metal as glTF describes them, then
+    // marble under a clearcoat, sheen and glass
+    let gltf = |color: Vec3, metallic: f64, roughness: f64| {
+        Principled::from_gltf_metallic_roughness(color, None, metallic, roughness, None)
+    };
+    let principled = [
+        gltf(Vec3::new(0.8, 0.1, 0.1), 0.0, 0.4),
+        gltf(Vec3::new(0.3, 0.5, 0.9), 1.0, 0.3),
+        Principled {
+            base_color: Texture::Noise {
+                scale: 8.0,
+                noise: Arc::new(Perlin::new()),
+            },
+            clearcoat: Texture::constant(1.0),
+            ..gltf(Vec3::new(1.0, 1.0, 1.0), 0.0, 0.6)
+        },
+        Principled {
+            sheen: Texture::constant(1.0),
+            ..gltf(Vec3::new(0.5, 0.2, 0.6), 0.0, 0.9)
+        },
+        Principled {
+            transmission: Texture::constant(1.0),
+            ..gltf(Vec3::new(1.0, 1.0, 1.0), 0.0, 0.1)
+        },
+    ];
+    row(
+        -2.0,
+        principled
+            .into_iter()
+            .map(|principled| material(MaterialKind::Principled(Arc::new(principled)), gray(1.0)))
+            .collect(),
+    );
+
+    // glasses that split white light into colors under --spectral, and
+    // green glass that darkens with depth
+    let glass = |dispersion: Option<Dispersion>, absorption_depth: f64, albedo: Vec3| {
+        material(
+            MaterialKind::Dielectric {
+                ref_idx: 1.5,
+                roughness: 0.0,
+                absorption_depth,
+                dispersion,
+            },
+            albedo,
+        )
+    };
+    row(
+        4.5,
+        vec![
+            glass(Some(Dispersion::FUSED_SILICA), f64::INFINITY, gray(1.0)),
+            glass(Some(Dispersion::BK7), f64::INFINITY, gray(1.0)),
+            glass(Some(Dispersion::SF11), f64::INFINITY, gray(1.0)),
+            glass(
+                Some(Dispersion::Cauchy { a: 1.5, b: 0.01 }),
+                0.5,
+                Vec3::new(0.4, 0.9, 0.5),
+            ),
+        ],
+    );
+    world
+}
+
+// Layered and blended materials: films and coats over metal, glass and
+// paint, behind them wax, jade, skin and rusty steel, and in front leaves
+// with two different sides.
+fn layers() -> World {
+    let mut world = checkered_floor();
+    // a film whose thickness wanders over `range` nanometers
+    let film = |range: f64, ior: f64, base: Arc<Material>| {
+        let thickness = Texture::Scale {
+            texture: Arc::new(Texture::Noise {
+                scale: 4.0,
+                noise: Arc::new(Perlin::new()),
+            }),
+            factor: gray(range),
+        };
+        material(
+            MaterialKind::ThinFilm {
+                thickness,
+                ior,
+                base,
+            },
+            gray(1.0),
+        )
+    };
+    let clear_glass = material(
+        MaterialKind::Dielectric {
+            ref_idx: 1.5,
+            roughness: 0.0,
+            absorption_depth: f64::INFINITY,
+            dispersion: None,
+        },
+        gray(1.0),
+    );
+    let coat = |base: Arc<Material>, roughness: f64, tint: Vec3| {
+        material(
+            MaterialKind::Coated {
+                base,
+                ior: 1.5,
+                roughness,
+            },
+            tint,
+        )
+    };
+    row_of_spheres(
+        &mut world,
+        2.0,
+        vec![
+            // oil over dark steel
+            film(
+                800.0,
+                1.45,
+                material(MaterialKind::Metal { roughness: 0.05 }, gray(0.3)),
+            ),
+            // soapy water over a glass ball
+            film(600.0, 1.33, clear_glass),
+            // blue car paint under clear lacquer
+            coat(
+                material(MaterialKind::Lambertian, Vec3::new(0.05, 0.1, 0.5)),
+                0.0,
+                gray(1.0),
+            ),
+            // copper under a frosted amber coat
+            coat(
+                material(MaterialKind::conductor(MetalPreset::Copper, 0.1), gray(1.0)),
+                0.2,
+                Vec3::new(1.0, 0.8, 0.5),
+            ),
+        ],
+    );
+
+    let translucent = |mean_free_path: Vec3, albedo: Vec3| {
+        material(
+            MaterialKind::Subsurface {
+                mean_free_path,
+                ior: 1.4,
+                roughness: 0.3,
+            },
+            albedo,
+        )
+    };
+    row_of_spheres(
+        &mut world,
+        -2.0,
+        vec![
+            translucent(Vec3::new(0.3, 0.2, 0.1), Vec3::new(0.9, 0.8, 0.6)),
+            translucent(Vec3::new(0.05, 0.2, 0.08), Vec3::new(0.3, 0.7, 0.4)),
+            translucent(Vec3::new(0.12, 0.05, 0.03), Vec3::new(0.85, 0.55, 0.45)),
+            // rust in patches over steel
+            material(
+                MaterialKind::Mix {
+                    a: material(MaterialKind::Metal { roughness: 0.1 }, gray(0.6)),
+                    b: material(MaterialKind::Lambertian, Vec3::new(0.4, 0.15, 0.05)),
+                    weight: Texture::Noise {
+                        scale: 10.0,
+                        noise: Arc::new(Perlin::new()),
+                    },
+                },
+                gray(1.0),
+            ),
+        ],
+    );
+
+    // in front, leaves light on top and dark underneath, one turned over
+    let leaf = material(
+        MaterialKind::TwoSided {
+            front: material(MaterialKind::Lambertian, Vec3::new(0.5, 0.7, 0.2)),
+            back: material(MaterialKind::Lambertian, Vec3::new(0.1, 0.3, 0.1)),
+        },
+        gray(1.0),
+    );
+    world.add(Disk::new(
+        Point::new(4.5, 0.2, 1.6),
+        Vec3::new(0.6, 1.0, 0.0),
+        0.3,
+        leaf.clone(),
+    ));
+    world.add(Disk::new(
+        Point::new(4.5, 0.2, 0.4),
+        Vec3::new(-0.6, -1.0, 0.0),
+        0.3,
+        leaf,
+    ));
+    world
+}
+
+// Surfaces whose detail comes from textures: brushed metals stretched along
+// and around the spheres, metal whose roughness varies, normal mapped studs
+// and bumps, in front a slab of displaced rock, and behind panels with
+// holes.
+fn surfaces() -> World {
+    let mut world = checkered_floor();
+    let aluminum = material(
+        MaterialKind::conductor(MetalPreset::Aluminum, 0.0),
+        gray(1.0),
+    );
+    let brushed = |alpha_x: Texture, alpha_y: Texture, rotation: f64| {
+        material(
+            MaterialKind::Anisotropic {
+                base: aluminum.clone(),
+                alpha_x,
+                alpha_y,
+                rotation: Texture::constant(rotation),
+            },
+            gray(1.0),
+        )
+    };
+    let blotches = Texture::Scale {
+        texture: Arc::new(Texture::Noise {
+            scale: 6.0,
+            noise: Arc::new(Perlin::new()),
+        }),
+        factor: gray(0.3),
+    };
+    // a tangent-space normal map of round studs, eight to a side
+    let size = 128;
+    let studs = (0..size * size)
+        .map(|i| {
+            let cell = |j: usize| (j % 16) as f64 / 8.0 - 15.0 / 16.0;
+            let (a, b) = (cell(i % size), -cell(i / size));
+            let normal = if a * a + b * b < 0.64 {
+                Vec3::new(a, b, (1.0 - a * a - b * b).sqrt())
+            } else {
+                Vec3::new(0.0, 0.0, 1.0)
+            };
+            (normal.unit_vector() + Vec3::new(1.0, 1.0, 1.0)) / 2.0
+        })
+        .collect();
+    let red = material(MaterialKind::Lambertian, Vec3::new(0.7, 0.15, 0.1));
+    let noise = Arc::new(Texture::Noise {
+        scale: 12.0,
+        noise: Arc::new(Perlin::new()),
+    });
+    row_of_spheres(
+        &mut world,
+        1.5,
+        vec![
+            brushed(Texture::constant(0.02), Texture::constant(0.3), 0.0),
+            brushed(Texture::constant(0.02), Texture::constant(0.3), 0.25),
+            brushed(blotches.clone(), blotches, 0.0),
+            material(
+                MaterialKind::NormalMap {
+                    base: red.clone(),
+                    map: Texture::Image(Arc::new(ImageData::new(size, size, studs))),
+                },
+                gray(1.0),
+            ),
+            material(
+                MaterialKind::Bump {
+                    base: red,
+                    height: Texture::Scale {
+                        texture: noise,
+                        factor: gray(0.05),
+                    },
+                },
+                gray(1.0),
+            ),
+        ],
+    );
+
+    // a flat square, split up finely and pushed up into rock
+    let (x, z) = (4.5, 1.0);
+    let slab = Mesh::new(
+        vec![
+            Point::new(x - 0.6, 0.01, z - 0.6),
+            Point::new(x + 0.6, 0.01, z - 0.6),
+            Point::new(x + 0.6, 0.01, z + 0.6),
+            Point::new(x - 0.6, 0.01, z + 0.6),
+        ],
+        vec![[0, 2, 1], [0, 3, 2]],
+        material(MaterialKind::Lambertian, gray(0.4)),
+    );
+    let height = Texture::Scale {
+        texture: Arc::new(Texture::Noise {
+            scale: 3.0,
+            noise: Arc::new(Perlin::new()),
+        }),
+        factor: gray(0.2),
+    };
+    world.add(slab.displaced(&height, 6));
+
+    // behind, a lattice cut out of a panel and a panel that lets half the
+    // light through
+    let cutout = |opacity: Texture, threshold: Option<f64>| {
+        material(
+            MaterialKind::Cutout {
+                base: material(MaterialKind::Lambertian, Vec3::new(0.2, 0.5, 0.2)),
+                opacity,
+                threshold,
+            },
+            gray(1.0),
+        )
+    };
+    let lattice = Texture::Checker {
+        scale: 0.2,
+        even: Arc::new(Texture::constant(1.0)),
+        odd: Arc::new(Texture::constant(0.0)),
+    };
+    world.add(Quad::new(
+        Point::new(-1.5, 0.0, 4.0),
+        Vec3::new(0.0, 0.0, -3.5),
+        Vec3::new(0.0, 1.6, 0.0),
+        cutout(lattice, Some(0.5)),
+    ));
+    world.add(Quad::new(
+        Point::new(-1.5, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -3.5),
+        Vec3::new(0.0, 1.6, 0.0),
+        cutout(Texture::constant(0.5), None),
+    ));
+    world
+}
@@ -0,0 +1,459 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::Point;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::scene::{sphere_derivatives, sphere_uv, HitRecord, Hittable};
+use crate::vec3::Vec3;
+
+const MAX_STEPS: usize = 512;
+// how close to the surface counts as on it
+const HIT_EPSILON: f64 = 1e-6;
+const MIN_STEP: f64 = 1e-5;
+const NORMAL_EPSILON: f64 = 1e-5;
+// a bound on the gradient of `Perlin::noise`
+const NOISE_LIPSCHITZ: f64 = 2.5;
+
+/// A tree of signed distance functions. Shapes are centered at the origin
+/// unless placed with `Translate`.
+pub(crate) enum Sdf {
+    Sphere {
+        radius: f64,
+    },
+    Box {
+        half_extents: Vec3,
+    },
+    // a box of the given half extents with edges rounded off by `radius`
+    RoundBox {
+        half_extents: Vec3,
+        radius: f64,
+    },
+    // around the y axis
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Capsule {
+        a: Point,
+        b: Point,
+        radius: f64,
+    },
+    Translate {
+        offset: Vec3,
+        shape: Box<Sdf>,
+    },
+    // Blend two shapes over a distance `k`. Zero gives the sharp versions.
+    SmoothUnion {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        k: f64,
+    },
+    // `a` with `b` carved out of it
+    SmoothSubtraction {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        k: f64,
+    },
+    SmoothIntersection {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        k: f64,
+    },
+    // copies of `shape` every `spacing`, `count` times in each direction
+    // along each axis; a spacing or count of zero leaves that axis alone
+    Repeat {
+        spacing: Vec3,
+        count: [u32; 3],
+        shape: Box<Sdf>,
+    },
+    // twists `rate` radians per unit around the y axis
+    Twist {
+        rate: f64,
+        shape: Box<Sdf>,
+    },
+    Displace {
+        amplitude: f64,
+        frequency: f64,
+        noise: Arc<Perlin>,
+        shape: Box<Sdf>,
+    },
+}
+
+impl Sdf {
+    pub(crate) fn distance(&self, p: Point) -> f64 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Box { half_extents } => {
+                let q = abs(p) - *half_extents;
+                max(q, 0.0).length() + q.x.max(q.y.max(q.z)).min(0.0)
+            }
+            Sdf::RoundBox {
+                half_extents,
+                radius,
+            } => {
+                let q = abs(p) - *half_extents + Vec3::new(*radius, *radius, *radius);
+                max(q, 0.0).length() + q.x.max(q.y.max(q.z)).min(0.0) - radius
+            }
+            Sdf::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let pa = p - *a;
+                let ba = *b - *a;
+                let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+                (pa - ba * h).length() - radius
+            }
+            Sdf::Translate { offset, shape } => shape.distance(p - *offset),
+            Sdf::SmoothUnion { a, b, k } => smooth_min(a.distance(p), b.distance(p), *k),
+            Sdf::SmoothSubtraction { a, b, k } => -smooth_min(-a.distance(p), b.distance(p), *k),
+            Sdf::SmoothIntersection { a, b, k } => -smooth_min(-a.distance(p), -b.distance(p), *k),
+            Sdf::Repeat {
+                spacing,
+                count,
+                shape,
+            } => {
+                let repeat = |x: f64, s: f64, n: u32| {
+                    if n == 0 || s == 0.0 {
+                        return x;
+                    }
+                    let n = n as f64;
+                    x - s * (x / s).round().clamp(-n, n)
+                };
+                shape.distance(Point::new(
+                    repeat(p.x, spacing.x, count[0]),
+                    repeat(p.y, spacing.y, count[1]),
+                    repeat(p.z, spacing.z, count[2]),
+                ))
+            }
+            Sdf::Twist { rate, shape } => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                shape.distance(Point::new(
+                    cos * p.x - sin * p.z,
+                    p.y,
+                    sin * p.x + cos * p.z,
+                ))
+            }
+            Sdf::Displace {
+                amplitude,
+                frequency,
+                noise,
+                shape,
+            } => shape.distance(p) + amplitude * noise.noise(p * *frequency),
+        }
+    }
+
+    /// A box around everything inside the shape.
+    pub(crate) fn bounds(&self) -> Aabb {
+        match self {
+            Sdf::Sphere { radius } => {
+                let r = Vec3::new(*radius, *radius, *radius);
+                Aabb::from_points(-r, r)
+            }
+            Sdf::Box { half_extents } | Sdf::RoundBox { half_extents, .. } => {
+                Aabb::from_points(-*half_extents, *half_extents)
+            }
+            Sdf::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let xz = major_radius + minor_radius;
+                let extent = Vec3::new(xz, *minor_radius, xz);
+                Aabb::from_points(-extent, extent)
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let r = Vec3::new(*radius, *radius, *radius);
+                Aabb::enclosing(
+                    Aabb::from_points(*a - r, *a + r),
+                    Aabb::from_points(*b - r, *b + r),
+                )
+            }
+            Sdf::Translate { offset, shape } => {
+                let b = shape.bounds();
+                Aabb::new(
+                    Interval::new(b.x.min + offset.x, b.x.max + offset.x),
+                    Interval::new(b.y.min + offset.y, b.y.max + offset.y),
+                    Interval::new(b.z.min + offset.z, b.z.max + offset.z),
+                )
+            }
+            // Smooth blending adds at most k / 4 around the seams.
+            Sdf::SmoothUnion { a, b, k } => grow(Aabb::enclosing(a.bounds(), b.bounds()), k / 4.0),
+            Sdf::SmoothSubtraction { a, .. } => a.bounds(),
+            Sdf::SmoothIntersection { a, b, .. } => {
+                let (a, b) = (a.bounds(), b.bounds());
+                Aabb::new(
+                    Interval::new(a.x.min.max(b.x.min), a.x.max.min(b.x.max)),
+                    Interval::new(a.y.min.max(b.y.min), a.y.max.min(b.y.max)),
+                    Interval::new(a.z.min.max(b.z.min), a.z.max.min(b.z.max)),
+                )
+            }
+            Sdf::Repeat {
+                spacing,
+                count,
+                shape,
+            } => {
+                let b = shape.bounds();
+                let reach = |s: f64, n: u32| s.abs() * n as f64;
+                let (rx, ry, rz) = (
+                    reach(spacing.x, count[0]),
+                    reach(spacing.y, count[1]),
+                    reach(spacing.z, count[2]),
+                );
+                Aabb::new(
+                    Interval::new(b.x.min - rx, b.x.max + rx),
+                    Interval::new(b.y.min - ry, b.y.max + ry),
+                    Interval::new(b.z.min - rz, b.z.max + rz),
+                )
+            }
+            Sdf::Twist { shape, .. } => {
+                let b = shape.bounds();
+                let r = xz_radius(&b);
+                Aabb::new(Interval::new(-r, r), b.y, Interval::new(-r, r))
+            }
+            Sdf::Displace {
+                amplitude, shape, ..
+            } => grow(shape.bounds(), amplitude.abs()),
+        }
+    }
+
+    // An upper bound on how fast the distance can change. Where it's above
+    // one, the value underestimates the distance by at most that factor.
+    fn lipschitz(&self) -> f64 {
+        match self {
+            Sdf::Sphere { .. }
+            | Sdf::Box { .. }
+            | Sdf::RoundBox { .. }
+            | Sdf::Torus { .. }
+            | Sdf::Capsule { .. } => 1.0,
+            Sdf::Translate { shape, .. } | Sdf::Repeat { shape, .. } => shape.lipschitz(),
+            Sdf::SmoothUnion { a, b, .. }
+            | Sdf::SmoothSubtraction { a, b, .. }
+            | Sdf::SmoothIntersection { a, b, .. } => a.lipschitz().max(b.lipschitz()),
+            Sdf::Twist { rate, shape } => {
+                let r = xz_radius(&shape.bounds());
+                shape.lipschitz() * (1.0 + (rate * r).powi(2)).sqrt()
+            }
+            Sdf::Displace {
+                amplitude,
+                frequency,
+                shape,
+                ..
+            } => shape.lipschitz() + (amplitude * frequency).abs() * NOISE_LIPSCHITZ,
+        }
+    }
+}
+
+fn abs(v: Vec3) -> Vec3 {
+    Vec3::new(v.x.abs(), v.y.abs(), v.z.abs())
+}
+
+fn max(v: Vec3, m: f64) -> Vec3 {
+    Vec3::new(v.x.max(m), v.y.max(m), v.z.max(m))
+}
+
+// polynomial smooth minimum
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k / 4.0
+}
+
+fn grow(bbox: Aabb, delta: f64) -> Aabb {
+    Aabb::new(
+        bbox.x.expand(2.0 * delta),
+        bbox.y.expand(2.0 * delta),
+        bbox.z.expand(2.0 * delta),
+    )
+}
+
+// the largest distance from the y axis within the box
+fn xz_radius(bbox: &Aabb) -> f64 {
+    let x = bbox.x.min.abs().max(bbox.x.max.abs());
+    let z = bbox.z.min.abs().max(bbox.z.max.abs());
+    (x * x + z * z).sqrt()
+}
+
+/// Renders an `Sdf` tree by sphere tracing, so that it can sit in the world
+/// next to analytic shapes.
+pub(crate) struct SdfObject {
+    sdf: Sdf,
+    material: Arc<Material>,
+    bbox: Aabb,
+    step_scale: f64,
+}
+
+impl SdfObject {
+    pub(crate) fn new(sdf: Sdf, material: Arc<Material>) -> Self {
+        let bbox = grow(sdf.bounds(), 1e-3);
+        let step_scale = 1.0 / sdf.lipschitz();
+        Self {
+            sdf,
+            material,
+            bbox,
+            step_scale,
+        }
+    }
+
+    fn normal(&self, p: Point) -> Vec3 {
+        let e = NORMAL_EPSILON;
+        let d = |offset: Vec3| self.sdf.distance(p + offset) - self.sdf.distance(p - offset);
+        Vec3::new(
+            d(Vec3::new(e, 0.0, 0.0)),
+            d(Vec3::new(0.0, e, 0.0)),
+            d(Vec3::new(0.0, 0.0, e)),
+        )
+        .unit_vector()
+    }
+
+    fn bisect(&self, origin: Point, direction: Vec3, side: f64, mut lo: f64, mut hi: f64) -> f64 {
+        for _ in 0..32 {
+            let mid = 0.5 * (lo + hi);
+            if side * self.sdf.distance(origin + direction * mid) < 0.0 {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        hi
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let ray_t = self.bbox.clip(ray, ray_t)?;
+
+        // March in units of distance, with a normalized direction.
+        let ray_length = ray.direction.length();
+        let direction = ray.direction / ray_length;
+        let (start, end) = (ray_t.min * ray_length, ray_t.max * ray_length);
+
+        // Rays that start inside look for the way out.
+        let side = self.sdf.distance(ray.origin + direction * start).signum();
+        let mut previous = start;
+        let mut t = start;
+        let mut surface = None;
+        for _ in 0..MAX_STEPS {
+            let distance = side * self.sdf.distance(ray.origin + direction * t);
+            if distance < 0.0 {
+                // stepped across the surface, so find it between the steps
+                surface = Some(self.bisect(ray.origin, direction, side, previous, t));
+                break;
+            }
+            if distance < HIT_EPSILON && t > start {
+                surface = Some(t);
+                break;
+            }
+            previous = t;
+            t += (distance * self.step_scale).max(MIN_STEP);
+            if t >= end {
+                return None;
+            }
+        }
+
+        let t = surface?;
+        let t_ray = t / ray_length;
+        if !ray_t.surrounds(t_ray) {
+            return None;
+        }
+        let p = ray.origin + direction * t;
+        let normal = self.normal(p);
+        // UVs map the normal onto the unit sphere, so tangents follow the
+        // sphere's, which lie in the surface since they are normal to it.
+        let (dpdu, dpdv) = sphere_derivatives(normal);
+        Some(
+            HitRecord::new(
+                ray,
+                p,
+                normal,
+                t_ray,
+                sphere_uv(normal),
+                self.material.clone(),
+            )
+            .with_derivatives(dpdu, dpdv),
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MaterialKind;
+
+    fn object(sdf: Sdf) -> SdfObject {
+        let material = Arc::new(Material {
+            kind: MaterialKind::Lambertian,
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        });
+        SdfObject::new(sdf, material)
+    }
+
+    #[test]
+    fn spheres_are_hit_where_the_analytic_sphere_is() {
+        let center = Point::new(1.0, -0.5, 0.25);
+        let sphere = object(Sdf::Translate {
+            offset: center,
+            shape: Box::new(Sdf::Sphere { radius: 1.0 }),
+        });
+        let everywhere = Interval::new(0.001, f64::INFINITY);
+
+        for origin in [Point::new(1.0, -0.5, 5.0), Point::new(1.4, -0.2, 5.0)] {
+            // a direction of length two, so that t isn't the distance
+            let ray = Ray::new(origin, Vec3::new(0.0, 0.0, -2.0));
+            let hit = sphere.hit(&ray, everywhere).unwrap();
+            let oc = origin - center;
+            let expected = (oc.z - (1.0 - oc.x * oc.x - oc.y * oc.y).sqrt()) / 2.0;
+            assert!((hit.t - expected).abs() < 1e-6, "{} vs {expected}", hit.t);
+            assert!(hit.front_face);
+            let normal = (ray.at(expected) - center).unit_vector();
+            assert!((hit.normal - normal).length() < 1e-4, "{:?}", hit.normal);
+        }
+
+        // from the center, the way out is a radius away
+        let ray = Ray::new(center, Vec3::new(0.6, 0.0, 0.8));
+        let hit = sphere.hit(&ray, everywhere).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6, "{}", hit.t);
+        assert!(!hit.front_face);
+        assert!((hit.normal + Vec3::new(0.6, 0.0, 0.8)).length() < 1e-4);
+    }
+
+    #[test]
+    fn twisted_shapes_are_not_stepped_through() {
+        // Twisting turns points far from the axis quickly, so unscaled
+        // steps along the axis would skip past the thin blade.
+        let blade = object(Sdf::Twist {
+            rate: 3.0,
+            shape: Box::new(Sdf::Box {
+                half_extents: Vec3::new(1.0, 1.0, 0.05),
+            }),
+        });
+        let mut hits = 0;
+        for i in 0..50 {
+            let angle = std::f64::consts::TAU * i as f64 / 50.0;
+            let origin = Point::new(0.9 * angle.cos(), -2.0, 0.9 * angle.sin());
+            let ray = Ray::new(origin, Vec3::new(0.0, 1.0, 0.0));
+            let Some(hit) = blade.hit(&ray, Interval::new(0.001, f64::INFINITY)) else {
+                continue;
+            };
+            hits += 1;
+            assert!(blade.sdf.distance(hit.p).abs() < 1e-4);
+            let steps = 2000;
+            for k in 0..steps {
+                let t = hit.t * k as f64 / steps as f64;
+                assert!(blade.sdf.distance(ray.at(t)) > -1e-6, "inside at {t}");
+            }
+        }
+        assert_eq!(hits, 50);
+    }
+}
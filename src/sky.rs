@@ -0,0 +1,352 @@
+use std::f64::consts::PI;
+
+use crate::common::random_f64;
+use crate::environment::Environment;
+use crate::onb::Onb;
+use crate::spectrum::RGB_WAVELENGTHS;
+use crate::texture::ImageData;
+use crate::vec3::Vec3;
+
+// Scenes are laid out with y up, x pointing east and -z north.
+
+/// The direction towards the sun at `elevation` degrees above the horizon
+/// and `azimuth` degrees clockwise from north.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let (sin_elevation, cos_elevation) = elevation.to_radians().sin_cos();
+    let (sin_azimuth, cos_azimuth) = azimuth.to_radians().sin_cos();
+    Vec3::new(
+        cos_elevation * sin_azimuth,
+        sin_elevation,
+        -cos_elevation * cos_azimuth,
+    )
+}
+
+/// The direction towards the sun as seen from `latitude` and `longitude`,
+/// in degrees north and east, on day `day_of_year` (1 to 365) at `hour` of
+/// local standard time, which is `utc_offset` hours ahead of UTC. From the
+/// approximation in the appendix of Preetham et al.
+pub fn solar_direction(
+    latitude: f64,
+    longitude: f64,
+    day_of_year: u32,
+    hour: f64,
+    utc_offset: f64,
+) -> Vec3 {
+    let day = day_of_year as f64;
+    let latitude = latitude.to_radians();
+    let standard_meridian = (15.0 * utc_offset).to_radians();
+    let solar_time = hour + 0.170 * (4.0 * PI * (day - 80.0) / 373.0).sin()
+        - 0.129 * (2.0 * PI * (day - 8.0) / 355.0).sin()
+        + 12.0 * (longitude.to_radians() - standard_meridian) / PI;
+    let declination = 0.4093 * (2.0 * PI * (day - 81.0) / 368.0).sin();
+
+    // from equatorial coordinates to east, north and up
+    let hour_angle = PI * (solar_time - 12.0) / 12.0;
+    let (sin_latitude, cos_latitude) = latitude.sin_cos();
+    let (sin_declination, cos_declination) = declination.sin_cos();
+    let east = -cos_declination * hour_angle.sin();
+    let north = cos_latitude * sin_declination - sin_latitude * cos_declination * hour_angle.cos();
+    let up = sin_latitude * sin_declination + cos_latitude * cos_declination * hour_angle.cos();
+    Vec3::new(east, up, -north)
+}
+
+/// Daylight from a clear sky, after Preetham et al., "A Practical Analytic
+/// Model for Daylight". `turbidity` measures the haze, from 2 for a very
+/// clear sky to about 10 for a hazy one. Below the horizon is a ground of
+/// `ground_albedo` lit by the sky and sun.
+///
+/// Radiances are in kilocandela per square meter, times `intensity`.
+pub struct Sky {
+    sun_direction: Vec3,
+    turbidity: f64,
+    ground_albedo: Vec3,
+    intensity: f64,
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Vec3) -> Self {
+        Self {
+            sun_direction: sun_direction.unit_vector(),
+            turbidity,
+            ground_albedo,
+            intensity: 1.0,
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// The sun's disk, with the color light from it has left after the way
+    /// through the atmosphere at its elevation. Black below the horizon.
+    pub(crate) fn sun(&self) -> Sun {
+        // luminance of the sun outside the atmosphere
+        const SUN_RADIANCE: f64 = 2.0e6;
+        // angular radius
+        const SUN_RADIUS: f64 = 0.2667;
+
+        let theta = self.sun_direction.y.clamp(-1.0, 1.0).acos();
+        let theta_degrees = theta.to_degrees();
+        let radiance = if theta_degrees < 93.885 {
+            // the relative optical mass of the air, and the transmittance of
+            // its molecules and aerosols, at a wavelength in micrometers
+            let mass = 1.0 / (theta.cos() + 0.15 * (93.885 - theta_degrees).powf(-1.253));
+            let beta = 0.04608365822050 * self.turbidity - 0.04586025928522;
+            let transmittance = |lambda: f64| {
+                let lambda = lambda / 1000.0;
+                let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+                let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+                rayleigh * aerosol
+            };
+            let [r, g, b] = RGB_WAVELENGTHS.map(transmittance);
+            Vec3::new(r, g, b) * (SUN_RADIANCE * self.intensity)
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        };
+        Sun {
+            direction: self.sun_direction,
+            cos_radius: SUN_RADIUS.to_radians().cos(),
+            radiance,
+        }
+    }
+
+    /// The sky and ground, without the sun, as an environment map of
+    /// `width` by `width / 2` pixels.
+    pub(crate) fn environment(&self, width: usize) -> Environment {
+        let height = (width / 2).max(1);
+        let direction = |x: usize, y: usize| {
+            // as in `Environment`, with v = 1 at the top row
+            let theta = PI * (1.0 - (y as f64 + 0.5) / height as f64);
+            let phi = 2.0 * PI * (x as f64 + 0.5) / width as f64;
+            Vec3::new(
+                -theta.sin() * phi.cos(),
+                -theta.cos(),
+                theta.sin() * phi.sin(),
+            )
+        };
+
+        let model = Preetham::new(self.turbidity, self.sun_direction);
+        let mut pixels: Vec<Vec3> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| model.radiance(direction(x, y)) * self.intensity)
+            .collect();
+
+        // light falling on the ground from the sky, summed over the upper
+        // rows, and from the sun
+        let solid_angle = |y: usize| {
+            let theta = PI * (1.0 - (y as f64 + 0.5) / height as f64);
+            theta.sin() * (PI / height as f64) * (2.0 * PI / width as f64)
+        };
+        let mut irradiance = Vec3::new(0.0, 0.0, 0.0);
+        for y in 0..height {
+            for x in 0..width {
+                let d = direction(x, y);
+                if d.y > 0.0 {
+                    irradiance += pixels[x + width * y] * (d.y * solid_angle(y));
+                }
+            }
+        }
+        let sun = self.sun();
+        irradiance +=
+            sun.radiance * (2.0 * PI * (1.0 - sun.cos_radius) * self.sun_direction.y.max(0.0));
+        let ground = self.ground_albedo * irradiance / PI;
+        for y in 0..height {
+            for x in 0..width {
+                if direction(x, y).y <= 0.0 {
+                    pixels[x + width * y] = ground;
+                }
+            }
+        }
+
+        Environment::from_image(ImageData::new(width, height, pixels), 1.0, 0.0)
+    }
+}
+
+// The distribution of luminance and chromaticity over the sky.
+struct Preetham {
+    sun_direction: Vec3,
+    // Perez coefficients for luminance and the chromaticities x and y
+    perez: [[f64; 5]; 3],
+    // luminance and chromaticity at the zenith, over their Perez function
+    // there
+    zenith: [f64; 3],
+}
+
+impl Preetham {
+    fn new(turbidity: f64, sun_direction: Vec3) -> Self {
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // the model stops at the horizon, so the sky stays as it is at
+        // sunset once the sun is below it
+        let sun_direction =
+            Vec3::new(sun_direction.x, sun_direction.y.max(0.0), sun_direction.z).unit_vector();
+        let theta_s = sun_direction.y.acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let thetas = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let ts = [t * t, t, 1.0];
+            (0..3)
+                .map(|i| ts[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f64>())
+                .sum::<f64>()
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let mut zenith = [luminance, x, y];
+        for (value, coefficients) in zenith.iter_mut().zip(&perez) {
+            *value /= perez_function(coefficients, 1.0, theta_s);
+        }
+        Self {
+            sun_direction,
+            perez,
+            zenith,
+        }
+    }
+
+    // Linear sRGB radiance in kilocandela per square meter, for directions
+    // above the horizon.
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let cos_theta = direction.y.max(1e-3);
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] =
+            [0, 1, 2].map(|i| self.zenith[i] * perez_function(&self.perez[i], cos_theta, gamma));
+
+        let (big_x, big_z) = (x * luminance / y, (1.0 - x - y) * luminance / y);
+        let big_y = luminance;
+        Vec3::new(
+            (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+            (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+            (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0),
+        )
+    }
+}
+
+fn perez_function(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / cos_theta).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+/// A distant light of constant radiance over a small disk of directions.
+pub(crate) struct Sun {
+    direction: Vec3,
+    // of its angular radius
+    cos_radius: f64,
+    radiance: Vec3,
+}
+
+impl Sun {
+    pub(crate) fn radiance(&self, direction: Vec3) -> Vec3 {
+        if direction.unit_vector().dot(self.direction) >= self.cos_radius {
+            self.radiance
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    /// Samples a direction towards the disk, uniformly over its solid angle,
+    /// returning it with its density.
+    pub(crate) fn sample(&self) -> Option<(Vec3, f64)> {
+        if self.radiance.length_squared() <= 0.0 {
+            return None;
+        }
+        let cos_theta = 1.0 - random_f64() * (1.0 - self.cos_radius);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let direction = Onb::new(self.direction).to_world(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        Some((direction, 1.0 / (2.0 * PI * (1.0 - self.cos_radius))))
+    }
+
+    pub(crate) fn pdf(&self, direction: Vec3) -> f64 {
+        if direction.unit_vector().dot(self.direction) >= self.cos_radius {
+            1.0 / (2.0 * PI * (1.0 - self.cos_radius))
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // elevation above the horizon and azimuth clockwise from north, in
+    // degrees
+    fn angles(direction: Vec3) -> (f64, f64) {
+        let elevation = direction.y.asin().to_degrees();
+        let azimuth = direction
+            .x
+            .atan2(-direction.z)
+            .to_degrees()
+            .rem_euclid(360.0);
+        (elevation, azimuth)
+    }
+
+    #[test]
+    fn sun_angles_round_trip() {
+        let (elevation, azimuth) = angles(sun_direction(35.0, 250.0));
+        assert!((elevation - 35.0).abs() < 1e-9);
+        assert!((azimuth - 250.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn solar_direction_matches_the_ephemeris() {
+        // Greenwich at noon on the June solstice, when NOAA's solar
+        // calculator puts the sun at 61.96 degrees, just east of south
+        let (elevation, azimuth) = angles(solar_direction(51.4779, 0.0, 172, 12.0, 0.0));
+        assert!((elevation - 61.96).abs() < 0.1, "{elevation}");
+        assert!((azimuth - 179.4).abs() < 1.0, "{azimuth}");
+
+        // rising due east at the equator on the March equinox, at 6:07
+        // local mean time
+        let (elevation, azimuth) = angles(solar_direction(0.0, 0.0, 80, 6.12, 0.0));
+        assert!(elevation.abs() < 0.5, "{elevation}");
+        assert!((azimuth - 90.0).abs() < 0.5, "{azimuth}");
+
+        // New York at 15:00 EST on New Year's Day, and Tokyo at 14:00 JST on
+        // April 10, from NOAA's solar position equations
+        let (elevation, azimuth) = angles(solar_direction(40.7128, -74.006, 1, 15.0, -5.0));
+        assert!((elevation - 13.65).abs() < 0.5, "{elevation}");
+        assert!((azimuth - 222.25).abs() < 1.0, "{azimuth}");
+        let (elevation, azimuth) = angles(solar_direction(35.6762, 139.6503, 100, 14.0, 9.0));
+        assert!((elevation - 47.94).abs() < 0.5, "{elevation}");
+        assert!((azimuth - 236.33).abs() < 1.0, "{azimuth}");
+    }
+}
@@ -0,0 +1,495 @@
+use std::sync::OnceLock;
+
+use crate::common::random_f64;
+use crate::vec3::Vec3;
+
+// Wavelengths in nanometers covered when integrating spectra to colors.
+pub(crate) const LAMBDA_MIN: f64 = 360.0;
+pub(crate) const LAMBDA_MAX: f64 = 830.0;
+
+/// The CIE 1931 color matching functions, using the multi-lobe Gaussian fit
+/// from Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE
+/// XYZ Color Matching Functions".
+pub(crate) fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub(crate) fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+/// Spectral radiance of a black body at `lambda` nanometers and
+/// `temperature` kelvin, from Planck's law.
+pub(crate) fn planck(lambda: f64, temperature: f64) -> f64 {
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const KB: f64 = 1.380_649e-23;
+    let l = lambda * 1e-9;
+    (2.0 * H * C * C) / (l.powi(5) * ((H * C / (l * KB * temperature)).exp() - 1.0))
+}
+
+/// The linear sRGB color of a black body, scaled so that one at 6500 K has a
+/// luminance of one. Hotter bodies are brighter as well as bluer.
+pub(crate) fn blackbody(temperature: f64) -> Vec3 {
+    let xyz = |temperature: f64| {
+        const STEPS: usize = 95;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64;
+        (0..STEPS)
+            .map(|i| {
+                let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+                cie_xyz(lambda) * planck(lambda, temperature)
+            })
+            .sum::<Vec3>()
+    };
+    let color = xyz(temperature) / xyz(6500.0).y;
+    let rgb = xyz_to_linear_srgb(color);
+    // cooler black bodies are outside the sRGB gamut
+    Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0))
+}
+
+// The linear sRGB color of light at `lambda` nanometers, scaled so that
+// the average over all wavelengths of a flat spectrum is white.
+fn wavelength_weight(lambda: f64) -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        const STEPS: usize = 470;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64;
+        (0..STEPS)
+            .map(|i| xyz_to_linear_srgb(cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * step)) * step)
+            .sum()
+    });
+    let rgb = xyz_to_linear_srgb(cie_xyz(lambda)) * (LAMBDA_MAX - LAMBDA_MIN);
+    Vec3::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+}
+
+/// Picks a wavelength uniformly, returning it with the linear sRGB weight
+/// that makes the average over many wavelengths white. The weights of single
+/// wavelengths lie outside the gamut, with negative channels.
+pub(crate) fn sample_wavelength() -> (f64, Vec3) {
+    let lambda = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * random_f64();
+    (lambda, wavelength_weight(lambda))
+}
+
+// Wavelengths at which the channels of tabulated RGB data, such as the
+// conductors in microfacet.rs, are taken to sample their spectra, and at
+// which RGB rendering evaluates wavelength-dependent effects like thin films.
+pub(crate) const RGB_WAVELENGTHS: [f64; 3] = [610.0, 550.0, 465.0];
+
+/// Three wavelengths spread evenly over the visible range from a randomly
+/// placed hero wavelength, after Wilkie et al., "Hero Wavelength Spectral
+/// Sampling". When rendering spectrally, the channels of the colors carried
+/// along a ray hold values at these wavelengths instead of red, green and
+/// blue.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SampledWavelengths {
+    pub(crate) lambda: [f64; 3],
+}
+
+impl SampledWavelengths {
+    pub(crate) fn sample() -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + range * random_f64();
+        let lambda = [0.0, 1.0, 2.0].map(|i| {
+            let lambda = hero + i * range / 3.0;
+            if lambda > LAMBDA_MAX {
+                lambda - range
+            } else {
+                lambda
+            }
+        });
+        Self { lambda }
+    }
+
+    /// The wavelength that keeps going when the others are dropped, as
+    /// they are where dispersion sends each wavelength its own way.
+    pub(crate) fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// The spectrum upsampled from a linear sRGB color, at these
+    /// wavelengths. Colors within [0, 1] give smooth reflectance spectra
+    /// within [0, 1] too, and brighter ones are scaled down and back up.
+    pub(crate) fn upsample(&self, rgb: Vec3) -> Vec3 {
+        let rgb = Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+        let max = rgb.x.max(rgb.y).max(rgb.z);
+        let (scale, rgb) = if max > 1.0 {
+            (2.0 * max, rgb / (2.0 * max))
+        } else {
+            (1.0, rgb)
+        };
+        let coefficients = RgbToSpectrumTable::get().lookup(rgb);
+        let [a, b, c] = self
+            .lambda
+            .map(|lambda| scale * sigmoid_polynomial(coefficients, lambda));
+        Vec3::new(a, b, c)
+    }
+
+    /// Values at these wavelengths interpolated linearly between the
+    /// channels of RGB data that sample a spectrum, like tabulated indices
+    /// of refraction, and held constant beyond them.
+    pub(crate) fn interpolate(&self, samples: Vec3) -> Vec3 {
+        let [red, green, blue] = RGB_WAVELENGTHS;
+        let [a, b, c] = self.lambda.map(|lambda| {
+            if lambda <= blue {
+                samples.z
+            } else if lambda <= green {
+                samples.z + (samples.y - samples.z) * (lambda - blue) / (green - blue)
+            } else if lambda <= red {
+                samples.y + (samples.x - samples.y) * (lambda - green) / (red - green)
+            } else {
+                samples.x
+            }
+        });
+        Vec3::new(a, b, c)
+    }
+
+    /// The linear sRGB color of spectral radiance sampled at these
+    /// wavelengths, white for a flat spectrum.
+    pub(crate) fn to_rgb(self, values: Vec3) -> Vec3 {
+        (wavelength_weight(self.lambda[0]) * values.x
+            + wavelength_weight(self.lambda[1]) * values.y
+            + wavelength_weight(self.lambda[2]) * values.z)
+            / 3.0
+    }
+}
+
+// A spectrum sigmoid(c0 t^2 + c1 t + c2) over the visible range, with t
+// from 0 to 1, as in Jakob and Hanika, "A Low-Dimensional Function Space for
+// Efficient Spectral Upsampling".
+fn sigmoid_polynomial(c: [f64; 3], lambda: f64) -> f64 {
+    let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+    let x = (c[0] * t + c[1]) * t + c[2];
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+const TABLE_RES: usize = 16;
+
+// Sigmoid polynomial coefficients fitted to colors on a grid, indexed by
+// the largest channel, its value z, and the other two channels as fractions
+// of it, following pbrt-v4's RGBToSpectrumTable. The z nodes crowd towards
+// 0 and 1, where the coefficients change fastest.
+struct RgbToSpectrumTable {
+    z_nodes: [f64; TABLE_RES],
+    coefficients: Vec<[f64; 3]>,
+}
+
+impl RgbToSpectrumTable {
+    // Fitted on first use, which takes a fraction of a second.
+    fn get() -> &'static Self {
+        static TABLE: OnceLock<RgbToSpectrumTable> = OnceLock::new();
+        TABLE.get_or_init(Self::fit)
+    }
+
+    fn index(max_channel: usize, zi: usize, yi: usize, xi: usize) -> usize {
+        ((max_channel * TABLE_RES + zi) * TABLE_RES + yi) * TABLE_RES + xi
+    }
+
+    fn fit() -> Self {
+        let smoothstep = |x: f64| x * x * (3.0 - 2.0 * x);
+        let z_nodes =
+            std::array::from_fn(|i| smoothstep(smoothstep(i as f64 / (TABLE_RES - 1) as f64)));
+        let mut coefficients = vec![[0.0; 3]; 3 * TABLE_RES.pow(3)];
+
+        // Sweep z out from a start node, seeding each fit with the last.
+        let start = TABLE_RES / 5;
+        for max_channel in 0..3 {
+            for yi in 0..TABLE_RES {
+                for xi in 0..TABLE_RES {
+                    let x = xi as f64 / (TABLE_RES - 1) as f64;
+                    let y = yi as f64 / (TABLE_RES - 1) as f64;
+                    let mut fit_node = |zi: usize, c: [f64; 3]| {
+                        let z: f64 = z_nodes[zi];
+                        let mut rgb = [0.0; 3];
+                        rgb[max_channel] = z;
+                        rgb[(max_channel + 1) % 3] = x * z;
+                        rgb[(max_channel + 2) % 3] = y * z;
+                        let c = fit_coefficients(Vec3::new(rgb[0], rgb[1], rgb[2]), c);
+                        coefficients[Self::index(max_channel, zi, yi, xi)] = c;
+                        c
+                    };
+                    let start_c = fit_node(start, [0.0; 3]);
+                    let mut c = start_c;
+                    for zi in start + 1..TABLE_RES {
+                        c = fit_node(zi, c);
+                    }
+                    let mut c = start_c;
+                    for zi in (0..start).rev() {
+                        c = fit_node(zi, c);
+                    }
+                }
+            }
+        }
+        Self {
+            z_nodes,
+            coefficients,
+        }
+    }
+
+    fn lookup(&self, rgb: Vec3) -> [f64; 3] {
+        let rgb = [rgb.x, rgb.y, rgb.z].map(|c| c.clamp(0.0, 1.0));
+        if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+            // flat spectra have an exact solution
+            let v = rgb[0];
+            return [0.0, 0.0, (v - 0.5) / (v * (1.0 - v)).sqrt()];
+        }
+
+        let max_channel = if rgb[0] >= rgb[1] && rgb[0] >= rgb[2] {
+            0
+        } else if rgb[1] >= rgb[2] {
+            1
+        } else {
+            2
+        };
+        let z = rgb[max_channel];
+        let scale = (TABLE_RES - 1) as f64 / z;
+        let x = rgb[(max_channel + 1) % 3] * scale;
+        let y = rgb[(max_channel + 2) % 3] * scale;
+        let zi = self
+            .z_nodes
+            .partition_point(|&node| node <= z)
+            .clamp(1, TABLE_RES - 1)
+            - 1;
+        let xi = (x as usize).min(TABLE_RES - 2);
+        let yi = (y as usize).min(TABLE_RES - 2);
+        let dz = (z - self.z_nodes[zi]) / (self.z_nodes[zi + 1] - self.z_nodes[zi]);
+        let (dx, dy) = (x - xi as f64, y - yi as f64);
+
+        let mut c = [0.0; 3];
+        for (k, c) in c.iter_mut().enumerate() {
+            let at = |dzi: usize, dyi: usize, dxi: usize| {
+                self.coefficients[Self::index(max_channel, zi + dzi, yi + dyi, xi + dxi)][k]
+            };
+            let lerp = |t: f64, a: f64, b: f64| a + (b - a) * t;
+            *c = lerp(
+                dz,
+                lerp(
+                    dy,
+                    lerp(dx, at(0, 0, 0), at(0, 0, 1)),
+                    lerp(dx, at(0, 1, 0), at(0, 1, 1)),
+                ),
+                lerp(
+                    dy,
+                    lerp(dx, at(1, 0, 0), at(1, 0, 1)),
+                    lerp(dx, at(1, 1, 0), at(1, 1, 1)),
+                ),
+            );
+        }
+        c
+    }
+}
+
+// Gauss-Newton iterations from `c` towards coefficients whose spectrum has
+// the color `rgb`.
+fn fit_coefficients(rgb: Vec3, mut c: [f64; 3]) -> [f64; 3] {
+    const SAMPLES: usize = 48;
+    static WEIGHTS: OnceLock<Vec<(f64, Vec3)>> = OnceLock::new();
+    let weights = WEIGHTS.get_or_init(|| {
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / SAMPLES as f64;
+        (0..SAMPLES)
+            .map(|i| {
+                let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+                (lambda, wavelength_weight(lambda) / SAMPLES as f64)
+            })
+            .collect()
+    });
+    let residual = |c: [f64; 3]| {
+        weights
+            .iter()
+            .map(|&(lambda, weight)| weight * sigmoid_polynomial(c, lambda))
+            .sum::<Vec3>()
+            - rgb
+    };
+
+    let mut r = residual(c);
+    for _ in 0..50 {
+        if r.length() < 1e-6 {
+            break;
+        }
+        // finite difference Jacobian, one column per coefficient
+        let columns: [Vec3; 3] = std::array::from_fn(|j| {
+            let mut dc = c;
+            dc[j] += 1e-5;
+            (residual(dc) - r) / 1e-5
+        });
+        let det = columns[0].dot(columns[1].cross(columns[2]));
+        if det.abs() < 1e-15 {
+            break;
+        }
+        // Cramer's rule for the step that zeroes the linearized residual,
+        // halved until it actually reduces the residual
+        let mut step = [
+            r.dot(columns[1].cross(columns[2])) / det,
+            columns[0].dot(r.cross(columns[2])) / det,
+            columns[0].dot(columns[1].cross(r)) / det,
+        ];
+        for _ in 0..20 {
+            let next = [c[0] - step[0], c[1] - step[1], c[2] - step[2]];
+            let next_r = residual(next);
+            if next_r.length() < r.length() {
+                c = next;
+                r = next_r;
+                break;
+            }
+            step = step.map(|s| s / 2.0);
+        }
+    }
+    c
+}
+
+/// An index of refraction that varies with wavelength, for dispersion.
+/// Coefficients take wavelengths in micrometers, as they are usually given.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Dispersion {
+    // Cauchy's equation, n = a + b / lambda^2
+    Cauchy { a: f64, b: f64 },
+    // the Sellmeier equation, n^2 = 1 + sum of b lambda^2 / (lambda^2 - c)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, a common crown glass.
+    pub(crate) const BK7: Self = Dispersion::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    };
+    /// Schott N-SF11, a dense flint glass that disperses strongly.
+    pub(crate) const SF11: Self = Dispersion::Sellmeier {
+        b: [1.737_596_95, 0.313_747_346, 1.898_781_01],
+        c: [0.013_188_707, 0.062_306_814_2, 155.236_29],
+    };
+    /// Fused silica.
+    pub(crate) const FUSED_SILICA: Self = Dispersion::Sellmeier {
+        b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+        c: [0.004_679_148, 0.013_512_063, 97.934_003],
+    };
+
+    /// The index of refraction at `lambda` nanometers.
+    pub(crate) fn ior(&self, lambda: f64) -> f64 {
+        let l2 = (lambda / 1000.0).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                n2.max(1.0).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::microfacet::MetalPreset;
+
+    // The average of `f` over hero wavelengths spread evenly over the
+    // visible range, standing in for the average over many random ones.
+    fn average(f: impl Fn(SampledWavelengths) -> Vec3) -> Vec3 {
+        const STEPS: usize = 300;
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        (0..STEPS)
+            .map(|i| {
+                let hero = LAMBDA_MIN + range * (i as f64 + 0.5) / STEPS as f64;
+                let lambda = [0.0, 1.0, 2.0].map(|j| {
+                    let lambda = hero + j * range / 3.0;
+                    if lambda > LAMBDA_MAX {
+                        lambda - range
+                    } else {
+                        lambda
+                    }
+                });
+                f(SampledWavelengths { lambda })
+            })
+            .sum::<Vec3>()
+            / STEPS as f64
+    }
+
+    #[test]
+    fn flat_spectra_are_white() {
+        let white = average(|wavelengths| wavelengths.to_rgb(Vec3::new(1.0, 1.0, 1.0)));
+        assert!(
+            (white - Vec3::new(1.0, 1.0, 1.0)).length() < 1e-2,
+            "{white:?}"
+        );
+
+        let wavelengths = SampledWavelengths::sample();
+        let gray = wavelengths.upsample(Vec3::new(0.5, 0.5, 0.5));
+        assert!(
+            (gray - Vec3::new(0.5, 0.5, 0.5)).length() < 1e-9,
+            "{gray:?}"
+        );
+    }
+
+    #[test]
+    fn upsampled_colors_come_back() {
+        for rgb in [
+            Vec3::new(0.8, 0.3, 0.1),
+            Vec3::new(0.1, 0.6, 0.2),
+            Vec3::new(0.2, 0.3, 0.9),
+            Vec3::new(0.95, 0.9, 0.05),
+        ] {
+            let values = SampledWavelengths::sample().upsample(rgb);
+            for value in [values.x, values.y, values.z] {
+                assert!((0.0..=1.0).contains(&value), "{value}");
+            }
+            let back = average(|wavelengths| wavelengths.to_rgb(wavelengths.upsample(rgb)));
+            assert!(
+                (back - rgb).length() < 2e-2,
+                "{rgb:?} came back as {back:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn bk7_has_its_catalog_index() {
+        // Schott's nd, at the helium d line
+        assert!((Dispersion::BK7.ior(587.6) - 1.5168).abs() < 1e-4);
+        assert!(Dispersion::BK7.ior(450.0) > Dispersion::BK7.ior(650.0));
+    }
+
+    #[test]
+    fn measured_metals_vary_across_the_spectrum() {
+        // reflectance at normal incidence
+        let reflectance = |metal: MetalPreset, lambda: f64| {
+            let (eta, k) = metal.eta_k_at(lambda);
+            ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k)
+        };
+        // gold and copper reflect red far better than blue, silver and
+        // aluminum nearly alike
+        assert!(reflectance(MetalPreset::Gold, 650.0) > 0.9);
+        assert!(reflectance(MetalPreset::Gold, 450.0) < 0.45);
+        assert!(reflectance(MetalPreset::Copper, 650.0) > 0.9);
+        assert!(reflectance(MetalPreset::Copper, 450.0) < 0.65);
+        for metal in [MetalPreset::Silver, MetalPreset::Aluminum] {
+            assert!(reflectance(metal, 450.0) > 0.85);
+            assert!(reflectance(metal, 650.0) > 0.85);
+        }
+        // held constant beyond the measurements
+        assert_eq!(
+            MetalPreset::Gold.eta_k_at(100.0),
+            MetalPreset::Gold.eta_k_at(354.2)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::common::Point;
+use crate::perlin::Perlin;
+use crate::vec3::Vec3;
+
+/// Pixels of an image in linear color, with the top row first.
+pub(crate) struct ImageData {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl ImageData {
+    /// Pixels given in rows from the top.
+    pub(crate) fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Loads an image file. `srgb` decodes 8 and 16 bit images from the sRGB
+    /// transfer curve, as wanted for colors but not for data such as
+    /// roughness. Floating point images are always taken as linear.
+    pub(crate) fn load(path: impl AsRef<Path>, srgb: bool) -> image::ImageResult<Self> {
+        let image = image::open(path)?;
+        let is_float = matches!(
+            image.color(),
+            image::ColorType::Rgb32F | image::ColorType::Rgba32F
+        );
+        let decode = |c: f32| {
+            let c = c as f64;
+            if !srgb || is_float {
+                c
+            } else if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let image = image.to_rgb32f();
+        let pixels = image
+            .pixels()
+            .map(|p| Vec3::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        Ok(Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels,
+        })
+    }
+
+    /// Loads the alpha channel of an image file into all three channels, or
+    /// its first channel, linearly, if it has no alpha.
+    pub(crate) fn load_alpha(path: impl AsRef<Path>) -> image::ImageResult<Self> {
+        let image = image::open(path)?;
+        let channel = if image.color().has_alpha() { 3 } else { 0 };
+        let image = image.to_rgba32f();
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                let value = p[channel] as f64;
+                Vec3::new(value, value, value)
+            })
+            .collect();
+        Ok(Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels,
+        })
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The pixel in column `x` and row `y`, counting rows from the top.
+    pub(crate) fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[x + self.width * y]
+    }
+
+    // Bilinear lookup with u and v wrapping around, v pointing up.
+    pub(crate) fn sample(&self, u: f64, v: f64) -> Vec3 {
+        if self.pixels.is_empty() {
+            return Vec3::new(0.0, 1.0, 1.0);
+        }
+        let x = (u - u.floor()) * self.width as f64 - 0.5;
+        let y = (1.0 - (v - v.floor())) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let wrap = |i: f64, n: usize| (i as i64).rem_euclid(n as i64) as usize;
+        let at =
+            |i: f64, j: f64| self.pixels[wrap(i, self.width) + self.width * wrap(j, self.height)];
+        at(x0, y0) * ((1.0 - fx) * (1.0 - fy))
+            + at(x0 + 1.0, y0) * (fx * (1.0 - fy))
+            + at(x0, y0 + 1.0) * ((1.0 - fx) * fy)
+            + at(x0 + 1.0, y0 + 1.0) * (fx * fy)
+    }
+}
+
+/// A color, or a scalar in every channel, that varies over a surface.
+#[derive(Clone)]
+pub(crate) enum Texture {
+    Constant(Vec3),
+    // looked up by the hit's UV coordinates
+    Image(Arc<ImageData>),
+    // a 3D checkerboard of cubes with sides `scale`
+    Checker {
+        scale: f64,
+        even: Arc<Texture>,
+        odd: Arc<Texture>,
+    },
+    // marble-like turbulence
+    Noise {
+        scale: f64,
+        noise: Arc<Perlin>,
+    },
+    // one channel of another texture in all three, such as the roughness
+    // packed into the green channel of a glTF texture
+    Channel {
+        texture: Arc<Texture>,
+        channel: usize,
+    },
+    // another texture multiplied by a constant per channel
+    Scale {
+        texture: Arc<Texture>,
+        factor: Vec3,
+    },
+}
+
+impl Texture {
+    pub(crate) fn constant(value: f64) -> Self {
+        Texture::Constant(Vec3::new(value, value, value))
+    }
+
+    pub(crate) fn image(path: impl AsRef<Path>, srgb: bool) -> image::ImageResult<Self> {
+        Ok(Texture::Image(Arc::new(ImageData::load(path, srgb)?)))
+    }
+
+    /// An image's alpha channel, for opacity masks.
+    pub(crate) fn image_alpha(path: impl AsRef<Path>) -> image::ImageResult<Self> {
+        Ok(Texture::Image(Arc::new(ImageData::load_alpha(path)?)))
+    }
+
+    pub(crate) fn value(&self, u: f64, v: f64, p: Point) -> Vec3 {
+        match self {
+            Texture::Constant(color) => *color,
+            Texture::Image(image) => image.sample(u, v),
+            Texture::Checker { scale, even, odd } => {
+                let cell = |x: f64| (x / scale).floor() as i64;
+                if (cell(p.x) + cell(p.y) + cell(p.z)).rem_euclid(2) == 0 {
+                    even.value(u, v, p)
+                } else {
+                    odd.value(u, v, p)
+                }
+            }
+            Texture::Noise { scale, noise } => {
+                let value = 0.5 * (1.0 + (scale * p.z + 10.0 * noise.turbulence(p, 7)).sin());
+                Vec3::new(value, value, value)
+            }
+            Texture::Channel { texture, channel } => {
+                let color = texture.value(u, v, p);
+                let value = match channel {
+                    0 => color.x,
+                    1 => color.y,
+                    _ => color.z,
+                };
+                Vec3::new(value, value, value)
+            }
+            Texture::Scale { texture, factor } => texture.value(u, v, p) * *factor,
+        }
+    }
+
+    /// The first channel, for textures of a single quantity.
+    pub(crate) fn scalar(&self, u: f64, v: f64, p: Point) -> f64 {
+        self.value(u, v, p).x
+    }
+}
@@ -0,0 +1,429 @@
+use std::ops::Mul;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::Point;
+use crate::interval::Interval;
+use crate::light::{Light, LightKind};
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::vec3::Vec3;
+
+/// A 4x4 matrix acting on column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Mat4 {
+    pub(crate) m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub(crate) const IDENTITY: Self = Self {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub(crate) fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub(crate) fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. Returns `None` for
+    /// singular matrices.
+    pub(crate) fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::IDENTITY.m;
+        for column in 0..4 {
+            let pivot =
+                (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inv.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inv[column][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[column][j];
+                        inv[row][j] -= factor * inv[column][j];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    fn point(&self, p: Point) -> Point {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Point::new(x, y, z)
+        } else {
+            Point::new(x, y, z) / w
+        }
+    }
+
+    fn vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+/// A rotation as a unit quaternion.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Quaternion {
+    pub(crate) w: f64,
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) z: f64,
+}
+
+impl Quaternion {
+    pub(crate) fn new(w: f64, x: f64, y: f64, z: f64) -> Self {
+        let length = (w * w + x * x + y * y + z * z).sqrt();
+        Self {
+            w: w / length,
+            x: x / length,
+            y: y / length,
+            z: z / length,
+        }
+    }
+
+    pub(crate) fn from_axis_angle(axis: Vec3, degrees: f64) -> Self {
+        let axis = axis.unit_vector();
+        let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+        Self::new(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    fn to_matrix(self) -> Mat4 {
+        let Self { w, x, y, z } = self;
+        Mat4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Self;
+
+    // Rotates by `other` first, then by `self`.
+    fn mul(self, other: Self) -> Self::Output {
+        Self::new(
+            self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        )
+    }
+}
+
+/// An affine transform together with its inverse. `a * b` applies `b` first.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Transform {
+    pub(crate) const IDENTITY: Self = Self {
+        matrix: Mat4::IDENTITY,
+        inverse: Mat4::IDENTITY,
+    };
+
+    pub(crate) fn from_matrix(matrix: Mat4) -> Option<Self> {
+        Some(Self {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub(crate) fn translate(offset: Vec3) -> Self {
+        let translation = |v: Vec3| {
+            Mat4::new([
+                [1.0, 0.0, 0.0, v.x],
+                [0.0, 1.0, 0.0, v.y],
+                [0.0, 0.0, 1.0, v.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ])
+        };
+        Self {
+            matrix: translation(offset),
+            inverse: translation(-offset),
+        }
+    }
+
+    /// Scales non-uniformly along the axes. All factors must be nonzero.
+    pub(crate) fn scale(factors: Vec3) -> Self {
+        let scaling = |v: Vec3| {
+            Mat4::new([
+                [v.x, 0.0, 0.0, 0.0],
+                [0.0, v.y, 0.0, 0.0],
+                [0.0, 0.0, v.z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ])
+        };
+        Self {
+            matrix: scaling(factors),
+            inverse: scaling(Vec3::new(1.0 / factors.x, 1.0 / factors.y, 1.0 / factors.z)),
+        }
+    }
+
+    pub(crate) fn rotate(axis: Vec3, degrees: f64) -> Self {
+        Self::from_quaternion(Quaternion::from_axis_angle(axis, degrees))
+    }
+
+    /// Rotates about the x axis, then the y axis, then the z axis, all fixed
+    /// in world space.
+    pub(crate) fn rotate_euler(x_degrees: f64, y_degrees: f64, z_degrees: f64) -> Self {
+        Self::rotate(Vec3::new(0.0, 0.0, 1.0), z_degrees)
+            * Self::rotate(Vec3::new(0.0, 1.0, 0.0), y_degrees)
+            * Self::rotate(Vec3::new(1.0, 0.0, 0.0), x_degrees)
+    }
+
+    pub(crate) fn from_quaternion(rotation: Quaternion) -> Self {
+        let matrix = rotation.to_matrix();
+        Self {
+            matrix,
+            // rotations are orthogonal
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub(crate) fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub(crate) fn point(&self, p: Point) -> Point {
+        self.matrix.point(p)
+    }
+
+    pub(crate) fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.vector(v)
+    }
+
+    // Normals transform by the inverse transpose to stay perpendicular to
+    // the transformed surface.
+    pub(crate) fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().vector(n)
+    }
+
+    pub(crate) fn ray(&self, ray: &Ray) -> Ray {
+        ray.continued(self.point(ray.origin), self.vector(ray.direction))
+    }
+
+    pub(crate) fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        let axes = [bbox.x, bbox.y, bbox.z];
+        if axes.iter().any(|axis| axis.min > axis.max) {
+            return Aabb::EMPTY;
+        }
+        if axes
+            .iter()
+            .any(|axis| axis.min.is_infinite() || axis.max.is_infinite())
+        {
+            return Aabb::UNIVERSE;
+        }
+
+        let mut result = Aabb::EMPTY;
+        for x in [bbox.x.min, bbox.x.max] {
+            for y in [bbox.y.min, bbox.y.max] {
+                for z in [bbox.z.min, bbox.z.max] {
+                    let p = self.point(Point::new(x, y, z));
+                    result = Aabb::enclosing(result, Aabb::from_points(p, p));
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Mul for Transform {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self::Output {
+        Self {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+/// Places a shared object in the world under a transform, without copying
+/// the object.
+pub(crate) struct Instance {
+    object: Arc<dyn Hittable + Send + Sync>,
+    transform: Transform,
+    bbox: Aabb,
+}
+
+impl Instance {
+    pub(crate) fn new(object: Arc<dyn Hittable + Send + Sync>, transform: Transform) -> Self {
+        let bbox = transform.bounding_box(&object.bounding_box());
+        Self {
+            object,
+            transform,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // The direction is not renormalized, so t is the same in both spaces.
+        let object_ray = self.transform.inverse().ray(ray);
+        let mut hit = self.object.hit(&object_ray, ray_t)?;
+
+        // A transformed normal keeps the sign of its dot product with the
+        // transformed ray, so `front_face` carries over as is.
+        hit.p = self.transform.point(hit.p);
+        hit.normal = self.transform.normal(hit.normal).unit_vector();
+        hit.geometric_normal = self.transform.normal(hit.geometric_normal).unit_vector();
+        hit.dpdu = self.transform.vector(hit.dpdu);
+        hit.dpdv = self.transform.vector(hit.dpdv);
+        Some(hit)
+    }
+
+    fn transmittance(&self, ray: &Ray, ray_t: Interval) -> Option<f64> {
+        self.object
+            .transmittance(&self.transform.inverse().ray(ray), ray_t)
+    }
+
+    fn triangle_lights(&self) -> Option<Vec<Light>> {
+        let lights = self.object.triangle_lights()?;
+        let place = |light: Light| match light.kind {
+            LightKind::Triangle { vertices } => Light {
+                kind: LightKind::Triangle {
+                    vertices: vertices.map(|v| self.transform.point(v)),
+                },
+                ..light
+            },
+            _ => light,
+        };
+        Some(lights.into_iter().map(place).collect())
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_identity(m: Mat4) {
+        for (i, row) in m.m.iter().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-12, "{m:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_round_trips() {
+        // a zero on the diagonal makes elimination pivot
+        let m = Mat4::new([
+            [0.0, 2.0, -1.0, 3.0],
+            [1.0, 0.5, 4.0, -2.0],
+            [3.0, -1.0, 0.0, 1.0],
+            [0.5, 0.0, 2.0, 1.0],
+        ]);
+        let inverse = m.inverse().unwrap();
+        assert_identity(m * inverse);
+        assert_identity(inverse * m);
+        assert_identity(inverse.inverse().unwrap() * inverse);
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        let m = Mat4::new([
+            [1.0, 2.0, 3.0, 4.0],
+            [2.0, 4.0, 6.0, 8.0],
+            [0.0, 1.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert!(m.inverse().is_none());
+        assert!(Transform::from_matrix(m).is_none());
+    }
+
+    #[test]
+    fn transforms_undo_and_keep_normals_perpendicular() {
+        let transform = Transform::translate(Vec3::new(1.0, -2.0, 3.0))
+            * Transform::rotate_euler(30.0, -45.0, 60.0)
+            * Transform::scale(Vec3::new(2.0, 0.5, 3.0));
+        assert_identity(transform.matrix * transform.inverse);
+
+        let p = Point::new(0.3, -1.2, 2.5);
+        assert!((transform.inverse().point(transform.point(p)) - p).length() < 1e-12);
+
+        // a tangent and the normal of the plane it lies in
+        let tangent = Vec3::new(1.0, 1.0, 0.0);
+        let normal = Vec3::new(1.0, -1.0, 2.0);
+        let dot = transform.vector(tangent).dot(transform.normal(normal));
+        assert!(dot.abs() < 1e-12);
+
+        let quaternion = Quaternion::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        let rotated = Transform::from_quaternion(quaternion).vector(Vec3::new(1.0, 0.0, 0.0));
+        assert!((rotated - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+    }
+}
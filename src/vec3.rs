@@ -1,4 +1,5 @@
 use std::{
+    f64::consts::PI,
     iter,
     ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub},
 };
@@ -84,6 +85,13 @@ impl Vec3 {
         }
     }
 
+    /// A direction about +z with density cos(theta) / pi.
+    pub(crate) fn cosine_direction(u: (f64, f64)) -> Self {
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        Self::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt())
+    }
+
     pub(crate) fn random_in_unit_disk() -> Self {
         loop {
             let p = Self {
@@ -96,19 +104,6 @@ impl Vec3 {
             }
         }
     }
-
-    pub(crate) fn reflect(&self, normal: Vec3) -> Vec3 {
-        *self - 2.0 * normal * self.dot(normal)
-    }
-
-    pub(crate) fn refract(&self, normal: Vec3, etai_over_etat: f64) -> Vec3 {
-        let cos_theta = (-*self).dot(normal);
-        let cos_theta = cos_theta.min(1.0);
-
-        let r_out_perp = etai_over_etat * (*self + cos_theta * normal);
-        let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * normal;
-        r_out_perp + r_out_parallel
-    }
 }
 
 impl Neg for Vec3 {
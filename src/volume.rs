@@ -0,0 +1,544 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::common::{invalid_data, random_f64, Point};
+use crate::interval::Interval;
+use crate::material::{Material, MaterialKind};
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::spectrum::blackbody;
+use crate::vec3::Vec3;
+
+const BINARY_MAGIC: &[u8; 4] = b"VOXG";
+const TEMPERATURE_CHANNEL: u32 = 1 << 1;
+const EMISSION_CHANNEL: u32 = 1 << 2;
+
+// entries in the blackbody color ramp
+const RAMP_SIZE: usize = 256;
+
+/// A dense grid of voxels with a density channel and optional temperature
+/// (in kelvin) and emission channels, sampled at the voxel centers.
+pub(crate) struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    density: Vec<f32>,
+    temperature: Option<Vec<f32>>,
+    emission: Option<Vec<f32>>,
+}
+
+impl VoxelGrid {
+    pub(crate) fn new(nx: usize, ny: usize, nz: usize, density: Vec<f32>) -> Self {
+        assert_eq!(density.len(), nx * ny * nz);
+        Self {
+            nx,
+            ny,
+            nz,
+            density,
+            temperature: None,
+            emission: None,
+        }
+    }
+
+    /// Loads a grid in either format, telling them apart by the binary magic:
+    ///
+    /// - binary: `VOXG`, then little endian `u32`s nx, ny, nz and a channel
+    ///   mask (bit 1 temperature, bit 2 emission), then `f32` voxels;
+    /// - text: a header line `nx ny nz [temperature] [emission]` naming the
+    ///   optional channels, then the voxel values separated by whitespace,
+    ///   with `#` starting a comment.
+    ///
+    /// In both, each voxel lists its density followed by its optional
+    /// channels, with x varying fastest and z slowest.
+    pub(crate) fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(BINARY_MAGIC) {
+            Self::parse_binary(&bytes[BINARY_MAGIC.len()..])
+        } else {
+            let text = String::from_utf8(bytes)
+                .map_err(|_| invalid_data("voxel grid is neither binary nor text"))?;
+            Self::parse_text(&text)
+        }
+    }
+
+    fn parse_binary(bytes: &[u8]) -> io::Result<Self> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(invalid_data(&format!(
+                "voxel grid has {} stray bytes at the end",
+                bytes.len() % 4
+            )));
+        }
+        let mut words = bytes
+            .chunks_exact(4)
+            .map(|chunk| [chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let mut header = || {
+            words
+                .next()
+                .map(u32::from_le_bytes)
+                .ok_or_else(|| invalid_data("truncated voxel grid header"))
+        };
+        let (nx, ny, nz) = (header()? as usize, header()? as usize, header()? as usize);
+        let channels = header()?;
+        if channels & !(TEMPERATURE_CHANNEL | EMISSION_CHANNEL) != 0 {
+            return Err(invalid_data(&format!(
+                "unknown voxel channels in mask {channels:#x}"
+            )));
+        }
+
+        let values: Vec<f32> = words.map(f32::from_le_bytes).collect();
+        Self::from_interleaved(nx, ny, nz, channels, values)
+    }
+
+    fn parse_text(text: &str) -> io::Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .filter(|line| !line.trim().is_empty());
+
+        let header = lines
+            .next()
+            .ok_or_else(|| invalid_data("empty voxel grid"))?;
+        let mut fields = header.split_whitespace();
+        let mut dimension = || {
+            fields
+                .next()
+                .and_then(|field| field.parse::<usize>().ok())
+                .ok_or_else(|| invalid_data("voxel grid header must start with nx ny nz"))
+        };
+        let (nx, ny, nz) = (dimension()?, dimension()?, dimension()?);
+        let mut channels = 0;
+        for name in fields {
+            channels |= match name {
+                "temperature" => TEMPERATURE_CHANNEL,
+                "emission" => EMISSION_CHANNEL,
+                _ => return Err(invalid_data(&format!("unknown voxel channel: {name}"))),
+            };
+        }
+
+        let values = lines
+            .flat_map(str::split_whitespace)
+            .map(|value| {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| invalid_data(&format!("invalid voxel value: {value}")))
+            })
+            .collect::<io::Result<Vec<f32>>>()?;
+        Self::from_interleaved(nx, ny, nz, channels, values)
+    }
+
+    fn from_interleaved(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        channels: u32,
+        values: Vec<f32>,
+    ) -> io::Result<Self> {
+        let has_temperature = channels & TEMPERATURE_CHANNEL != 0;
+        let has_emission = channels & EMISSION_CHANNEL != 0;
+        let stride = 1 + has_temperature as usize + has_emission as usize;
+
+        let count = nx
+            .checked_mul(ny)
+            .and_then(|count| count.checked_mul(nz))
+            .and_then(|count| count.checked_mul(stride))
+            .ok_or_else(|| invalid_data("voxel grid is too large"))?;
+        if count == 0 || values.len() != count {
+            return Err(invalid_data(&format!(
+                "expected {count} voxel values, found {}",
+                values.len()
+            )));
+        }
+
+        let channel = |offset: usize| {
+            values
+                .iter()
+                .skip(offset)
+                .step_by(stride)
+                .copied()
+                .collect()
+        };
+        let temperature = has_temperature.then(|| channel(1));
+        let emission = has_emission.then(|| channel(stride - 1));
+        Ok(Self {
+            nx,
+            ny,
+            nz,
+            density: channel(0),
+            temperature,
+            emission,
+        })
+    }
+
+    pub(crate) fn max_density(&self) -> f64 {
+        self.density.iter().fold(0.0_f32, |a, &b| a.max(b)) as f64
+    }
+
+    // Trilinear interpolation of a channel at grid coordinates in [0, 1]^3.
+    fn lookup(&self, values: &[f32], p: Vec3) -> f64 {
+        let axis = |t: f64, n: usize| {
+            let x = (t * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let i = (x.floor() as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), x - i as f64)
+        };
+        let (x0, x1, fx) = axis(p.x, self.nx);
+        let (y0, y1, fy) = axis(p.y, self.ny);
+        let (z0, z1, fz) = axis(p.z, self.nz);
+
+        let at = |x: usize, y: usize, z: usize| values[x + self.nx * (y + self.ny * z)] as f64;
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        lerp(
+            lerp(
+                lerp(at(x0, y0, z0), at(x1, y0, z0), fx),
+                lerp(at(x0, y1, z0), at(x1, y1, z0), fx),
+                fy,
+            ),
+            lerp(
+                lerp(at(x0, y0, z1), at(x1, y0, z1), fx),
+                lerp(at(x0, y1, z1), at(x1, y1, z1), fx),
+                fy,
+            ),
+            fz,
+        )
+    }
+
+    pub(crate) fn density(&self, p: Vec3) -> f64 {
+        self.lookup(&self.density, p)
+    }
+
+    pub(crate) fn temperature(&self, p: Vec3) -> Option<f64> {
+        self.temperature.as_ref().map(|t| self.lookup(t, p))
+    }
+
+    pub(crate) fn emission(&self, p: Vec3) -> Option<f64> {
+        self.emission.as_ref().map(|e| self.lookup(e, p))
+    }
+
+    fn max_temperature(&self) -> f64 {
+        self.temperature
+            .as_ref()
+            .map_or(0.0, |t| t.iter().fold(0.0_f32, |a, &b| a.max(b)) as f64)
+    }
+}
+
+/// A heterogeneous medium whose density comes from a voxel grid stretched
+/// over an axis-aligned box.
+///
+/// Free flights are sampled with delta tracking against the grid's maximum
+/// density, which keeps them unbiased, and shadow rays through it estimate
+/// its transmittance by ratio tracking. Temperature is turned into emitted
+/// color through a blackbody ramp and scaled by the emission channel.
+pub(crate) struct GridMedium {
+    grid: Arc<VoxelGrid>,
+    bbox: Aabb,
+    min: Point,
+    extent: Vec3,
+    density_scale: f64,
+    majorant: f64,
+    albedo: Vec3,
+    // the probability that a collision scatters rather than absorbs
+    scatter_probability: f64,
+    phase_function: Arc<Material>,
+    // the material of absorption events, which gives off the medium's
+    // emission in place of the light it absorbed
+    absorber: Arc<Material>,
+}
+
+impl GridMedium {
+    pub(crate) fn new(
+        grid: Arc<VoxelGrid>,
+        a: Point,
+        b: Point,
+        density_scale: f64,
+        albedo: Vec3,
+        g: f64,
+    ) -> Self {
+        let bbox = Aabb::from_points(a, b);
+        let min = Point::new(bbox.x.min, bbox.y.min, bbox.z.min);
+        let extent = Vec3::new(bbox.x.size(), bbox.y.size(), bbox.z.size());
+        let majorant = grid.max_density() * density_scale;
+
+        let scatter_probability = ((albedo.x + albedo.y + albedo.z) / 3.0).clamp(0.0, 1.0);
+        let kind = if g == 0.0 {
+            MaterialKind::Isotropic
+        } else {
+            MaterialKind::HenyeyGreenstein { g }
+        };
+        let phase_function = Arc::new(Material {
+            kind,
+            albedo: albedo / scatter_probability.max(1e-8),
+        });
+        let absorber = Arc::new(Material {
+            kind: MaterialKind::DiffuseLight,
+            albedo: Vec3::new(0.0, 0.0, 0.0),
+        });
+
+        Self {
+            grid,
+            bbox,
+            min,
+            extent,
+            density_scale,
+            majorant,
+            albedo,
+            scatter_probability,
+            phase_function,
+            absorber,
+        }
+    }
+
+    /// Makes the medium glow. The emitted radiance at a point is
+    /// `emission_scale` times the emission channel (or one, without it) times
+    /// the blackbody color of the temperature channel times
+    /// `temperature_scale` (or white, without it).
+    pub(crate) fn with_emission(mut self, emission_scale: f64, temperature_scale: f64) -> Self {
+        let (ramp, ramp_max) = if self.grid.temperature.is_some() {
+            let ramp_max = self.grid.max_temperature() * temperature_scale;
+            let ramp = (0..RAMP_SIZE)
+                .map(|i| blackbody(ramp_max * i as f64 / (RAMP_SIZE - 1) as f64))
+                .collect();
+            (ramp, ramp_max)
+        } else {
+            (Vec::new(), 0.0)
+        };
+        let emission = GridEmission {
+            grid: self.grid.clone(),
+            min: self.min,
+            extent: self.extent,
+            emission_scale,
+            temperature_scale,
+            ramp,
+            ramp_max,
+        };
+        // absorption events stand for the absorbed share of collisions
+        let absorbed = Vec3::new(1.0, 1.0, 1.0) - self.albedo;
+        self.absorber = Arc::new(Material {
+            kind: MaterialKind::MediumEmission(Arc::new(emission)),
+            albedo: absorbed / (1.0 - self.scatter_probability).max(1e-8),
+        });
+        self
+    }
+}
+
+/// The light a glowing grid medium gives off, as the material of its
+/// absorption events.
+pub(crate) struct GridEmission {
+    grid: Arc<VoxelGrid>,
+    min: Point,
+    extent: Vec3,
+    emission_scale: f64,
+    temperature_scale: f64,
+    ramp: Vec<Vec3>,
+    ramp_max: f64,
+}
+
+impl GridEmission {
+    /// The radiance given off at `p`, in world space.
+    pub(crate) fn radiance(&self, p: Point) -> Vec3 {
+        let uvw = grid_coordinates(p, self.min, self.extent);
+        let strength = self.grid.emission(uvw).unwrap_or(1.0);
+        let color = match self.grid.temperature(uvw) {
+            Some(temperature) if self.ramp_max > 0.0 => {
+                let x = (temperature * self.temperature_scale / self.ramp_max).clamp(0.0, 1.0)
+                    * (RAMP_SIZE - 1) as f64;
+                let i = (x as usize).min(RAMP_SIZE - 2);
+                let f = x - i as f64;
+                self.ramp[i] * (1.0 - f) + self.ramp[i + 1] * f
+            }
+            Some(_) => Vec3::new(0.0, 0.0, 0.0),
+            None => Vec3::new(1.0, 1.0, 1.0),
+        };
+        color * (self.emission_scale * strength)
+    }
+}
+
+// Where `p` is in a box from `min` of size `extent`, in [0, 1]^3 inside it.
+fn grid_coordinates(p: Point, min: Point, extent: Vec3) -> Vec3 {
+    let d = p - min;
+    Vec3::new(d.x / extent.x, d.y / extent.y, d.z / extent.z)
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let ray_t = self.bbox.clip(ray, ray_t)?;
+        if self.majorant <= 0.0 {
+            return None;
+        }
+
+        // Delta tracking: take steps as if the whole box had the maximum
+        // density, and accept each tentative collision with probability
+        // density / maximum. Rejected ones are null collisions.
+        let ray_length = ray.direction.length();
+        let mut t = ray_t.min;
+        loop {
+            t -= (1.0 - random_f64()).ln() / (self.majorant * ray_length);
+            if t >= ray_t.max {
+                return None;
+            }
+            let p = ray.at(t);
+            let uvw = grid_coordinates(p, self.min, self.extent);
+            let density = self.grid.density(uvw) * self.density_scale;
+            if random_f64() * self.majorant >= density {
+                continue;
+            }
+
+            // A real collision either scatters or absorbs, and absorbed
+            // light is replaced by the medium's own emission.
+            let material = if random_f64() < self.scatter_probability {
+                self.phase_function.clone()
+            } else {
+                self.absorber.clone()
+            };
+            let mut hit = HitRecord::new(ray, p, Vec3::new(1.0, 0.0, 0.0), t, (0.0, 0.0), material);
+            hit.front_face = true;
+            return Some(hit);
+        }
+    }
+
+    /// The fraction of light that makes it through the medium within
+    /// `ray_t`, estimated without bias by ratio tracking.
+    fn transmittance(&self, ray: &Ray, ray_t: Interval) -> Option<f64> {
+        let Some(ray_t) = self.bbox.clip(ray, ray_t) else {
+            return Some(1.0);
+        };
+        if self.majorant <= 0.0 {
+            return Some(1.0);
+        }
+
+        let ray_length = ray.direction.length();
+        let mut transmittance = 1.0;
+        let mut t = ray_t.min;
+        loop {
+            t -= (1.0 - random_f64()).ln() / (self.majorant * ray_length);
+            if t >= ray_t.max {
+                return Some(transmittance);
+            }
+            let uvw = grid_coordinates(ray.at(t), self.min, self.extent);
+            let density = self.grid.density(uvw) * self.density_scale;
+            transmittance *= 1.0 - density / self.majorant;
+            if transmittance <= 0.0 {
+                return Some(0.0);
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::temp_file;
+
+    fn load(name: &str, contents: &[u8]) -> io::Result<VoxelGrid> {
+        let path = temp_file(name, contents);
+        let grid = VoxelGrid::load(&path);
+        fs::remove_file(path).unwrap();
+        grid
+    }
+
+    fn binary(dimensions: [u32; 3], channels: u32, values: &[f32]) -> Vec<u8> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        for word in dimensions.into_iter().chain([channels]) {
+            bytes.extend(word.to_le_bytes());
+        }
+        for value in values {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    // voxel (x, y, z) of a 2x1x2 grid has density x + 2z, temperature
+    // 1000 (1 + x) and emission z
+    const VALUES: [f32; 12] = [
+        0.0, 1000.0, 0.0, 1.0, 2000.0, 0.0, 2.0, 1000.0, 1.0, 3.0, 2000.0, 1.0,
+    ];
+
+    fn assert_grid(grid: &VoxelGrid) {
+        // voxel centers sit at (i + 0.5) / n
+        let center = |x: f64, z: f64| Vec3::new((x + 0.5) / 2.0, 0.5, (z + 0.5) / 2.0);
+        assert_eq!(grid.density(center(1.0, 1.0)), 3.0);
+        assert_eq!(grid.temperature(center(1.0, 0.0)), Some(2000.0));
+        assert_eq!(grid.emission(center(0.0, 1.0)), Some(1.0));
+        assert!((grid.density(Vec3::new(0.5, 0.5, 0.5)) - 1.5).abs() < 1e-12);
+        assert_eq!(grid.max_density(), 3.0);
+    }
+
+    #[test]
+    fn text_and_binary_grids_agree() {
+        let text = "# a tiny grid\n2 1 2 temperature emission\n\
+                    0 1000 0  1 2000 0\n2 1000 1  3 2000 1 # last row\n";
+        assert_grid(&load("grid.txt", text.as_bytes()).unwrap());
+        let bytes = binary([2, 1, 2], TEMPERATURE_CHANNEL | EMISSION_CHANNEL, &VALUES);
+        assert_grid(&load("grid.voxg", &bytes).unwrap());
+
+        let grid = load("density.txt", b"1 1 2\n0.5 0.25\n").unwrap();
+        assert_eq!(grid.temperature(Vec3::new(0.5, 0.5, 0.5)), None);
+    }
+
+    #[test]
+    fn invalid_grids_are_errors() {
+        let cases: [(&str, Vec<u8>); 9] = [
+            ("empty.txt", b"# nothing\n".to_vec()),
+            ("header.txt", b"2 2\n".to_vec()),
+            ("channel.txt", b"1 1 1 velocity\n0 0\n".to_vec()),
+            ("value.txt", b"1 1 1\nthick\n".to_vec()),
+            ("count.txt", b"2 1 1\n0.5\n".to_vec()),
+            (
+                "truncated.voxg",
+                BINARY_MAGIC.iter().chain(&[2, 0, 0]).copied().collect(),
+            ),
+            // more voxels than can be counted
+            ("huge.voxg", binary([u32::MAX; 3], 0, &[0.0])),
+            // a whole voxel and part of another
+            (
+                "stray.voxg",
+                binary([1, 1, 1], 0, &[0.5])
+                    .into_iter()
+                    .chain([0, 0])
+                    .collect(),
+            ),
+            // bit 3 names no channel
+            ("mask.voxg", binary([1, 1, 1], 1 << 3, &[0.5])),
+        ];
+        for (name, contents) in cases {
+            let error = load(name, &contents).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{name}");
+        }
+    }
+
+    #[test]
+    fn ratio_tracking_matches_beer_lambert() {
+        // a uniform grid, so the majorant is loose only by the density scale
+        let grid = Arc::new(VoxelGrid::new(2, 2, 2, vec![0.5; 8]));
+        let medium = GridMedium::new(
+            grid,
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(2.0, 1.0, 1.0),
+            2.0,
+            Vec3::new(0.5, 0.5, 0.5),
+            0.0,
+        );
+        let ray = Ray::new(Point::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        let n = 20000;
+        let mean = (0..n)
+            .map(|_| {
+                medium
+                    .transmittance(&ray, Interval::new(0.0, 10.0))
+                    .unwrap()
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert!((mean - (-2.0_f64).exp()).abs() < 0.01, "{mean}");
+
+        let outside = Ray::new(Point::new(-1.0, 3.0, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(
+            medium.transmittance(&outside, Interval::new(0.0, 10.0)),
+            Some(1.0)
+        );
+    }
+}